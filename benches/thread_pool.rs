@@ -0,0 +1,124 @@
+//! Compares the throughput of the work-stealing pool with the previous design,
+//! where every worker waits on a shared `Mutex<Receiver>`
+//!
+//! Run with `cargo bench --bench thread_pool`.
+//! The crate has no library target, so the pool is compiled into the benchmark from its sources.
+//!
+//! Results on a single CPU, where the workers and the producer share one core,
+//! so the work-stealing pool pays for its coordination without gaining any parallelism:
+//!
+//! ```text
+//! pool size  1: mutex receiver    9592391 jobs/s, work stealing    6224171 jobs/s
+//! pool size  2: mutex receiver    9828476 jobs/s, work stealing    6333800 jobs/s
+//! pool size  4: mutex receiver    7105443 jobs/s, work stealing    3268374 jobs/s
+//! pool size  8: mutex receiver    9627618 jobs/s, work stealing    2661535 jobs/s
+//! pool size 16: mutex receiver    9573987 jobs/s, work stealing    5966678 jobs/s
+//! ```
+//!
+//! Nearly all of the time is spent submitting the jobs. Producers used to wake a sleeping
+//! worker for every job queued before it ran, up to 200000 wake-ups for about 20000 sleeps,
+//! so each sleeping worker is now woken once. These numbers say nothing about the contention
+//! the redesign targets, the comparison still has to be run on a machine with several cores.
+
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    thread,
+    time::Instant,
+};
+
+// Parts of the pool only the server and the unit tests use
+#[allow(dead_code, unused_imports)]
+#[path = "../src/concurrent/mod.rs"]
+mod concurrent;
+
+use concurrent::thread_pool::ThreadPool;
+
+const JOBS: usize = 200_000;
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// The previous pool design, every worker waits on a shared `Mutex<Receiver>`
+struct MutexReceiverPool {
+    workers: Vec<thread::JoinHandle<()>>,
+    sender: Option<mpsc::Sender<Job>>,
+}
+
+impl MutexReceiverPool {
+    fn new(size: usize) -> MutexReceiverPool {
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let workers = (0..size)
+            .map(|_| {
+                let receiver = Arc::clone(&receiver);
+                thread::spawn(move || {
+                    while let Ok(job) = receiver.lock().unwrap().recv() {
+                        job();
+                    }
+                })
+            })
+            .collect();
+
+        MutexReceiverPool {
+            workers,
+            sender: Some(sender),
+        }
+    }
+
+    fn execute(&self, job: impl FnOnce() + Send + 'static) {
+        self.sender.as_ref().unwrap().send(Box::new(job)).unwrap();
+    }
+
+    fn join(mut self) {
+        drop(self.sender.take());
+        for worker in self.workers.drain(..) {
+            worker.join().unwrap();
+        }
+    }
+}
+
+fn job(counter: &Arc<AtomicUsize>) -> impl FnOnce() + Send + 'static {
+    let counter = Arc::clone(counter);
+    move || {
+        let sum = (0..64u64).fold(0u64, |sum, i| sum.wrapping_add(i * i));
+        counter.fetch_add(std::hint::black_box(sum) as usize & 1, Ordering::Relaxed);
+    }
+}
+
+fn main() {
+    println!(
+        "{} jobs on {} CPUs",
+        JOBS,
+        thread::available_parallelism().map_or(1, |n| n.get())
+    );
+
+    for size in [1, 2, 4, 8, 16] {
+        let counter = Arc::new(AtomicUsize::new(0));
+
+        let started = Instant::now();
+        let pool = MutexReceiverPool::new(size);
+        for _ in 0..JOBS {
+            pool.execute(job(&counter));
+        }
+        pool.join();
+        let mutex_receiver = JOBS as f64 / started.elapsed().as_secs_f64();
+
+        let started = Instant::now();
+        let pool = ThreadPool::builder()
+            .size(size)
+            .queue_capacity(JOBS)
+            .build();
+        for _ in 0..JOBS {
+            pool.execute(job(&counter)).unwrap();
+        }
+        drop(pool);
+        let work_stealing = JOBS as f64 / started.elapsed().as_secs_f64();
+
+        println!(
+            "pool size {:>2}: mutex receiver {:>10.0} jobs/s, work stealing {:>10.0} jobs/s",
+            size, mutex_receiver, work_stealing
+        );
+    }
+}
@@ -0,0 +1,77 @@
+use std::{io, mem};
+
+/// Pins the current thread to the CPU
+///
+/// Fails with `InvalidInput` if the CPU does not fit into a `cpu_set_t`
+pub fn pin_current_thread(cpu: usize) -> io::Result<()> {
+    if cpu >= libc::CPU_SETSIZE as usize {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("CPU {} is out of range [0 - {})", cpu, libc::CPU_SETSIZE),
+        ));
+    }
+
+    // SAFETY: `cpu_set_t` is a plain bit mask, zeroed it is an empty set
+    let mut set: libc::cpu_set_t = unsafe { mem::zeroed() };
+    // SAFETY: the CPU fits into the set, `CPU_SET` does not check it
+    unsafe { libc::CPU_SET(cpu, &mut set) };
+
+    // SAFETY: the set is initialized and its size is passed along, pid 0 is the calling thread
+    let result = unsafe { libc::sched_setaffinity(0, mem::size_of::<libc::cpu_set_t>(), &set) };
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// The CPUs the current thread is allowed to run on
+pub fn allowed_cpus() -> io::Result<Vec<usize>> {
+    // SAFETY: `cpu_set_t` is a plain bit mask, zeroed it is an empty set
+    let mut set: libc::cpu_set_t = unsafe { mem::zeroed() };
+
+    // SAFETY: the set is writable and its size is passed along, pid 0 is the calling thread
+    let result = unsafe { libc::sched_getaffinity(0, mem::size_of::<libc::cpu_set_t>(), &mut set) };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let cpus = (0..libc::CPU_SETSIZE as usize)
+        // SAFETY: every CPU below `CPU_SETSIZE` fits into the set
+        .filter(|&cpu| unsafe { libc::CPU_ISSET(cpu, &set) })
+        .collect();
+
+    Ok(cpus)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn pin_current_thread_must_restrict_thread_to_cpu() {
+        let cpu = allowed_cpus().unwrap()[0];
+
+        let pinned = thread::spawn(move || {
+            pin_current_thread(cpu).unwrap();
+            allowed_cpus().unwrap()
+        })
+        .join()
+        .unwrap();
+
+        assert_eq!(vec![cpu], pinned, "Thread must only run on the pinned CPU");
+    }
+
+    #[test]
+    fn pin_current_thread_must_reject_cpu_out_of_set() {
+        let result = pin_current_thread(libc::CPU_SETSIZE as usize);
+
+        assert_eq!(
+            io::ErrorKind::InvalidInput,
+            result.unwrap_err().kind(),
+            "CPU that does not fit into the set must be rejected"
+        );
+    }
+}
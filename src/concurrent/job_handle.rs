@@ -0,0 +1,148 @@
+use std::{
+    any::Any,
+    fmt::Display,
+    future::Future,
+    panic::{self, AssertUnwindSafe},
+    pin::Pin,
+    sync::{Arc, Condvar, Mutex},
+    task::{Context, Poll, Waker},
+};
+
+use crate::concurrent::thread_pool::PoolShared;
+
+#[derive(Debug)]
+pub enum JobError {
+    /// The job panicked, holds the panic payload
+    Panicked(Box<dyn Any + Send + 'static>),
+    /// The job has been dropped by the overload policy or a shut down pool without running
+    Cancelled,
+}
+
+/// An owned permission to wait for the result of a job spawned on a pool
+///
+/// The result can be waited for with `join` or by awaiting the handle
+pub struct JobHandle<T> {
+    slot: Arc<JobSlot<T>>,
+    pool: Arc<PoolShared>,
+}
+
+struct JobSlot<T> {
+    state: Mutex<JobState<T>>,
+    finished: Condvar,
+}
+
+enum JobState<T> {
+    Pending(Option<Waker>),
+    Finished(Result<T, JobError>),
+    Taken,
+}
+
+/// Stores the result of a job, or `JobError::Cancelled` if the job is dropped before running
+struct Completion<T> {
+    slot: Option<Arc<JobSlot<T>>>,
+}
+
+impl<T> JobHandle<T> {
+    /// Wraps the task into a job that stores its result for the returned handle
+    pub(crate) fn new<F>(task: F, pool: Arc<PoolShared>) -> (impl FnOnce() + Send, JobHandle<T>)
+    where
+        F: FnOnce() -> T + Send,
+        T: Send,
+    {
+        let slot = Arc::new(JobSlot {
+            state: Mutex::new(JobState::Pending(None)),
+            finished: Condvar::new(),
+        });
+        let completion = Completion {
+            slot: Some(Arc::clone(&slot)),
+        };
+
+        let job = move || {
+            let result = panic::catch_unwind(AssertUnwindSafe(task));
+            completion.complete(result.map_err(JobError::Panicked));
+        };
+
+        (job, JobHandle { slot, pool })
+    }
+
+    /// Waits for the job to finish and returns its result
+    ///
+    /// When called from a worker of the same pool, queued jobs are run while waiting,
+    /// so jobs can fan out subtasks without exhausting the pool
+    pub fn join(self) -> Result<T, JobError> {
+        let mut state = self
+            .pool
+            .wait_while(&self.slot.state, &self.slot.finished, |state| {
+                matches!(state, JobState::Pending(_))
+            });
+
+        match std::mem::replace(&mut *state, JobState::Taken) {
+            JobState::Finished(result) => result,
+            JobState::Pending(_) | JobState::Taken => unreachable!("Job result is taken once"),
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        !matches!(*self.slot.state.lock().unwrap(), JobState::Pending(_))
+    }
+}
+
+impl<T> Future for JobHandle<T> {
+    type Output = Result<T, JobError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.slot.state.lock().unwrap();
+
+        match std::mem::replace(&mut *state, JobState::Taken) {
+            JobState::Finished(result) => Poll::Ready(result),
+            JobState::Pending(_) => {
+                *state = JobState::Pending(Some(cx.waker().clone()));
+                Poll::Pending
+            }
+            JobState::Taken => panic!("Job handle polled after completion"),
+        }
+    }
+}
+
+impl<T> Completion<T> {
+    fn complete(mut self, result: Result<T, JobError>) {
+        if let Some(slot) = self.slot.take() {
+            slot.finish(result);
+        }
+    }
+}
+
+impl<T> Drop for Completion<T> {
+    fn drop(&mut self) {
+        if let Some(slot) = self.slot.take() {
+            slot.finish(Err(JobError::Cancelled));
+        }
+    }
+}
+
+impl<T> JobSlot<T> {
+    fn finish(&self, result: Result<T, JobError>) {
+        let mut state = self.state.lock().unwrap();
+        let waker = match std::mem::replace(&mut *state, JobState::Finished(result)) {
+            JobState::Pending(waker) => waker,
+            _ => None,
+        };
+        drop(state);
+
+        self.finished.notify_all();
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+impl Display for JobError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JobError::Panicked(_) => write!(f, "Job panicked"),
+            JobError::Cancelled => write!(f, "Job has been cancelled before running"),
+        }
+    }
+}
+
+impl std::error::Error for JobError {}
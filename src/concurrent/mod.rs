@@ -1,2 +1,7 @@
+#[cfg(target_os = "linux")]
+pub mod affinity;
+pub mod job_handle;
+pub mod scheduler;
+pub mod scope;
 pub mod thread_pool;
 mod worker;
@@ -0,0 +1,529 @@
+use std::{
+    cell::RefCell,
+    fmt::Display,
+    iter,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Condvar, Mutex, RwLock,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use clap::ValueEnum;
+use crossbeam_deque::{Injector, Steal, Stealer, Worker as Deque};
+use log::warn;
+
+use crate::concurrent::worker::Job;
+
+/// What happens to a new job when the queue is full
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Default)]
+pub enum OverloadPolicy {
+    /// Wait until a worker takes a job off the queue
+    #[default]
+    Block,
+    /// Refuse the new job
+    Reject,
+    /// Discard the job that has been waiting the longest to make room for the new one
+    DropOldest,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExecuteError {
+    /// The queue is full and the overload policy is `Reject`
+    QueueFull,
+    /// The pool has been shut down
+    ShutDown,
+}
+
+/// How many times a worker looks for a job again right away while jobs are queued
+/// but none can be taken, e.g. because it is still being pushed
+const MAX_POP_RETRIES: usize = 16;
+
+/// How long a worker waits before looking for a queued job again once it has run out of retries
+const POP_BACKOFF: Duration = Duration::from_millis(1);
+
+pub(crate) enum Popped {
+    Job(Job),
+    /// No job has arrived in time
+    TimedOut,
+    /// The scheduler is closed and all the remaining jobs are taken
+    Closed,
+}
+
+/// Distributes jobs between the workers of a pool, holding at most `capacity` jobs
+///
+/// Jobs submitted from outside of the pool go to a shared injector queue,
+/// jobs submitted by a worker go to its own local deque.
+/// A worker takes jobs from its local deque first, then moves a batch of jobs
+/// from the injector and finally steals from the other workers,
+/// so workers rarely contend for the same lock
+pub(crate) struct Scheduler {
+    id: usize,
+    capacity: usize,
+    overload_policy: OverloadPolicy,
+    injector: Injector<Job>,
+    stealers: RwLock<Vec<(usize, Stealer<Job>)>>,
+    /// Jobs that are queued or reserved to be queued
+    queued: AtomicUsize,
+    closed: AtomicBool,
+    /// Workers waiting for a job that have not been woken up yet,
+    /// producers only take the lock to wake one up if there are any
+    sleeping_workers: AtomicUsize,
+    /// Wake-ups sent to sleeping workers that have not been taken by one yet
+    sleepers: Mutex<usize>,
+    job_available: Condvar,
+    blocked_producers: AtomicUsize,
+    waiting_producers: Mutex<()>,
+    room_available: Condvar,
+}
+
+struct LocalQueue {
+    scheduler_id: usize,
+    deque: Deque<Job>,
+}
+
+thread_local! {
+    /// The local deque of the worker running on the current thread
+    static LOCAL_QUEUE: RefCell<Option<LocalQueue>> = const { RefCell::new(None) };
+}
+
+static NEXT_SCHEDULER_ID: AtomicUsize = AtomicUsize::new(0);
+
+impl Scheduler {
+    pub(crate) fn new(capacity: usize, overload_policy: OverloadPolicy) -> Scheduler {
+        assert!(capacity > 0);
+
+        Scheduler {
+            id: NEXT_SCHEDULER_ID.fetch_add(1, Ordering::Relaxed),
+            capacity,
+            overload_policy,
+            injector: Injector::new(),
+            stealers: RwLock::new(Vec::new()),
+            queued: AtomicUsize::new(0),
+            closed: AtomicBool::new(false),
+            sleeping_workers: AtomicUsize::new(0),
+            sleepers: Mutex::new(0),
+            job_available: Condvar::new(),
+            blocked_producers: AtomicUsize::new(0),
+            waiting_producers: Mutex::new(()),
+            room_available: Condvar::new(),
+        }
+    }
+
+    pub(crate) fn overload_policy(&self) -> OverloadPolicy {
+        self.overload_policy
+    }
+
+    /// Gives the current thread a local deque, must be called by a worker before `pop`
+    pub(crate) fn register_worker(&self, worker_id: usize) {
+        let deque = Deque::new_lifo();
+        self.stealers
+            .write()
+            .unwrap()
+            .push((worker_id, deque.stealer()));
+
+        LOCAL_QUEUE.set(Some(LocalQueue {
+            scheduler_id: self.id,
+            deque,
+        }));
+    }
+
+    /// Removes the local deque of the current thread, its remaining jobs go to the injector
+    pub(crate) fn unregister_worker(&self, worker_id: usize) {
+        self.stealers
+            .write()
+            .unwrap()
+            .retain(|(id, _)| *id != worker_id);
+
+        if let Some(local) = LOCAL_QUEUE.take() {
+            while let Some(job) = local.deque.pop() {
+                self.injector.push(job);
+            }
+        }
+    }
+
+    /// Adds the job to the queue, applying the overload policy if it is full
+    ///
+    /// A rejected or discarded job is dropped without being run
+    pub(crate) fn push(&self, job: Job) -> Result<(), ExecuteError> {
+        self.push_with(job, self.overload_policy)
+    }
+
+    /// Adds the job to the queue like `push`, but refuses it instead of waiting for room
+    pub(crate) fn try_push(&self, job: Job) -> Result<(), ExecuteError> {
+        let overload_policy = match self.overload_policy {
+            OverloadPolicy::Block => OverloadPolicy::Reject,
+            overload_policy => overload_policy,
+        };
+
+        self.push_with(job, overload_policy)
+    }
+
+    fn push_with(&self, job: Job, overload_policy: OverloadPolicy) -> Result<(), ExecuteError> {
+        if self.closed.load(Ordering::SeqCst) {
+            return Err(ExecuteError::ShutDown);
+        }
+
+        loop {
+            let reserved = self
+                .queued
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |queued| {
+                    (queued < self.capacity).then(|| queued + 1)
+                });
+            if reserved.is_ok() {
+                break;
+            }
+
+            match overload_policy {
+                // A worker waiting for room would stop draining the queue, e.g. when every
+                // worker spawns a subtask, so it runs the queued jobs itself until there is room
+                OverloadPolicy::Block if self.is_worker_thread() => match self.try_pop() {
+                    Some(queued) => {
+                        let _ = panic::catch_unwind(AssertUnwindSafe(queued));
+                    }
+                    None => thread::sleep(POP_BACKOFF),
+                },
+                OverloadPolicy::Block => {
+                    let guard = self.waiting_producers.lock().unwrap();
+                    self.blocked_producers.fetch_add(1, Ordering::SeqCst);
+                    drop(
+                        self.room_available
+                            .wait_while(guard, |_| {
+                                self.queued.load(Ordering::SeqCst) >= self.capacity
+                            })
+                            .unwrap(),
+                    );
+                    self.blocked_producers.fetch_sub(1, Ordering::SeqCst);
+                }
+                OverloadPolicy::Reject => {
+                    drop(job);
+                    return Err(ExecuteError::QueueFull);
+                }
+                OverloadPolicy::DropOldest => {
+                    // The slot of the oldest job is taken over by the new one
+                    if let Some(oldest) = self.steal_oldest() {
+                        self.enqueue(job);
+                        warn!("Job queue is full, dropping the oldest job");
+                        drop(oldest);
+                        return Ok(());
+                    }
+                }
+            }
+        }
+
+        self.enqueue(job);
+
+        Ok(())
+    }
+
+    /// Takes the next job for the worker running on the current thread,
+    /// waiting up to `timeout` for one to arrive
+    pub(crate) fn pop(&self, timeout: Duration) -> Popped {
+        let deadline = Instant::now() + timeout;
+        let mut retries = 0;
+
+        loop {
+            if let Some(job) = self.try_pop() {
+                return Popped::Job(job);
+            }
+
+            if self.queued.load(Ordering::SeqCst) > 0 && retries < MAX_POP_RETRIES {
+                // A job is being queued or is held by another worker, look again
+                retries += 1;
+                thread::yield_now();
+                continue;
+            }
+
+            let mut wakeups = self.sleepers.lock().unwrap();
+            self.sleeping_workers.fetch_add(1, Ordering::SeqCst);
+            let popped = if self.queued.load(Ordering::SeqCst) > 0 {
+                // The job still can not be taken, back off instead of spinning on it
+                wakeups = self
+                    .job_available
+                    .wait_timeout(wakeups, POP_BACKOFF)
+                    .unwrap()
+                    .0;
+                None
+            } else if self.closed.load(Ordering::SeqCst) {
+                Some(Popped::Closed)
+            } else {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                if remaining.is_zero() {
+                    Some(Popped::TimedOut)
+                } else {
+                    wakeups = self
+                        .job_available
+                        .wait_timeout(wakeups, remaining)
+                        .unwrap()
+                        .0;
+                    None
+                }
+            };
+            // A producer that has sent a wake-up has already taken a worker off the sleeping ones
+            if *wakeups > 0 {
+                *wakeups -= 1;
+            } else {
+                self.sleeping_workers.fetch_sub(1, Ordering::SeqCst);
+            }
+            drop(wakeups);
+
+            if let Some(popped) = popped {
+                return popped;
+            }
+        }
+    }
+
+    /// Takes the next job without waiting, used by workers that wait for other jobs
+    pub(crate) fn try_pop(&self) -> Option<Job> {
+        let job = self.find_job()?;
+        self.queued.fetch_sub(1, Ordering::SeqCst);
+        if self.blocked_producers.load(Ordering::SeqCst) > 0 {
+            let _guard = self.waiting_producers.lock().unwrap();
+            self.room_available.notify_one();
+        }
+
+        Some(job)
+    }
+
+    /// Whether the current thread is a worker registered in this scheduler
+    pub(crate) fn is_worker_thread(&self) -> bool {
+        LOCAL_QUEUE.with_borrow(|local| {
+            local
+                .as_ref()
+                .is_some_and(|local| local.scheduler_id == self.id)
+        })
+    }
+
+    /// Wakes up all the workers waiting for jobs so they can shut down
+    pub(crate) fn close(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+
+        let _guard = self.sleepers.lock().unwrap();
+        self.job_available.notify_all();
+    }
+
+    pub(crate) fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.queued.load(Ordering::SeqCst)
+    }
+
+    fn enqueue(&self, job: Job) {
+        let job = LOCAL_QUEUE.with_borrow(|local| match local {
+            Some(local) if local.scheduler_id == self.id => {
+                local.deque.push(job);
+                None
+            }
+            _ => Some(job),
+        });
+        if let Some(job) = job {
+            self.injector.push(job);
+        }
+
+        // Each sleeping worker is woken up once, rather than once per job queued before it runs
+        if self.sleeping_workers.load(Ordering::SeqCst) > 0 {
+            let mut wakeups = self.sleepers.lock().unwrap();
+            let woken = self.sleeping_workers.fetch_update(
+                Ordering::SeqCst,
+                Ordering::SeqCst,
+                |sleeping| sleeping.checked_sub(1),
+            );
+            if woken.is_ok() {
+                *wakeups += 1;
+                self.job_available.notify_one();
+            }
+        }
+    }
+
+    fn find_job(&self) -> Option<Job> {
+        LOCAL_QUEUE.with_borrow(|local| {
+            let local = local
+                .as_ref()
+                .filter(|local| local.scheduler_id == self.id)?;
+
+            local.deque.pop().or_else(|| {
+                iter::repeat_with(|| {
+                    self.injector.steal_batch_and_pop(&local.deque).or_else(|| {
+                        self.stealers
+                            .read()
+                            .unwrap()
+                            .iter()
+                            .map(|(_, stealer)| stealer.steal())
+                            .collect()
+                    })
+                })
+                .find(|steal| !steal.is_retry())
+                .and_then(Steal::success)
+            })
+        })
+    }
+
+    fn steal_oldest(&self) -> Option<Job> {
+        iter::repeat_with(|| {
+            self.injector.steal().or_else(|| {
+                self.stealers
+                    .read()
+                    .unwrap()
+                    .iter()
+                    .map(|(_, stealer)| stealer.steal())
+                    .collect()
+            })
+        })
+        .find(|steal| !steal.is_retry())
+        .and_then(Steal::success)
+    }
+}
+
+impl Display for ExecuteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExecuteError::QueueFull => write!(f, "Job queue is full"),
+            ExecuteError::ShutDown => write!(f, "Thread pool is shut down"),
+        }
+    }
+}
+
+impl std::error::Error for ExecuteError {}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use super::*;
+
+    fn counting_job(counter: &Arc<AtomicUsize>, value: usize) -> Job {
+        let counter = Arc::clone(counter);
+        Box::new(move || {
+            counter.fetch_add(value, Ordering::SeqCst);
+        })
+    }
+
+    fn run_all(scheduler: &Scheduler) {
+        scheduler.close();
+        scheduler.register_worker(0);
+        while let Popped::Job(job) = scheduler.pop(Duration::ZERO) {
+            job();
+        }
+        scheduler.unregister_worker(0);
+    }
+
+    #[test]
+    fn push_must_reject_jobs_when_queue_is_full() {
+        let scheduler = Scheduler::new(1, OverloadPolicy::Reject);
+        let counter = Arc::new(AtomicUsize::new(0));
+
+        let first = scheduler.push(counting_job(&counter, 1));
+        let second = scheduler.push(counting_job(&counter, 10));
+        run_all(&scheduler);
+
+        assert_eq!(Ok(()), first, "First job must be queued");
+        assert_eq!(
+            Err(ExecuteError::QueueFull),
+            second,
+            "Second job must be rejected"
+        );
+        assert_eq!(
+            1,
+            counter.load(Ordering::SeqCst),
+            "Only the first job must run"
+        );
+    }
+
+    #[test]
+    fn push_must_drop_oldest_job_when_queue_is_full() {
+        let scheduler = Scheduler::new(1, OverloadPolicy::DropOldest);
+        let counter = Arc::new(AtomicUsize::new(0));
+
+        scheduler.push(counting_job(&counter, 1)).unwrap();
+        let second = scheduler.push(counting_job(&counter, 10));
+        run_all(&scheduler);
+
+        assert_eq!(Ok(()), second, "Second job must be queued");
+        assert_eq!(
+            10,
+            counter.load(Ordering::SeqCst),
+            "Only the second job must run"
+        );
+    }
+
+    #[test]
+    fn push_must_block_until_queue_has_room() {
+        let scheduler = Arc::new(Scheduler::new(1, OverloadPolicy::Block));
+        let counter = Arc::new(AtomicUsize::new(0));
+        scheduler.push(counting_job(&counter, 1)).unwrap();
+
+        let producer = {
+            let scheduler = Arc::clone(&scheduler);
+            let counter = Arc::clone(&counter);
+            std::thread::spawn(move || scheduler.push(counting_job(&counter, 10)))
+        };
+        scheduler.register_worker(0);
+        if let Popped::Job(job) = scheduler.pop(Duration::from_secs(5)) {
+            job();
+        }
+        scheduler.unregister_worker(0);
+        let second = producer.join().unwrap();
+        run_all(&scheduler);
+
+        assert_eq!(Ok(()), second, "Blocked job must be queued");
+        assert_eq!(11, counter.load(Ordering::SeqCst), "Both jobs must run");
+    }
+
+    #[test]
+    fn try_push_must_reject_instead_of_blocking_when_queue_is_full() {
+        let scheduler = Scheduler::new(1, OverloadPolicy::Block);
+        let counter = Arc::new(AtomicUsize::new(0));
+
+        scheduler.push(counting_job(&counter, 1)).unwrap();
+        let second = scheduler.try_push(counting_job(&counter, 10));
+        run_all(&scheduler);
+
+        assert_eq!(
+            Err(ExecuteError::QueueFull),
+            second,
+            "Job must be rejected instead of waiting for room"
+        );
+        assert_eq!(
+            1,
+            counter.load(Ordering::SeqCst),
+            "Only the first job must run"
+        );
+    }
+
+    #[test]
+    fn idle_worker_must_steal_jobs_from_busy_worker() {
+        let scheduler = Arc::new(Scheduler::new(16, OverloadPolicy::Block));
+        let counter = Arc::new(AtomicUsize::new(0));
+
+        // Jobs pushed by a worker land in its local deque
+        scheduler.register_worker(0);
+        for _ in 0..4 {
+            scheduler.push(counting_job(&counter, 1)).unwrap();
+        }
+
+        let thief = {
+            let scheduler = Arc::clone(&scheduler);
+            std::thread::spawn(move || {
+                scheduler.register_worker(1);
+                let popped = scheduler.pop(Duration::from_secs(5));
+                scheduler.unregister_worker(1);
+                popped
+            })
+        };
+        let stolen = matches!(thief.join().unwrap(), Popped::Job(_));
+        scheduler.unregister_worker(0);
+
+        assert!(
+            stolen,
+            "Job must be stolen from the local deque of another worker"
+        );
+        assert_eq!(3, scheduler.len(), "Stolen job must leave the queue");
+    }
+}
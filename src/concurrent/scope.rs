@@ -0,0 +1,122 @@
+use std::{
+    marker::PhantomData,
+    sync::{Arc, Condvar, Mutex},
+};
+
+use crate::concurrent::{
+    job_handle::{JobError, JobHandle},
+    scheduler::ExecuteError,
+    thread_pool::PoolHandle,
+};
+
+/// A scope to spawn jobs borrowing data from outside of it, created by `PoolHandle::scope`
+///
+/// All the jobs spawned in the scope are finished before `PoolHandle::scope` returns
+pub struct Scope<'scope, 'env: 'scope> {
+    pool: &'scope PoolHandle,
+    state: Arc<ScopeState>,
+    scope: PhantomData<&'scope mut &'scope ()>,
+    env: PhantomData<&'env mut &'env ()>,
+}
+
+/// A handle to a job spawned in a scope, it can not outlive the scope
+pub struct ScopedJobHandle<'scope, T> {
+    handle: JobHandle<T>,
+    scope: PhantomData<&'scope ()>,
+}
+
+pub(crate) struct ScopeState {
+    pending: Mutex<usize>,
+    all_finished: Condvar,
+}
+
+/// A job of the scope, the task is dropped before the job is marked as finished
+/// even when the job does not run
+struct ScopedJob<J> {
+    job: J,
+    pending: PendingJob,
+}
+
+/// Marks a job of the scope as finished when it has run or has been dropped without running
+struct PendingJob {
+    state: Arc<ScopeState>,
+}
+
+impl<'scope, 'env> Scope<'scope, 'env> {
+    pub(crate) fn new(pool: &'scope PoolHandle) -> Scope<'scope, 'env> {
+        Scope {
+            pool,
+            state: Arc::new(ScopeState {
+                pending: Mutex::new(0),
+                all_finished: Condvar::new(),
+            }),
+            scope: PhantomData,
+            env: PhantomData,
+        }
+    }
+
+    /// Queues the task on the pool, unlike `PoolHandle::spawn` it can borrow from outside the scope
+    pub fn spawn<F, T>(&'scope self, task: F) -> Result<ScopedJobHandle<'scope, T>, ExecuteError>
+    where
+        F: FnOnce() -> T + Send + 'scope,
+        T: Send + 'scope,
+    {
+        *self.state.pending.lock().unwrap() += 1;
+        let pending = PendingJob {
+            state: Arc::clone(&self.state),
+        };
+
+        let (job, handle) = JobHandle::new(task, self.pool.shared());
+        let scoped_job = ScopedJob { job, pending };
+        let job: Box<dyn FnOnce() + Send + 'scope> = Box::new(move || scoped_job.run());
+        // SAFETY: `PoolHandle::scope` does not return before every job spawned in the scope
+        // has either run or been dropped, which `PendingJob` keeps track of,
+        // so the data borrowed for 'scope outlives the job
+        let job: Box<dyn FnOnce() + Send + 'static> = unsafe { std::mem::transmute(job) };
+
+        self.pool.execute(job)?;
+
+        Ok(ScopedJobHandle {
+            handle,
+            scope: PhantomData,
+        })
+    }
+
+    /// Waits for every job spawned in the scope
+    pub(crate) fn wait(&self) {
+        drop(self.pool.shared().wait_while(
+            &self.state.pending,
+            &self.state.all_finished,
+            |pending| *pending > 0,
+        ));
+    }
+}
+
+impl<T> ScopedJobHandle<'_, T> {
+    /// Waits for the job to finish and returns its result
+    pub fn join(self) -> Result<T, JobError> {
+        self.handle.join()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.handle.is_finished()
+    }
+}
+
+impl<J: FnOnce()> ScopedJob<J> {
+    fn run(self) {
+        let ScopedJob { job, pending } = self;
+        job();
+        drop(pending);
+    }
+}
+
+impl Drop for PendingJob {
+    fn drop(&mut self) {
+        let mut pending = self.state.pending.lock().unwrap();
+        *pending -= 1;
+        if *pending == 0 {
+            self.state.all_finished.notify_all();
+        }
+    }
+}
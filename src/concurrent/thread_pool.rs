@@ -1,57 +1,700 @@
-use std::sync::{mpsc, Arc, Mutex};
+use std::{
+    cell::RefCell,
+    ops::Deref,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Condvar, Mutex, MutexGuard,
+    },
+    thread,
+    time::Duration,
+};
 
-use crate::concurrent::worker::{Job, Worker};
+use log::{debug, error};
 
+use crate::concurrent::{
+    job_handle::JobHandle,
+    scheduler::{ExecuteError, OverloadPolicy, Scheduler},
+    scope::Scope,
+    worker::Worker,
+};
+
+thread_local! {
+    /// The pool of the worker running on the current thread
+    static CURRENT_POOL: RefCell<Option<PoolHandle>> = const { RefCell::new(None) };
+}
+
+/// How often a worker waiting for another job checks the queue for jobs to run meanwhile
+const HELP_INTERVAL: Duration = Duration::from_millis(1);
+
+/// The default max amount of jobs waiting for a free worker
+pub const DEFAULT_QUEUE_CAPACITY: usize = 1024;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PoolConfig {
+    /// Workers that are kept alive even when there is nothing to do
+    pub min_size: usize,
+    /// The max amount of workers, new ones are spawned when the queue backs up
+    pub max_size: usize,
+    /// How long a worker above `min_size` waits for a job before shutting down
+    pub keep_alive: Duration,
+    pub queue_capacity: usize,
+    pub overload_policy: OverloadPolicy,
+}
+
+/// Called with the worker id on the worker thread
+pub type ThreadHook = Arc<dyn Fn(usize) + Send + Sync + 'static>;
+
+pub struct ThreadPoolBuilder {
+    config: PoolConfig,
+    threads: ThreadConfig,
+}
+
+/// How the worker threads are started
+#[derive(Clone)]
+pub(crate) struct ThreadConfig {
+    /// Threads are named `<name_prefix>-<worker id>`
+    pub(crate) name_prefix: String,
+    pub(crate) stack_size: Option<usize>,
+    /// Workers are pinned to these CPUs in turn, empty to let the OS schedule them
+    #[cfg(target_os = "linux")]
+    pub(crate) cpus: Vec<usize>,
+    pub(crate) on_start: Option<ThreadHook>,
+    pub(crate) on_stop: Option<ThreadHook>,
+}
+
+/// A snapshot of the pool load
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PoolStats {
+    /// Live workers
+    pub workers: usize,
+    /// Workers running a job
+    pub active: usize,
+    /// Workers waiting for a job
+    pub idle: usize,
+    /// Jobs waiting for a free worker
+    pub queued: usize,
+}
+
+/// Owns the workers of a pool, they are shut down once it is dropped
+///
+/// Jobs are submitted through the `PoolHandle` it dereferences to
 pub struct ThreadPool {
-    workers: Vec<Worker>,
-    sender: Option<mpsc::Sender<Job>>,
+    handle: PoolHandle,
+}
+
+/// Submits jobs to a pool, it can be cloned and shared with the jobs themselves
+#[derive(Clone)]
+pub struct PoolHandle {
+    shared: Arc<PoolShared>,
+}
+
+/// State shared by the pool and its workers
+pub(crate) struct PoolShared {
+    min_size: usize,
+    max_size: usize,
+    keep_alive: Duration,
+    pub(crate) threads: ThreadConfig,
+    pub(crate) scheduler: Scheduler,
+    workers: AtomicUsize,
+    active: AtomicUsize,
+    next_worker_id: AtomicUsize,
+    handles: Mutex<Vec<Worker>>,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        let parallelism = thread::available_parallelism().map_or(1, |n| n.get());
+
+        Self {
+            min_size: 1,
+            max_size: parallelism * 4,
+            keep_alive: Duration::from_secs(60),
+            queue_capacity: DEFAULT_QUEUE_CAPACITY,
+            overload_policy: OverloadPolicy::Block,
+        }
+    }
 }
 
 impl ThreadPool {
-    /// Creates a new thread pool with the provided size
+    /// Creates a new thread pool with the provided fixed size
     ///
-    /// The size is the max amound of threads in the pool
+    /// At most `DEFAULT_QUEUE_CAPACITY` jobs wait for a free worker
     ///
     /// # Panics
     ///
     /// `new` will panic if the provided size is 0
     pub fn new(size: usize) -> ThreadPool {
-        assert!(size > 0);
+        ThreadPool::builder().size(size).build()
+    }
 
-        let (sender, receiver) = mpsc::channel();
-        let receiver = Arc::new(Mutex::new(receiver));
-        let mut workers = Vec::with_capacity(size);
+    pub fn builder() -> ThreadPoolBuilder {
+        ThreadPoolBuilder {
+            config: PoolConfig::default(),
+            threads: ThreadConfig {
+                name_prefix: String::from("worker"),
+                stack_size: None,
+                #[cfg(target_os = "linux")]
+                cpus: Vec::new(),
+                on_start: None,
+                on_stop: None,
+            },
+        }
+    }
+}
+
+impl ThreadPoolBuilder {
+    /// Sets all the sizes and queue settings at once
+    pub fn config(mut self, config: PoolConfig) -> ThreadPoolBuilder {
+        self.config = config;
+
+        self
+    }
+
+    /// Sets a fixed size, the pool neither grows nor shrinks
+    pub fn size(mut self, size: usize) -> ThreadPoolBuilder {
+        self.config.min_size = size;
+        self.config.max_size = size;
+
+        self
+    }
+
+    pub fn min_size(mut self, min_size: usize) -> ThreadPoolBuilder {
+        self.config.min_size = min_size;
+
+        self
+    }
+
+    pub fn max_size(mut self, max_size: usize) -> ThreadPoolBuilder {
+        self.config.max_size = max_size;
+
+        self
+    }
+
+    pub fn keep_alive(mut self, keep_alive: Duration) -> ThreadPoolBuilder {
+        self.config.keep_alive = keep_alive;
+
+        self
+    }
+
+    pub fn queue_capacity(mut self, queue_capacity: usize) -> ThreadPoolBuilder {
+        self.config.queue_capacity = queue_capacity;
+
+        self
+    }
+
+    pub fn overload_policy(mut self, overload_policy: OverloadPolicy) -> ThreadPoolBuilder {
+        self.config.overload_policy = overload_policy;
 
-        for worker_id in 0..size {
-            workers.push(Worker::new(worker_id, Arc::clone(&receiver)));
+        self
+    }
+
+    /// Worker threads are named `<name_prefix>-<worker id>`
+    pub fn thread_name(mut self, name_prefix: impl Into<String>) -> ThreadPoolBuilder {
+        self.threads.name_prefix = Into::into(name_prefix);
+
+        self
+    }
+
+    /// Stack size of the worker threads in bytes
+    pub fn stack_size(mut self, stack_size: usize) -> ThreadPoolBuilder {
+        self.threads.stack_size = Some(stack_size);
+
+        self
+    }
+
+    /// Pins the workers to the CPUs in turn, the first worker to the first CPU and so on
+    #[cfg(target_os = "linux")]
+    pub fn cpu_affinity(mut self, cpus: Vec<usize>) -> ThreadPoolBuilder {
+        self.threads.cpus = cpus;
+
+        self
+    }
+
+    /// Runs the hook on every worker thread before it takes its first job
+    pub fn on_thread_start(
+        mut self,
+        hook: impl Fn(usize) + Send + Sync + 'static,
+    ) -> ThreadPoolBuilder {
+        self.threads.on_start = Some(Arc::new(hook));
+
+        self
+    }
+
+    /// Runs the hook on every worker thread before it shuts down
+    pub fn on_thread_stop(
+        mut self,
+        hook: impl Fn(usize) + Send + Sync + 'static,
+    ) -> ThreadPoolBuilder {
+        self.threads.on_stop = Some(Arc::new(hook));
+
+        self
+    }
+
+    /// Creates the pool and starts `min_size` workers
+    ///
+    /// # Panics
+    ///
+    /// `build` will panic if `max_size` or the queue capacity is 0,
+    /// or if `min_size` is greater than `max_size`
+    pub fn build(self) -> ThreadPool {
+        let config = self.config;
+        assert!(config.max_size > 0);
+        assert!(config.min_size <= config.max_size);
+
+        let shared = Arc::new(PoolShared {
+            min_size: config.min_size,
+            max_size: config.max_size,
+            keep_alive: config.keep_alive,
+            threads: self.threads,
+            scheduler: Scheduler::new(config.queue_capacity, config.overload_policy),
+            workers: AtomicUsize::new(0),
+            active: AtomicUsize::new(0),
+            next_worker_id: AtomicUsize::new(0),
+            handles: Mutex::new(Vec::with_capacity(config.max_size)),
+        });
+
+        for _ in 0..config.min_size {
+            shared.try_spawn_worker();
         }
 
         ThreadPool {
-            workers,
-            sender: Some(sender),
+            handle: PoolHandle { shared },
         }
     }
+}
+
+impl Deref for ThreadPool {
+    type Target = PoolHandle;
+
+    fn deref(&self) -> &Self::Target {
+        &self.handle
+    }
+}
+
+impl PoolHandle {
+    /// The pool running the current job, `None` outside of pool workers
+    ///
+    /// Lets request handlers fan out work on the pool of the server
+    pub fn current() -> Option<PoolHandle> {
+        CURRENT_POOL.with_borrow(|pool| pool.clone())
+    }
 
-    pub fn execute<F>(&self, task: F)
+    /// Queues the task to be run by a free worker, spawning a new one if all are busy
+    ///
+    /// When the queue is full the overload policy decides whether this call waits,
+    /// the task is rejected or the oldest queued task is dropped to make room for it.
+    /// Rejected and dropped tasks are never run
+    pub fn execute<F>(&self, task: F) -> Result<(), ExecuteError>
     where
         F: FnOnce() + Send + 'static,
     {
-        let task = Box::new(task);
+        self.grow_if_busy();
+        self.shared.scheduler.push(Box::new(task))
+    }
+
+    /// Queues the task like `execute`, but never waits for room in the queue
+    ///
+    /// Under the `Block` overload policy the task is rejected when the queue is full,
+    /// for callers such as an event loop that must not stall
+    pub fn try_execute<F>(&self, task: F) -> Result<(), ExecuteError>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.grow_if_busy();
+        self.shared.scheduler.try_push(Box::new(task))
+    }
+
+    /// Queues the task like `execute` and returns a handle to wait for its result
+    pub fn spawn<F, T>(&self, task: F) -> Result<JobHandle<T>, ExecuteError>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let (job, handle) = JobHandle::new(task, self.shared());
+        self.execute(job)?;
+
+        Ok(handle)
+    }
+
+    /// Spawns a worker for a new task if the idle ones can not take it
+    fn grow_if_busy(&self) {
+        let stats = self.stats();
+        if stats.queued >= stats.idle && !self.shared.scheduler.is_closed() {
+            self.shared.try_spawn_worker();
+        }
+    }
+
+    /// Creates a scope for spawning jobs that borrow non-`'static` data
+    ///
+    /// Every job spawned in the scope is finished before `scope` returns,
+    /// even if the closure panics
+    pub fn scope<'env, F, R>(&self, f: F) -> R
+    where
+        F: for<'scope> FnOnce(&'scope Scope<'scope, 'env>) -> R,
+    {
+        let scope = Scope::new(self);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| f(&scope)));
+        scope.wait();
 
-        self.sender.as_ref().unwrap().send(task).unwrap();
+        match result {
+            Ok(result) => result,
+            Err(payload) => panic::resume_unwind(payload),
+        }
     }
 
+    /// The current amount of workers
     pub fn size(&self) -> usize {
-        self.workers.len()
+        self.shared.workers.load(Ordering::SeqCst)
+    }
+
+    pub fn min_size(&self) -> usize {
+        self.shared.min_size
+    }
+
+    pub fn max_size(&self) -> usize {
+        self.shared.max_size
+    }
+
+    pub fn overload_policy(&self) -> OverloadPolicy {
+        self.shared.scheduler.overload_policy()
+    }
+
+    pub(crate) fn shared(&self) -> Arc<PoolShared> {
+        Arc::clone(&self.shared)
+    }
+
+    pub fn stats(&self) -> PoolStats {
+        let workers = self.shared.workers.load(Ordering::SeqCst);
+        let active = self.shared.active.load(Ordering::SeqCst);
+
+        PoolStats {
+            workers,
+            active,
+            idle: workers.saturating_sub(active),
+            queued: self.shared.scheduler.len(),
+        }
+    }
+}
+
+impl PoolShared {
+    /// Blocks while the condition holds, like `Condvar::wait_while`
+    ///
+    /// A worker of this pool runs queued jobs while waiting instead of idling,
+    /// since the job it waits for may be among them
+    pub(crate) fn wait_while<'a, G>(
+        &self,
+        mutex: &'a Mutex<G>,
+        condvar: &Condvar,
+        mut condition: impl FnMut(&mut G) -> bool,
+    ) -> MutexGuard<'a, G> {
+        let guard = mutex.lock().unwrap();
+        if !self.scheduler.is_worker_thread() {
+            return condvar.wait_while(guard, condition).unwrap();
+        }
+
+        let mut guard = guard;
+        while condition(&mut guard) {
+            drop(guard);
+            if let Some(job) = self.scheduler.try_pop() {
+                let _ = panic::catch_unwind(AssertUnwindSafe(job));
+                guard = mutex.lock().unwrap();
+                continue;
+            }
+
+            guard = mutex.lock().unwrap();
+            if condition(&mut guard) {
+                guard = condvar.wait_timeout(guard, HELP_INTERVAL).unwrap().0;
+            }
+        }
+
+        guard
+    }
+
+    /// Makes the pool available through `PoolHandle::current` on a worker thread
+    pub(crate) fn set_current(self: &Arc<Self>) {
+        CURRENT_POOL.set(Some(PoolHandle {
+            shared: Arc::clone(self),
+        }));
+    }
+
+    pub(crate) fn clear_current(&self) {
+        CURRENT_POOL.set(None);
+    }
+
+    pub(crate) fn keep_alive(&self) -> Duration {
+        self.keep_alive
+    }
+
+    pub(crate) fn job_started(&self) {
+        self.active.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub(crate) fn job_finished(&self) {
+        self.active.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    /// Lets an idle worker shut down unless the pool would shrink below `min_size`
+    pub(crate) fn try_retire_worker(&self) -> bool {
+        self.workers
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |workers| {
+                (workers > self.min_size).then(|| workers - 1)
+            })
+            .is_ok()
+    }
+
+    fn try_spawn_worker(self: &Arc<Self>) {
+        let reserved = self
+            .workers
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |workers| {
+                (workers < self.max_size).then(|| workers + 1)
+            });
+        if reserved.is_err() {
+            return;
+        }
+
+        let worker_id = self.next_worker_id.fetch_add(1, Ordering::SeqCst);
+        let worker = match Worker::new(worker_id, Arc::clone(self)) {
+            Ok(worker) => worker,
+            Err(e) => {
+                self.workers.fetch_sub(1, Ordering::SeqCst);
+                error!("Unable to start worker {}: {}", worker_id, e);
+                return;
+            }
+        };
+
+        let mut handles = self.handles.lock().unwrap();
+        handles.retain(|worker| !worker.is_finished());
+        handles.push(worker);
     }
 }
 
 impl Drop for ThreadPool {
     fn drop(&mut self) {
-        drop(self.sender.take());
-        for worker in self.workers.drain(..) {
-            println!("Shutting down worker {}", worker.id);
+        let shared = &self.handle.shared;
+        shared.scheduler.close();
+
+        let workers = std::mem::take(&mut *shared.handles.lock().unwrap());
+        for worker in workers {
+            debug!("Shutting down worker {}", worker.id);
             worker.join().unwrap();
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        future::Future,
+        pin::Pin,
+        sync::mpsc,
+        task::{Context, Poll, Waker},
+        time::Instant,
+    };
+
+    use super::*;
+    use crate::concurrent::job_handle::JobError;
+
+    #[test]
+    fn execute_must_grow_pool_up_to_max_size() {
+        let pool = ThreadPool::builder().min_size(1).max_size(3).build();
+        let (release_sender, release_receiver) = mpsc::channel::<()>();
+        let release_receiver = Arc::new(Mutex::new(release_receiver));
+        let (started_sender, started_receiver) = mpsc::channel();
+
+        for _ in 0..4 {
+            let release_receiver = Arc::clone(&release_receiver);
+            let started_sender = started_sender.clone();
+            pool.execute(move || {
+                started_sender.send(()).unwrap();
+                let _ = release_receiver.lock().unwrap().recv();
+            })
+            .unwrap();
+        }
+        for _ in 0..3 {
+            started_receiver
+                .recv_timeout(Duration::from_secs(5))
+                .unwrap();
+        }
+
+        assert_eq!(
+            PoolStats {
+                workers: 3,
+                active: 3,
+                idle: 0,
+                queued: 1,
+            },
+            pool.stats(),
+            "Pool must grow to the max size and queue the rest"
+        );
+
+        drop(release_sender);
+    }
+
+    #[test]
+    fn idle_workers_above_min_size_must_be_retired() {
+        let pool = ThreadPool::builder()
+            .min_size(1)
+            .max_size(2)
+            .keep_alive(Duration::from_millis(10))
+            .build();
+        let (release_sender, release_receiver) = mpsc::channel::<()>();
+        let release_receiver = Arc::new(Mutex::new(release_receiver));
+        let (started_sender, started_receiver) = mpsc::channel();
+
+        for _ in 0..2 {
+            let release_receiver = Arc::clone(&release_receiver);
+            let started_sender = started_sender.clone();
+            pool.execute(move || {
+                started_sender.send(()).unwrap();
+                let _ = release_receiver.lock().unwrap().recv();
+            })
+            .unwrap();
+        }
+        for _ in 0..2 {
+            started_receiver
+                .recv_timeout(Duration::from_secs(5))
+                .unwrap();
+        }
+        assert_eq!(2, pool.size(), "Pool must grow while jobs are running");
+
+        drop(release_sender);
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while pool.size() > 1 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        assert_eq!(1, pool.size(), "Pool must shrink back to the min size");
+    }
+
+    #[test]
+    fn spawn_must_return_job_result() {
+        let pool = ThreadPool::new(2);
+
+        let handle = pool.spawn(|| 2 + 2).unwrap();
+        let panicking = pool.spawn(|| panic!("Job failure")).unwrap();
+
+        assert_eq!(4, handle.join().unwrap(), "Job result must be returned");
+        assert!(
+            matches!(panicking.join(), Err(JobError::Panicked(_))),
+            "Job panic must be returned as an error"
+        );
+    }
+
+    #[test]
+    fn join_must_run_queued_jobs_on_single_worker() {
+        let pool = ThreadPool::new(1);
+
+        let handle = pool
+            .spawn(|| {
+                let pool = PoolHandle::current().expect("Job must run on a pool");
+                let subtasks: Vec<JobHandle<u64>> = (1..=4)
+                    .map(|i| pool.spawn(move || i * 10).unwrap())
+                    .collect();
+
+                subtasks.into_iter().map(|h| h.join().unwrap()).sum::<u64>()
+            })
+            .unwrap();
+
+        assert_eq!(
+            100,
+            handle.join().unwrap(),
+            "Subtasks must be run by the worker waiting for them"
+        );
+    }
+
+    #[test]
+    fn spawn_must_not_block_worker_on_full_queue() {
+        let pool = ThreadPool::builder().size(1).queue_capacity(1).build();
+
+        let handle = pool
+            .spawn(|| {
+                let pool = PoolHandle::current().expect("Job must run on a pool");
+                let subtasks: Vec<JobHandle<u64>> = (1..=4)
+                    .map(|i| pool.spawn(move || i * 10).unwrap())
+                    .collect();
+
+                subtasks.into_iter().map(|h| h.join().unwrap()).sum::<u64>()
+            })
+            .unwrap();
+
+        assert_eq!(
+            100,
+            handle.join().unwrap(),
+            "Worker waiting for room must run the queued subtasks"
+        );
+    }
+
+    #[test]
+    fn scope_must_allow_borrowing_data() {
+        let pool = ThreadPool::new(2);
+        let numbers: Vec<u64> = (1..=100).collect();
+
+        let sum: u64 = pool.scope(|scope| {
+            let handles: Vec<_> = numbers
+                .chunks(25)
+                .map(|chunk| scope.spawn(move || chunk.iter().sum::<u64>()).unwrap())
+                .collect();
+
+            handles.into_iter().map(|h| h.join().unwrap()).sum()
+        });
+
+        assert_eq!(5050, sum, "Scoped jobs must sum the borrowed numbers");
+    }
+
+    #[test]
+    fn job_handle_must_be_pollable() {
+        let pool = ThreadPool::new(1);
+        let (release_sender, release_receiver) = mpsc::channel::<()>();
+        let mut handle = pool
+            .spawn(move || {
+                let _ = release_receiver.recv();
+                "done"
+            })
+            .unwrap();
+        let mut context = Context::from_waker(Waker::noop());
+
+        let pending = Pin::new(&mut handle).poll(&mut context).is_pending();
+        drop(release_sender);
+        while !handle.is_finished() {
+            thread::sleep(Duration::from_millis(1));
+        }
+        let ready = Pin::new(&mut handle).poll(&mut context);
+
+        assert!(pending, "Unfinished job must be pending");
+        assert!(
+            matches!(ready, Poll::Ready(Ok("done"))),
+            "Finished job must be ready"
+        );
+    }
+
+    #[test]
+    fn builder_must_configure_worker_threads() {
+        let (sender, receiver) = mpsc::channel();
+        let start_sender = Mutex::new(sender.clone());
+        let stop_sender = Mutex::new(sender);
+        let pool = ThreadPool::builder()
+            .size(1)
+            .thread_name("test-worker")
+            .stack_size(256 * 1024)
+            .on_thread_start(move |id| {
+                let name = thread::current().name().map(String::from);
+                start_sender.lock().unwrap().send((id, name)).unwrap();
+            })
+            .on_thread_stop(move |id| {
+                stop_sender.lock().unwrap().send((id, None)).unwrap();
+            })
+            .build();
+
+        let started = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        drop(pool);
+        let stopped = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+
+        assert_eq!(
+            (0, Some(String::from("test-worker-0"))),
+            started,
+            "Start hook must run on the named worker thread"
+        );
+        assert_eq!((0, None), stopped, "Stop hook must run on shutdown");
+    }
+}
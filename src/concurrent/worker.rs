@@ -1,9 +1,19 @@
 use std::{
     any::Any,
-    sync::{mpsc::Receiver, Arc, Mutex},
+    io,
+    panic::{self, AssertUnwindSafe},
+    sync::Arc,
     thread::{self, JoinHandle},
 };
 
+#[cfg(target_os = "linux")]
+use log::warn;
+use log::{debug, error};
+
+#[cfg(target_os = "linux")]
+use crate::concurrent::affinity;
+use crate::concurrent::{scheduler::Popped, thread_pool::PoolShared};
+
 pub struct Worker {
     pub id: usize,
     handle: JoinHandle<()>,
@@ -12,26 +22,78 @@ pub struct Worker {
 pub type Job = Box<dyn FnOnce() + Send + 'static>;
 
 impl Worker {
-    pub fn new(id: usize, receiver: Arc<Mutex<Receiver<Job>>>) -> Worker {
-        let handle = thread::spawn(move || loop {
-            let message = receiver.lock().unwrap().recv();
-
-            match message {
-                Ok(job) => {
-                    println!("Worker {id} received a new job");
-                    job();
-                }
-                Err(_) => {
-                    println!("Worker {id} disconnected, shutting down");
-                    break;
-                }
+    pub(crate) fn new(id: usize, pool: Arc<PoolShared>) -> io::Result<Worker> {
+        let mut builder =
+            thread::Builder::new().name(format!("{}-{}", pool.threads.name_prefix, id));
+        if let Some(stack_size) = pool.threads.stack_size {
+            builder = builder.stack_size(stack_size);
+        }
+
+        let handle = builder.spawn(move || {
+            #[cfg(target_os = "linux")]
+            pin(id, &pool.threads.cpus);
+            if let Some(on_start) = &pool.threads.on_start {
+                on_start(id);
             }
-        });
+            pool.scheduler.register_worker(id);
+            pool.set_current();
+
+            run(id, &pool);
 
-        Worker { id, handle }
+            pool.clear_current();
+            pool.scheduler.unregister_worker(id);
+            if let Some(on_stop) = &pool.threads.on_stop {
+                on_stop(id);
+            }
+        })?;
+
+        Ok(Worker { id, handle })
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.handle.is_finished()
     }
 
     pub fn join(self) -> Result<(), Box<dyn Any + Send + 'static>> {
         self.handle.join()
     }
 }
+
+#[cfg(target_os = "linux")]
+fn pin(id: usize, cpus: &[usize]) {
+    if cpus.is_empty() {
+        return;
+    }
+
+    let cpu = cpus[id % cpus.len()];
+    if let Err(e) = affinity::pin_current_thread(cpu) {
+        warn!("Unable to pin worker {id} to CPU {cpu}: {e}");
+    }
+}
+
+fn run(id: usize, pool: &PoolShared) {
+    loop {
+        let message = pool.scheduler.pop(pool.keep_alive());
+
+        match message {
+            Popped::Job(job) => {
+                debug!("Worker {id} received a new job");
+                pool.job_started();
+                if panic::catch_unwind(AssertUnwindSafe(job)).is_err() {
+                    error!("Worker {id} job panicked");
+                }
+                pool.job_finished();
+            }
+            Popped::TimedOut => {
+                if pool.try_retire_worker() {
+                    debug!("Worker {id} is idle for too long, shutting down");
+                    break;
+                }
+            }
+            Popped::Closed => {
+                debug!("Worker {id} disconnected, shutting down");
+                break;
+            }
+        }
+    }
+}
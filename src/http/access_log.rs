@@ -0,0 +1,478 @@
+use std::{
+    fmt::Write as _,
+    fs::{File, OpenOptions},
+    io::{self, Read, Write},
+    net::IpAddr,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use clap::ValueEnum;
+use log::warn;
+
+use crate::{
+    http::{request::Request, response::Response, router::REQUEST_ID_HEADER},
+    logging::{json::JsonObject, timestamp::Timestamp},
+};
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Default)]
+pub enum AccessLogFormat {
+    /// Common Log Format: client, time, request line, status and response size
+    Common,
+    /// Combined Log Format: the Common Log Format followed by the referer and user agent
+    #[default]
+    Combined,
+    /// One JSON object per line, including the latency
+    Json,
+}
+
+/// Where access log lines are written to
+#[derive(Debug, Clone, PartialEq)]
+pub enum AccessLogTarget {
+    Stdout,
+    File(PathBuf),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccessLogConfig {
+    pub target: AccessLogTarget,
+    pub format: AccessLogFormat,
+}
+
+/// Records every processed request as a single line
+///
+/// A log file is reopened before the next line once `reopen` is requested,
+/// so it can be rotated by renaming it and sending SIGHUP to the server
+pub struct AccessLog {
+    format: AccessLogFormat,
+    output: Mutex<Output>,
+    reopen: Arc<AtomicBool>,
+}
+
+enum Output {
+    Stdout,
+    File(PathBuf, File),
+}
+
+/// A request as it is recorded in the access log
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccessLogEntry {
+    pub client: Option<IpAddr>,
+    pub timestamp: Timestamp,
+    /// Method, URL with its query string and protocol, missing if the request could not be parsed
+    pub request_line: Option<(String, String, String)>,
+    pub status: u16,
+    /// Size of the response body in bytes, a streamed body counts the bytes sent
+    pub size: usize,
+    pub referer: Option<String>,
+    pub user_agent: Option<String>,
+    pub latency: Duration,
+    /// ID the request has been handled with, missing if the request could not be parsed
+    pub request_id: Option<String>,
+}
+
+/// A streamed response body that writes the entry of its response once it has been sent,
+/// with the amount of bytes read from it as the size
+struct LoggedBody {
+    body: Box<dyn Read + Send>,
+    access_log: Arc<AccessLog>,
+    entry: AccessLogEntry,
+    started: Instant,
+}
+
+/// The part of an access log entry known before the request is handled
+pub(crate) struct PendingEntry {
+    client: Option<IpAddr>,
+    timestamp: Timestamp,
+    started: Instant,
+    request_line: Option<(String, String, String)>,
+    referer: Option<String>,
+    user_agent: Option<String>,
+}
+
+impl AccessLog {
+    pub fn open(config: AccessLogConfig) -> io::Result<AccessLog> {
+        let output = match config.target {
+            AccessLogTarget::Stdout => Output::Stdout,
+            AccessLogTarget::File(path) => {
+                let file = open_file(&path)?;
+                Output::File(path, file)
+            }
+        };
+
+        Ok(AccessLog {
+            format: config.format,
+            output: Mutex::new(output),
+            reopen: Arc::new(AtomicBool::new(false)),
+        })
+    }
+
+    /// Asks for the log file to be reopened before the next line is written
+    pub fn reopen(&self) {
+        self.reopen.store(true, Ordering::Relaxed);
+    }
+
+    /// Reopens the log file whenever the process receives SIGHUP
+    #[cfg(unix)]
+    pub fn reopen_on_sighup(&self) -> io::Result<()> {
+        signal_hook::flag::register(signal_hook::consts::SIGHUP, Arc::clone(&self.reopen))?;
+
+        Ok(())
+    }
+
+    pub fn write(&self, entry: &AccessLogEntry) {
+        let mut line = self.format(entry);
+        line.push('\n');
+
+        let mut output = self.output.lock().unwrap();
+        if self.reopen.swap(false, Ordering::Relaxed)
+            && let Output::File(path, file) = &mut *output
+        {
+            match open_file(path) {
+                Ok(reopened) => *file = reopened,
+                Err(e) => warn!("Unable to reopen the access log {}: {}", path.display(), e),
+            }
+        }
+
+        let written = match &mut *output {
+            Output::Stdout => io::stdout().lock().write_all(line.as_bytes()),
+            Output::File(_, file) => file.write_all(line.as_bytes()),
+        };
+        if let Err(e) = written {
+            warn!("Unable to write the access log: {}", e);
+        }
+    }
+
+    fn format(&self, entry: &AccessLogEntry) -> String {
+        match self.format {
+            AccessLogFormat::Common => common_log_line(entry),
+            AccessLogFormat::Combined => {
+                let mut line = common_log_line(entry);
+                let _ = write!(
+                    line,
+                    " \"{}\" \"{}\"",
+                    quoted_or_dash(entry.referer.as_deref()),
+                    quoted_or_dash(entry.user_agent.as_deref())
+                );
+                line
+            }
+            AccessLogFormat::Json => {
+                let (method, url, protocol) = match &entry.request_line {
+                    Some((method, url, protocol)) => (
+                        Some(method.as_str()),
+                        Some(url.as_str()),
+                        Some(protocol.as_str()),
+                    ),
+                    None => (None, None, None),
+                };
+
+                JsonObject::new()
+                    .string("timestamp", &entry.timestamp.to_string())
+                    .optional_string(
+                        "client_ip",
+                        entry.client.map(|ip| ip.to_string()).as_deref(),
+                    )
+                    .optional_string("method", method)
+                    .optional_string("url", url)
+                    .optional_string("protocol", protocol)
+                    .number("status", entry.status)
+                    .number("size", entry.size)
+                    .optional_string("referer", entry.referer.as_deref())
+                    .optional_string("user_agent", entry.user_agent.as_deref())
+                    .number(
+                        "latency_ms",
+                        format_args!("{:.3}", entry.latency.as_secs_f64() * 1000.0),
+                    )
+                    .optional_string("request_id", entry.request_id.as_deref())
+                    .build()
+            }
+        }
+    }
+}
+
+impl PendingEntry {
+    /// Starts an entry for the request, `None` stands for a request that could not be parsed
+    pub(crate) fn new(
+        client: Option<IpAddr>,
+        started: Instant,
+        request: Option<&Request>,
+    ) -> PendingEntry {
+        PendingEntry {
+            client,
+            timestamp: Timestamp::now(),
+            started,
+            request_line: request.map(|request| {
+                let target = match request.query() {
+                    Some(query) => format!("{}?{}", request.url(), query),
+                    None => request.url().to_string(),
+                };
+                (
+                    request.method().to_string(),
+                    target,
+                    request.version().to_string(),
+                )
+            }),
+            referer: request.and_then(|request| request.get_header("Referer").cloned()),
+            user_agent: request.and_then(|request| request.get_header("User-Agent").cloned()),
+        }
+    }
+
+    /// Writes the entry of the response, once its body has been sent if it is streamed
+    pub(crate) fn log(self, access_log: &Arc<AccessLog>, response: Response) -> Response {
+        if !response.has_body_stream() {
+            access_log.write(&self.finish(&response));
+            return response;
+        }
+
+        let started = self.started;
+        let entry = self.finish(&response);
+        let access_log = Arc::clone(access_log);
+        response.map_body_stream(move |body| LoggedBody {
+            body,
+            access_log,
+            entry,
+            started,
+        })
+    }
+
+    fn finish(self, response: &Response) -> AccessLogEntry {
+        AccessLogEntry {
+            client: self.client,
+            timestamp: self.timestamp,
+            request_line: self.request_line,
+            status: response.code(),
+            size: response.body().len(),
+            referer: self.referer,
+            user_agent: self.user_agent,
+            latency: self.started.elapsed(),
+            request_id: response.get_header(REQUEST_ID_HEADER).cloned(),
+        }
+    }
+}
+
+impl Read for LoggedBody {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.body.read(buf)?;
+        self.entry.size += read;
+
+        Ok(read)
+    }
+}
+
+impl Drop for LoggedBody {
+    fn drop(&mut self) {
+        self.entry.latency = self.started.elapsed();
+        self.access_log.write(&self.entry);
+    }
+}
+
+fn open_file(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+fn common_log_line(entry: &AccessLogEntry) -> String {
+    let client = entry
+        .client
+        .map_or_else(|| String::from("-"), |ip| ip.to_string());
+    let request_line = match &entry.request_line {
+        Some((method, url, protocol)) => escape(&format!("{} {} {}", method, url, protocol)),
+        None => String::from("-"),
+    };
+    let size = match entry.size {
+        0 => String::from("-"),
+        size => size.to_string(),
+    };
+
+    format!(
+        "{} - - [{}] \"{}\" {} {}",
+        client,
+        entry.timestamp.to_common_log_format(),
+        request_line,
+        entry.status,
+        size
+    )
+}
+
+fn quoted_or_dash(value: Option<&str>) -> String {
+    value.map_or_else(|| String::from("-"), escape)
+}
+
+/// Escapes quotes, backslashes and control characters the way Apache does,
+/// so a client can not forge log lines
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if c.is_control() => {
+                let _ = write!(escaped, "\\x{:02x}", c as u32);
+            }
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, net::Ipv4Addr, time::UNIX_EPOCH};
+
+    use super::*;
+    use crate::http::request::limits::RequestLimits;
+
+    fn entry() -> AccessLogEntry {
+        AccessLogEntry {
+            client: Some(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1))),
+            timestamp: Timestamp::from(UNIX_EPOCH + Duration::from_secs(971_186_136)),
+            request_line: Some((
+                String::from("GET"),
+                String::from("/apache_pb.gif"),
+                String::from("HTTP/1.0"),
+            )),
+            status: 200,
+            size: 2326,
+            referer: Some(String::from("http://www.example.com/start.html")),
+            user_agent: Some(String::from("Mozilla/4.08 \"test\"")),
+            latency: Duration::from_micros(1500),
+            request_id: Some(String::from("test_id")),
+        }
+    }
+
+    fn access_log(format: AccessLogFormat) -> AccessLog {
+        AccessLog::open(AccessLogConfig {
+            target: AccessLogTarget::Stdout,
+            format,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn format_must_write_common_and_combined_log_format() {
+        assert_eq!(
+            r#"127.0.0.1 - - [10/Oct/2000:13:55:36 +0000] "GET /apache_pb.gif HTTP/1.0" 200 2326"#,
+            access_log(AccessLogFormat::Common).format(&entry()),
+            "Entry must be written in the Common Log Format"
+        );
+        assert_eq!(
+            r#"127.0.0.1 - - [10/Oct/2000:13:55:36 +0000] "GET /apache_pb.gif HTTP/1.0" 200 2326 "http://www.example.com/start.html" "Mozilla/4.08 \"test\"""#,
+            access_log(AccessLogFormat::Combined).format(&entry()),
+            "Entry must be written in the Combined Log Format with escaped quotes"
+        );
+
+        let unparsed = AccessLogEntry {
+            client: None,
+            request_line: None,
+            status: 400,
+            size: 0,
+            referer: None,
+            user_agent: None,
+            request_id: None,
+            ..entry()
+        };
+        assert_eq!(
+            r#"- - - [10/Oct/2000:13:55:36 +0000] "-" 400 - "-" "-""#,
+            access_log(AccessLogFormat::Combined).format(&unparsed),
+            "Missing fields must be written as dashes"
+        );
+    }
+
+    #[test]
+    fn format_must_write_json_lines() {
+        assert_eq!(
+            r#"{"timestamp":"2000-10-10T13:55:36.000Z","client_ip":"127.0.0.1","method":"GET","url":"/apache_pb.gif","protocol":"HTTP/1.0","status":200,"size":2326,"referer":"http://www.example.com/start.html","user_agent":"Mozilla/4.08 \"test\"","latency_ms":1.500,"request_id":"test_id"}"#,
+            access_log(AccessLogFormat::Json).format(&entry()),
+            "Entry must be written as a JSON object"
+        );
+    }
+
+    #[test]
+    fn pending_entry_must_keep_query_string_in_request_line() {
+        let raw_request = b"GET /search?q=x&page=2 HTTP/1.1\r\n\r\n";
+        let request = Request::parse(&mut &raw_request[..], &RequestLimits::default()).unwrap();
+
+        let entry = PendingEntry::new(None, Instant::now(), Some(&request));
+
+        assert_eq!(
+            Some((
+                String::from("GET"),
+                String::from("/search?q=x&page=2"),
+                String::from("HTTP/1.1")
+            )),
+            entry.request_line,
+            "Request line must contain the query string"
+        );
+    }
+
+    #[test]
+    fn write_must_reopen_rotated_file() {
+        let path = std::env::temp_dir().join(format!("rust_web_server_{}.log", std::process::id()));
+        let rotated_path = path.with_extension("log.1");
+        let access_log = AccessLog::open(AccessLogConfig {
+            target: AccessLogTarget::File(path.clone()),
+            format: AccessLogFormat::Common,
+        })
+        .unwrap();
+
+        access_log.write(&entry());
+        fs::rename(&path, &rotated_path).unwrap();
+        access_log.write(&entry());
+        access_log.reopen();
+        access_log.write(&entry());
+
+        let rotated = fs::read_to_string(&rotated_path).unwrap();
+        let reopened = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&rotated_path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(
+            2,
+            rotated.lines().count(),
+            "Lines must be written to the rotated file until it is reopened"
+        );
+        assert_eq!(
+            1,
+            reopened.lines().count(),
+            "Lines must be written to a new file once it is reopened"
+        );
+    }
+
+    #[test]
+    fn log_must_write_streamed_response_with_bytes_sent_once_it_has_been_sent() {
+        let path = std::env::temp_dir().join(format!(
+            "rust_web_server_streamed_{}.log",
+            std::process::id()
+        ));
+        let access_log = Arc::new(
+            AccessLog::open(AccessLogConfig {
+                target: AccessLogTarget::File(path.clone()),
+                format: AccessLogFormat::Common,
+            })
+            .unwrap(),
+        );
+        let response = Response::builder()
+            .code(200)
+            .body_stream(&b"test_body"[..])
+            .build();
+
+        let response = PendingEntry::new(None, Instant::now(), None).log(&access_log, response);
+        let before_sending = fs::read_to_string(&path).unwrap();
+        response.write(&mut Vec::new()).unwrap();
+        let after_sending = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert!(
+            before_sending.is_empty(),
+            "Streamed response must not be logged before it has been sent"
+        );
+        assert!(
+            after_sending.trim_end().ends_with("\"-\" 200 9"),
+            "Size must be the amount of bytes sent: {}",
+            after_sending
+        );
+    }
+}
@@ -0,0 +1,407 @@
+use std::{
+    io,
+    net::IpAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use log::warn;
+#[cfg(unix)]
+use tokio::net::UnixListener;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::TcpListener,
+    runtime::Runtime,
+    task::JoinHandle,
+    time,
+};
+
+use crate::http::{
+    health::{Lifecycle, DRAIN_POLL_INTERVAL},
+    listener::Listener,
+    request::{error::ParseError, is_request_complete},
+    router::Router,
+};
+
+const READ_BUFFER_SIZE: usize = 4096;
+
+/// Serves connections on a tokio runtime with async accept, read and write
+///
+/// A request is buffered until it is complete, so handlers never wait for the client
+pub(crate) struct AsyncServer {
+    runtime: Runtime,
+    read_timeout: Duration,
+    write_timeout: Duration,
+    /// Connections accepted and not yet closed
+    connections: Arc<AtomicUsize>,
+}
+
+/// Counts a connection as open until it is dropped
+struct OpenConnection(Arc<AtomicUsize>);
+
+impl AsyncServer {
+    pub(crate) fn new(
+        worker_threads: usize,
+        read_timeout: Duration,
+        write_timeout: Duration,
+    ) -> Result<AsyncServer, io::Error> {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(worker_threads)
+            .thread_name("async-worker")
+            .enable_all()
+            .build()?;
+
+        Ok(AsyncServer {
+            runtime,
+            read_timeout,
+            write_timeout,
+            connections: Arc::new(AtomicUsize::new(0)),
+        })
+    }
+
+    /// Accepts connections on all listeners until the server stops accepting,
+    /// then closes the listeners
+    pub(crate) fn run(
+        &self,
+        listeners: &[Listener],
+        router: &Arc<Router>,
+        lifecycle: &Lifecycle,
+    ) -> io::Result<()> {
+        self.runtime.block_on(async {
+            let mut accept_loops = Vec::with_capacity(listeners.len());
+            for listener in listeners {
+                accept_loops.push(self.spawn_accept_loop(listener, router)?);
+            }
+
+            while lifecycle.is_accepting() {
+                time::sleep(DRAIN_POLL_INTERVAL).await;
+            }
+
+            // Dropping an accept loop drops its listener
+            for accept_loop in accept_loops {
+                accept_loop.abort();
+                if let Ok(result) = accept_loop.await {
+                    result?;
+                }
+            }
+
+            Ok(())
+        })
+    }
+
+    /// Waits until the accepted connections are closed or the timeout expires
+    ///
+    /// Returns whether all connections have been closed
+    pub(crate) fn drain(&self, timeout: Duration) -> bool {
+        self.runtime.block_on(async {
+            let deadline = Instant::now() + timeout;
+
+            while self.connections.load(Ordering::SeqCst) > 0 {
+                if Instant::now() >= deadline {
+                    return false;
+                }
+                time::sleep(DRAIN_POLL_INTERVAL).await;
+            }
+
+            true
+        })
+    }
+
+    fn spawn_accept_loop(
+        &self,
+        listener: &Listener,
+        router: &Arc<Router>,
+    ) -> io::Result<JoinHandle<io::Result<()>>> {
+        let router = Arc::clone(router);
+        let connections = Arc::clone(&self.connections);
+        let (read_timeout, write_timeout) = (self.read_timeout, self.write_timeout);
+
+        match listener {
+            Listener::Tcp(listener) => {
+                let listener = listener.try_clone()?;
+                listener.set_nonblocking(true)?;
+                let listener = TcpListener::from_std(listener)?;
+
+                Ok(tokio::spawn(async move {
+                    loop {
+                        let (stream, address) = match listener.accept().await {
+                            Ok(accepted) => accepted,
+                            Err(e) => {
+                                warn!("Unable to accept a connection: {}", e);
+                                continue;
+                            }
+                        };
+                        let client = Some(address.ip());
+                        let router = Arc::clone(&router);
+                        let open = OpenConnection::new(&connections);
+                        tokio::spawn(async move {
+                            serve(stream, client, &router, read_timeout, write_timeout).await;
+                            drop(open);
+                        });
+                    }
+                }))
+            }
+            #[cfg(unix)]
+            Listener::Unix(listener, _) => {
+                let listener = listener.try_clone()?;
+                listener.set_nonblocking(true)?;
+                let listener = UnixListener::from_std(listener)?;
+
+                Ok(tokio::spawn(async move {
+                    loop {
+                        let stream = match listener.accept().await {
+                            Ok((stream, _)) => stream,
+                            Err(e) => {
+                                warn!("Unable to accept a connection: {}", e);
+                                continue;
+                            }
+                        };
+                        let router = Arc::clone(&router);
+                        let open = OpenConnection::new(&connections);
+                        tokio::spawn(async move {
+                            serve(stream, None, &router, read_timeout, write_timeout).await;
+                            drop(open);
+                        });
+                    }
+                }))
+            }
+        }
+    }
+}
+
+impl OpenConnection {
+    fn new(connections: &Arc<AtomicUsize>) -> OpenConnection {
+        connections.fetch_add(1, Ordering::SeqCst);
+        OpenConnection(Arc::clone(connections))
+    }
+}
+
+impl Drop for OpenConnection {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Reads a single request from the stream and writes the response back
+async fn serve<S>(
+    mut stream: S,
+    client: Option<IpAddr>,
+    router: &Router,
+    read_timeout: Duration,
+    write_timeout: Duration,
+) where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let started = Instant::now();
+    let response = match time::timeout(read_timeout, read_request(&mut stream, router)).await {
+        Ok(Ok(buffer)) => router.process_async(&buffer, client).await,
+        Ok(Err(e)) => router.reject(ParseError::from(e), client, started),
+        Err(_) => router.reject(ParseError::Timeout, client, started),
+    };
+
+    let mut bytes = Vec::new();
+    if let Err(e) = response.write(&mut bytes) {
+        warn!("Unable to write a response: {}", e);
+        return;
+    }
+
+    match time::timeout(write_timeout, write_response(&mut stream, &bytes)).await {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => warn!("Unable to write a response: {}", e),
+        Err(_) => warn!("Unable to write a response: timed out"),
+    }
+}
+
+async fn read_request<S>(stream: &mut S, router: &Router) -> io::Result<Vec<u8>>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut buffer = Vec::new();
+    let mut chunk = [0; READ_BUFFER_SIZE];
+
+    while !is_request_complete(&buffer, &router.limits()) {
+        let read = stream.read(&mut chunk).await?;
+        if read == 0 {
+            break;
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+    }
+
+    Ok(buffer)
+}
+
+async fn write_response<S>(stream: &mut S, bytes: &[u8]) -> io::Result<()>
+where
+    S: AsyncWrite + Unpin,
+{
+    stream.write_all(bytes).await?;
+    stream.shutdown().await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        net::TcpStream,
+        thread,
+    };
+
+    use super::*;
+    use crate::http::{
+        listener::bind_tcp,
+        request::{limits::RequestLimits, matcher::RequestMatcher},
+        response::Response,
+        router::RequestHandler,
+    };
+
+    fn start(handlers: Vec<RequestHandler>, read_timeout: Duration) -> u16 {
+        let listener = bind_tcp("127.0.0.1:0".parse().unwrap(), false).unwrap();
+        let port = match &listener {
+            Listener::Tcp(listener) => listener.local_addr().unwrap().port(),
+            #[cfg(unix)]
+            Listener::Unix(_, _) => unreachable!(),
+        };
+        let router = Arc::new(Router::new(handlers, RequestLimits::default()));
+
+        thread::spawn(move || {
+            let server = AsyncServer::new(2, read_timeout, Duration::from_secs(5)).unwrap();
+            server
+                .run(&[listener], &router, &Lifecycle::default())
+                .unwrap();
+        });
+
+        port
+    }
+
+    fn exchange(port: u16, raw_request: &[u8]) -> String {
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        stream.write_all(raw_request).unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+
+        response
+    }
+
+    #[test]
+    fn async_server_must_serve_async_and_sync_handlers() {
+        let handlers = vec![
+            RequestHandler::from_fn(
+                RequestMatcher::post().url("/async").build(),
+                |request| async move {
+                    time::sleep(Duration::from_millis(10)).await;
+                    Response::builder()
+                        .code(200)
+                        .body(request.body().to_uppercase())
+                        .build()
+                },
+            ),
+            RequestHandler::new(
+                RequestMatcher::get().url("/sync").build(),
+                Box::new(|_| Response::builder().code(200).body("sync").build()),
+            ),
+        ];
+        let port = start(handlers, Duration::from_secs(5));
+
+        let async_response = exchange(
+            port,
+            b"POST /async HTTP/1.1\r\nX-Request-Id: test_id\r\nContent-Length: 9\r\n\r\ntest_body",
+        );
+        let sync_response = exchange(port, b"GET /sync HTTP/1.1\r\n\r\n");
+
+        assert_eq!(
+            "HTTP/1.1 200 OK\r\nX-Request-Id: test_id\r\nContent-Length: 9\r\n\r\nTEST_BODY",
+            async_response,
+            "Async handler must be awaited"
+        );
+        assert!(
+            sync_response.ends_with("\r\n\r\nsync"),
+            "Sync handler must be served by the async server"
+        );
+    }
+
+    #[test]
+    fn async_server_must_respond_with_internal_error_when_async_handler_panics() {
+        let handlers = vec![RequestHandler::from_fn(
+            RequestMatcher::get().url("/panic").build(),
+            |_| async { panic!("handler failure") },
+        )];
+        let port = start(handlers, Duration::from_secs(5));
+
+        let response = exchange(port, b"GET /panic HTTP/1.1\r\n\r\n");
+
+        assert!(
+            response.starts_with("HTTP/1.1 500 Internal Server Error\r\n"),
+            "Panicking async handler must result in 500"
+        );
+    }
+
+    #[test]
+    fn async_server_must_time_out_incomplete_requests() {
+        let port = start(Vec::new(), Duration::from_millis(100));
+
+        let response = exchange(port, b"GET / HTTP/1.1\r\n");
+
+        assert!(
+            response.starts_with("HTTP/1.1 408 Request Timeout\r\n"),
+            "Incomplete request must be answered with 408"
+        );
+    }
+
+    #[test]
+    fn async_server_must_close_listeners_and_drain_connections_once_it_stops_accepting() {
+        let listener = bind_tcp("127.0.0.1:0".parse().unwrap(), false).unwrap();
+        let address = match &listener {
+            Listener::Tcp(listener) => listener.local_addr().unwrap(),
+            #[cfg(unix)]
+            Listener::Unix(_, _) => unreachable!(),
+        };
+        let (started_sender, started) = std::sync::mpsc::channel();
+        let router = Arc::new(Router::new(
+            vec![RequestHandler::from_fn(
+                RequestMatcher::get().url("/slow").build(),
+                move |_| {
+                    let started_sender = started_sender.clone();
+                    async move {
+                        started_sender.send(()).unwrap();
+                        time::sleep(Duration::from_millis(300)).await;
+                        Response::builder().code(200).build()
+                    }
+                },
+            )],
+            RequestLimits::default(),
+        ));
+        let lifecycle = Lifecycle::default();
+        let server_lifecycle = lifecycle.clone();
+        let drained = thread::spawn(move || {
+            let server = AsyncServer::new(2, Duration::from_secs(5), Duration::from_secs(5))?;
+            server.run(&[listener], &router, &server_lifecycle)?;
+            let refused = TcpStream::connect(address).is_err();
+            Ok::<_, io::Error>((refused, server.drain(Duration::from_secs(5))))
+        });
+
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        stream.write_all(b"GET /slow HTTP/1.1\r\n\r\n").unwrap();
+        started.recv().unwrap();
+        lifecycle.stop_accepting();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let (refused, drained) = drained.join().unwrap().unwrap();
+
+        assert!(refused, "Listener must be closed once accepting stops");
+        assert!(drained, "Open connections must be drained");
+        assert!(
+            response.starts_with("HTTP/1.1 200 OK\r\n"),
+            "Request in flight must be answered"
+        );
+    }
+}
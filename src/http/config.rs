@@ -0,0 +1,417 @@
+use std::{
+    ffi::OsString,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use clap::{
+    error::ErrorKind, parser::ValueSource, Arg, ArgAction, ArgMatches, CommandFactory,
+    FromArgMatches,
+};
+use toml::{Table, Value};
+
+use crate::http::server::Config;
+
+/// Prefix of the environment variables overriding the configuration, e.g. `RWS_PORT`
+pub const ENV_PREFIX: &str = "RWS_";
+
+/// Options that only control how the configuration is loaded, they can only be passed on the command line
+const COMMAND_LINE_ONLY: [&str; 2] = ["config", "check_config"];
+
+/// Loads the configuration from the command line arguments, the environment and the config file
+///
+/// Every option can be set in the TOML file named by `--config` by its long name in snake case,
+/// e.g. `max_pool_size = 8`, and overridden by the `RWS_`-prefixed environment variable,
+/// e.g. `RWS_MAX_POOL_SIZE=8`, which is in turn overridden by the command line.
+/// Repeatable options take an array in the file and a comma-separated list in the environment.
+/// Values from every source go through the same validation as the command line
+pub fn load<I, T>(args: I, env: impl Fn(&str) -> Option<String>) -> Result<Config, clap::Error>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<OsString> + Clone,
+{
+    Config::from_arg_matches(&load_matches(args, env)?)
+}
+
+/// Parses the command line, then parses it again with the options it does not set
+/// taken from the environment or the config file
+pub(crate) fn load_matches<I, T>(
+    args: I,
+    env: impl Fn(&str) -> Option<String>,
+) -> Result<ArgMatches, clap::Error>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<OsString> + Clone,
+{
+    let args: Vec<OsString> = args.into_iter().map(Into::into).collect();
+    // Required options may be set by the environment or the config file,
+    // so they are only validated once the sources are layered
+    let matches = Config::command()
+        .ignore_errors(true)
+        .try_get_matches_from(&args)?;
+    let mut command = Config::command();
+
+    let file = match matches.get_one::<PathBuf>("config") {
+        Some(path) => read_file(path)?,
+        None => Table::new(),
+    };
+    if let Some(key) = file.keys().find(|key| {
+        !command
+            .get_arguments()
+            .any(|arg| arg.get_id() == key.as_str() && is_configurable(arg))
+    }) {
+        return Err(error(
+            ErrorKind::UnknownArgument,
+            format!("Unknown option '{}' in the config file", key),
+        ));
+    }
+
+    let mut layered: Vec<OsString> = args.iter().take(1).cloned().collect();
+    for arg in command.get_arguments().filter(|arg| is_configurable(arg)) {
+        let id = arg.get_id().as_str();
+        let Some(long) = arg.get_long() else {
+            continue;
+        };
+        if matches.value_source(id) == Some(ValueSource::CommandLine) {
+            continue;
+        }
+
+        let values = match env(&env_var_name(id)) {
+            Some(value) if is_repeatable(arg) => {
+                value.split(',').map(|v| String::from(v.trim())).collect()
+            }
+            Some(value) => vec![value],
+            None => match file.get(id) {
+                Some(value) => from_toml_value(id, value)?,
+                None => continue,
+            },
+        };
+
+        for value in values {
+            if arg.get_action().takes_values() {
+                layered.push(OsString::from(format!("--{}={}", long, value)));
+            } else if parse_flag(id, &value)? {
+                layered.push(OsString::from(format!("--{}", long)));
+            }
+        }
+    }
+    layered.extend(args.into_iter().skip(1));
+
+    let matches = command.try_get_matches_from_mut(layered)?;
+    validate(&matches)?;
+
+    Ok(matches)
+}
+
+/// Checks the constraints between options the parser can not express
+fn validate(matches: &ArgMatches) -> Result<(), clap::Error> {
+    let min_pool_size = matches.get_one::<usize>("min_pool_size");
+    let max_pool_size = matches.get_one::<usize>("max_pool_size");
+    if !matches.contains_id("pool_size") && min_pool_size > max_pool_size {
+        return Err(error(
+            ErrorKind::ValueValidation,
+            format!(
+                "Min pool size {} is greater than max pool size {}",
+                min_pool_size.unwrap_or(&0),
+                max_pool_size.unwrap_or(&0)
+            ),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Renders the effective configuration as a config file, options without a value are left out
+pub(crate) fn effective_config(matches: &ArgMatches) -> Table {
+    let mut table = Table::new();
+
+    for arg in Config::command()
+        .get_arguments()
+        .filter(|arg| is_configurable(arg))
+    {
+        let id = arg.get_id().as_str();
+        let Some(values) = matches.get_raw(id) else {
+            continue;
+        };
+
+        let mut values: Vec<Value> = values
+            .map(|value| to_toml_value(&value.to_string_lossy()))
+            .collect();
+        let value = if is_repeatable(arg) {
+            Value::Array(values)
+        } else {
+            values.remove(0)
+        };
+        table.insert(String::from(id), value);
+    }
+
+    table
+}
+
+/// The environment variable overriding the option, e.g. `RWS_MAX_POOL_SIZE` for `max_pool_size`
+pub fn env_var_name(id: &str) -> String {
+    format!("{}{}", ENV_PREFIX, id.to_uppercase())
+}
+
+fn read_file(path: &Path) -> Result<Table, clap::Error> {
+    let content = fs::read_to_string(path).map_err(|e| {
+        error(
+            ErrorKind::Io,
+            format!("Unable to read the config file {}: {}", path.display(), e),
+        )
+    })?;
+
+    content.parse::<Table>().map_err(|e| {
+        error(
+            ErrorKind::InvalidValue,
+            format!("Invalid config file {}: {}", path.display(), e),
+        )
+    })
+}
+
+/// Whether the option is a server setting, rather than help or how the configuration is loaded
+fn is_configurable(arg: &Arg) -> bool {
+    let is_setting = matches!(
+        arg.get_action(),
+        ArgAction::Set | ArgAction::Append | ArgAction::SetTrue
+    );
+
+    is_setting && !COMMAND_LINE_ONLY.contains(&arg.get_id().as_str())
+}
+
+fn is_repeatable(arg: &Arg) -> bool {
+    matches!(arg.get_action(), ArgAction::Append)
+}
+
+/// Converts a value of the config file into the command line values of the option
+fn from_toml_value(id: &str, value: &Value) -> Result<Vec<String>, clap::Error> {
+    match value {
+        Value::String(value) => Ok(vec![value.clone()]),
+        Value::Integer(value) => Ok(vec![value.to_string()]),
+        Value::Float(value) => Ok(vec![value.to_string()]),
+        Value::Boolean(value) => Ok(vec![value.to_string()]),
+        Value::Array(values) => values
+            .iter()
+            .map(|value| match value {
+                Value::Array(_) | Value::Table(_) => Err(nested_value_error(id)),
+                value => from_toml_value(id, value).map(|mut values| values.remove(0)),
+            })
+            .collect(),
+        Value::Datetime(_) | Value::Table(_) => Err(nested_value_error(id)),
+    }
+}
+
+fn nested_value_error(id: &str) -> clap::Error {
+    error(
+        ErrorKind::InvalidValue,
+        format!(
+            "Option '{}' in the config file must be a string, number, boolean or array of them",
+            id
+        ),
+    )
+}
+
+fn parse_flag(id: &str, value: &str) -> Result<bool, clap::Error> {
+    value.parse().map_err(|_| {
+        error(
+            ErrorKind::InvalidValue,
+            format!("Option '{}' must be true or false, got '{}'", id, value),
+        )
+    })
+}
+
+/// An error formatted like the command line parsing errors
+fn error(kind: ErrorKind, message: impl std::fmt::Display) -> clap::Error {
+    Config::command().error(kind, message)
+}
+
+/// Types a command line value for the TOML rendering of the configuration
+fn to_toml_value(value: &str) -> Value {
+    if let Ok(value) = value.parse::<i64>() {
+        Value::Integer(value)
+    } else if let Ok(value) = value.parse::<bool>() {
+        Value::Boolean(value)
+    } else {
+        Value::String(String::from(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_config_file(name: &str, content: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "rust_web_server_{}_{}.toml",
+            name,
+            std::process::id()
+        ));
+        fs::write(&path, content).unwrap();
+        path
+    }
+
+    fn no_env(_: &str) -> Option<String> {
+        None
+    }
+
+    #[test]
+    fn load_must_layer_file_env_and_command_line() {
+        let path = write_config_file(
+            "layering",
+            "port = 9000\nmax_pool_size = 8\nqueue_capacity = 16\nlisten = [\"127.0.0.1:9001\"]\n",
+        );
+        let env = |name: &str| match name {
+            "RWS_MAX_POOL_SIZE" => Some(String::from("6")),
+            "RWS_READ_TIMEOUT" => Some(String::from("10")),
+            _ => None,
+        };
+
+        let config = load(
+            [
+                "rws",
+                "--config",
+                path.to_str().unwrap(),
+                "--read-timeout",
+                "5",
+            ],
+            env,
+        )
+        .unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(9000, config.port, "File must override the default");
+        assert_eq!(
+            6, config.max_pool_size,
+            "Environment must override the file"
+        );
+        assert_eq!(
+            5, config.read_timeout,
+            "Command line must override the environment"
+        );
+        assert_eq!(16, config.queue_capacity, "File value must be kept");
+        assert_eq!(
+            vec![String::from("127.0.0.1:9001")],
+            config.listen,
+            "Arrays must set repeatable options"
+        );
+        assert_eq!(
+            30, config.write_timeout,
+            "Unset options must keep the default"
+        );
+    }
+
+    #[test]
+    fn load_must_reject_invalid_file() {
+        let invalid_port = write_config_file("invalid_port", "port = 0\n");
+        let unknown_option = write_config_file("unknown_option", "prot = 8080\n");
+        let load_file = |path: &PathBuf| load(["rws", "--config", path.to_str().unwrap()], no_env);
+
+        let invalid_port_result = load_file(&invalid_port);
+        let unknown_option_result = load_file(&unknown_option);
+        let invalid_env_result = load(["rws"], |name: &str| {
+            (name == "RWS_MAX_POOL_SIZE").then(|| String::from("0"))
+        });
+        fs::remove_file(&invalid_port).unwrap();
+        fs::remove_file(&unknown_option).unwrap();
+
+        assert_eq!(
+            ErrorKind::ValueValidation,
+            invalid_port_result.err().unwrap().kind(),
+            "File values must be validated"
+        );
+        assert_eq!(
+            ErrorKind::UnknownArgument,
+            unknown_option_result.err().unwrap().kind(),
+            "Unknown file options must be rejected"
+        );
+        assert_eq!(
+            ErrorKind::ValueValidation,
+            invalid_env_result.err().unwrap().kind(),
+            "Environment values must be validated"
+        );
+    }
+
+    #[test]
+    fn load_must_validate_pool_sizes() {
+        let fixed = load(["rws", "--pool-size", "3"], no_env).unwrap();
+        let inverted = load(
+            ["rws", "--min-pool-size", "4", "--max-pool-size", "2"],
+            no_env,
+        );
+
+        assert_eq!(Some(3), fixed.pool_size, "Fixed pool size must be kept");
+        assert_eq!(
+            ErrorKind::ValueValidation,
+            inverted.err().unwrap().kind(),
+            "Min pool size above the max pool size must be rejected"
+        );
+    }
+
+    #[cfg(feature = "tls")]
+    #[test]
+    fn load_must_take_required_options_from_file_and_env() {
+        let path = write_config_file("required", "tls_cert = \"cert.pem\"\n");
+        let env = |name: &str| match name {
+            "RWS_TLS_CERT" => Some(String::from("env_cert.pem")),
+            _ => None,
+        };
+
+        let from_file = load(
+            [
+                "rws",
+                "--config",
+                path.to_str().unwrap(),
+                "--tls-key",
+                "key.pem",
+            ],
+            no_env,
+        );
+        let from_env = load(["rws", "--tls-key", "key.pem"], env);
+        let missing = load(["rws", "--tls-key", "key.pem"], no_env);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            Some(PathBuf::from("cert.pem")),
+            from_file.unwrap().tls_cert,
+            "Required option must be taken from the file"
+        );
+        assert_eq!(
+            Some(PathBuf::from("env_cert.pem")),
+            from_env.unwrap().tls_cert,
+            "Required option must be taken from the environment"
+        );
+        assert_eq!(
+            ErrorKind::MissingRequiredArgument,
+            missing.err().unwrap().kind(),
+            "Missing required option must still be rejected"
+        );
+    }
+
+    #[test]
+    fn effective_config_must_load_back_as_same_config() {
+        let matches = load_matches(
+            [
+                "rws",
+                "--port",
+                "9000",
+                "--listen",
+                "127.0.0.1:9001",
+                "--disable-metrics",
+            ],
+            no_env,
+        )
+        .unwrap();
+        let path = write_config_file("effective", &effective_config(&matches).to_string());
+
+        let config = load(["rws", "--config", path.to_str().unwrap()], no_env).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(9000, config.port, "Port must be kept");
+        assert_eq!(
+            vec![String::from("127.0.0.1:9001")],
+            config.listen,
+            "Listen addresses must be kept"
+        );
+        assert!(config.disable_metrics, "Flags must be kept");
+    }
+}
@@ -0,0 +1,70 @@
+use std::io::{self, Cursor, Read, Write};
+
+/// A bidirectional byte stream a request is read from and the response is written to
+///
+/// Implemented for every `Read + Write` type, so sockets, TLS sessions and
+/// in-memory streams are handled the same way
+pub trait Connection: Read + Write {}
+
+impl<T: Read + Write> Connection for T {}
+
+/// An in-memory connection that serves the prepared input and records everything written to it
+#[derive(Debug, Default)]
+pub struct MemoryConnection {
+    input: Cursor<Vec<u8>>,
+    output: Vec<u8>,
+}
+
+impl MemoryConnection {
+    pub fn new(input: impl Into<Vec<u8>>) -> MemoryConnection {
+        MemoryConnection {
+            input: Cursor::new(input.into()),
+            output: Vec::new(),
+        }
+    }
+
+    pub fn output(&self) -> &[u8] {
+        &self.output
+    }
+
+    pub fn into_output(self) -> Vec<u8> {
+        self.output
+    }
+}
+
+impl Read for MemoryConnection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.input.read(buf)
+    }
+}
+
+impl Write for MemoryConnection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.output.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn memory_connection_must_serve_input_and_record_output() {
+        let mut connection = MemoryConnection::new("test_input");
+
+        let mut input = String::new();
+        connection.read_to_string(&mut input).unwrap();
+        connection.write_all(b"test_output").unwrap();
+
+        assert_eq!("test_input", input, "Input must be served");
+        assert_eq!(
+            b"test_output",
+            connection.output(),
+            "Output must be recorded"
+        );
+    }
+}
@@ -0,0 +1,808 @@
+use std::{
+    collections::HashMap,
+    io::{self, ErrorKind, Read, Write},
+    net::IpAddr,
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use log::warn;
+use mio::{event::Source, Events, Interest, Poll, Registry, Token, Waker};
+
+use crate::{
+    concurrent::thread_pool::ThreadPool,
+    http::{
+        connection::MemoryConnection,
+        health::Lifecycle,
+        listener::Listener,
+        request::{error::ParseError, is_request_complete},
+        response::Response,
+        router::{overloaded_response, Router},
+    },
+};
+
+const WAKER: Token = Token(usize::MAX);
+/// How often connections are checked for expired timeouts
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+const READ_BUFFER_SIZE: usize = 4096;
+
+/// A readiness based I/O core that multiplexes all connections over a single thread
+///
+/// Connections are read without blocking until a whole request is buffered,
+/// only then the request is parsed and dispatched on the thread pool.
+/// Responses are sent back to the loop and written without blocking as well
+pub(crate) struct EventLoop {
+    poll: Poll,
+    waker: Arc<Waker>,
+    listeners: Vec<EventListener>,
+    connections: HashMap<Token, EventConnection>,
+    next_token: usize,
+    response_sender: Sender<(Token, Vec<u8>)>,
+    response_receiver: Receiver<(Token, Vec<u8>)>,
+    read_timeout: Duration,
+    write_timeout: Duration,
+}
+
+enum EventListener {
+    Tcp(mio::net::TcpListener),
+    #[cfg(unix)]
+    Unix(mio::net::UnixListener),
+}
+
+enum EventStream {
+    Tcp(mio::net::TcpStream),
+    #[cfg(unix)]
+    Unix(mio::net::UnixStream),
+}
+
+struct EventConnection {
+    stream: EventStream,
+    client: Option<IpAddr>,
+    state: ConnectionState,
+}
+
+enum ConnectionState {
+    Reading {
+        buffer: Vec<u8>,
+        deadline: Instant,
+    },
+    Processing,
+    Writing {
+        response: Vec<u8>,
+        written: usize,
+        deadline: Instant,
+    },
+}
+
+impl EventLoop {
+    pub(crate) fn new(
+        listeners: &[Listener],
+        read_timeout: Duration,
+        write_timeout: Duration,
+    ) -> Result<EventLoop, io::Error> {
+        let poll = Poll::new()?;
+        let waker = Arc::new(Waker::new(poll.registry(), WAKER)?);
+
+        let mut event_listeners = Vec::with_capacity(listeners.len());
+        for (index, listener) in listeners.iter().enumerate() {
+            let mut event_listener = EventListener::from_listener(listener)?;
+            poll.registry()
+                .register(&mut event_listener, Token(index), Interest::READABLE)?;
+            event_listeners.push(event_listener);
+        }
+
+        let (response_sender, response_receiver) = mpsc::channel();
+
+        Ok(EventLoop {
+            poll,
+            waker,
+            next_token: event_listeners.len(),
+            listeners: event_listeners,
+            connections: HashMap::new(),
+            response_sender,
+            response_receiver,
+            read_timeout,
+            write_timeout,
+        })
+    }
+
+    /// Runs the loop until the server stops accepting, then closes the listeners
+    pub(crate) fn run(
+        &mut self,
+        pool: &ThreadPool,
+        router: &Arc<Router>,
+        lifecycle: &Lifecycle,
+    ) -> Result<(), io::Error> {
+        let mut events = Events::with_capacity(1024);
+
+        while lifecycle.is_accepting() {
+            self.turn(&mut events, POLL_INTERVAL, pool, router)?;
+        }
+
+        for mut listener in self.listeners.drain(..) {
+            let _ = self.poll.registry().deregister(&mut listener);
+        }
+
+        Ok(())
+    }
+
+    /// Keeps serving the open connections until they are all closed or the timeout expires
+    ///
+    /// Returns whether all connections have been closed
+    pub(crate) fn drain(
+        &mut self,
+        pool: &ThreadPool,
+        router: &Arc<Router>,
+        timeout: Duration,
+    ) -> Result<bool, io::Error> {
+        let mut events = Events::with_capacity(1024);
+        let deadline = Instant::now() + timeout;
+
+        while !self.connections.is_empty() {
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(false);
+            }
+            self.turn(&mut events, POLL_INTERVAL.min(deadline - now), pool, router)?;
+        }
+
+        Ok(true)
+    }
+
+    /// Handles the events of a single poll and expires connections
+    fn turn(
+        &mut self,
+        events: &mut Events,
+        timeout: Duration,
+        pool: &ThreadPool,
+        router: &Arc<Router>,
+    ) -> Result<(), io::Error> {
+        if let Err(e) = self.poll.poll(events, Some(timeout)) {
+            if e.kind() == ErrorKind::Interrupted {
+                return Ok(());
+            }
+            return Err(e);
+        }
+
+        for event in events.iter() {
+            match event.token() {
+                WAKER => self.receive_responses(),
+                Token(index) if index < self.listeners.len() => self.accept(index),
+                token => {
+                    if event.is_readable() || event.is_read_closed() {
+                        self.read(token, pool, router);
+                    }
+                    if event.is_writable() {
+                        self.write(token);
+                    }
+                }
+            }
+        }
+
+        self.expire_connections(router);
+
+        Ok(())
+    }
+
+    fn accept(&mut self, index: usize) {
+        loop {
+            let (mut stream, client) = match self.listeners[index].accept() {
+                Ok(accepted) => accepted,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return,
+                Err(e) => {
+                    warn!("Unable to accept a connection: {}", e);
+                    return;
+                }
+            };
+
+            let token = Token(self.next_token);
+            self.next_token += 1;
+            if let Err(e) = self
+                .poll
+                .registry()
+                .register(&mut stream, token, Interest::READABLE)
+            {
+                warn!("Unable to register a connection: {}", e);
+                continue;
+            }
+
+            let connection = EventConnection {
+                stream,
+                client,
+                state: ConnectionState::Reading {
+                    buffer: Vec::new(),
+                    deadline: Instant::now() + self.read_timeout,
+                },
+            };
+            self.connections.insert(token, connection);
+        }
+    }
+
+    fn read(&mut self, token: Token, pool: &ThreadPool, router: &Arc<Router>) {
+        let Some(connection) = self.connections.get_mut(&token) else {
+            return;
+        };
+        let ConnectionState::Reading { buffer, .. } = &mut connection.state else {
+            return;
+        };
+
+        let max_size = router.limits().max_request_size();
+        let raw_request = match read_available(&mut connection.stream, buffer, max_size) {
+            Ok(true) if buffer.is_empty() => None,
+            Ok(true) => Some(std::mem::take(buffer)),
+            Ok(false) if is_request_complete(buffer, &router.limits()) => {
+                Some(std::mem::take(buffer))
+            }
+            Ok(false) => return,
+            Err(_) => None,
+        };
+        let Some(raw_request) = raw_request else {
+            self.close(token);
+            return;
+        };
+        connection.state = ConnectionState::Processing;
+
+        let client = connection.client;
+        let router = Arc::clone(router);
+        let mut pending = PendingResponse {
+            token,
+            sender: Some(self.response_sender.clone()),
+            waker: Arc::clone(&self.waker),
+        };
+        // The loop serves every connection, so a full queue is answered with 503 instead of waited on
+        let queued = pool.try_execute(move || {
+            let response = router.process(&mut MemoryConnection::new(raw_request), client);
+            pending.send(response);
+        });
+        if let Err(e) = queued {
+            warn!("Refused a request: {}", e);
+        }
+    }
+
+    fn receive_responses(&mut self) {
+        while let Ok((token, response)) = self.response_receiver.try_recv() {
+            self.respond(token, response);
+        }
+    }
+
+    fn respond(&mut self, token: Token, response: Vec<u8>) {
+        let Some(connection) = self.connections.get_mut(&token) else {
+            return;
+        };
+
+        connection.state = ConnectionState::Writing {
+            response,
+            written: 0,
+            deadline: Instant::now() + self.write_timeout,
+        };
+        let registered =
+            self.poll
+                .registry()
+                .reregister(&mut connection.stream, token, Interest::WRITABLE);
+        if let Err(e) = registered {
+            warn!("Unable to register a connection for writing: {}", e);
+            self.close(token);
+            return;
+        }
+
+        self.write(token);
+    }
+
+    fn write(&mut self, token: Token) {
+        let Some(connection) = self.connections.get_mut(&token) else {
+            return;
+        };
+        let ConnectionState::Writing {
+            response, written, ..
+        } = &mut connection.state
+        else {
+            return;
+        };
+
+        while *written < response.len() {
+            match connection.stream.write(&response[*written..]) {
+                Ok(0) => break,
+                Ok(bytes) => *written += bytes,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(_) => break,
+            }
+        }
+
+        self.close(token);
+    }
+
+    /// Answers connections that have not sent a whole request in time with 408
+    /// and drops connections that do not accept the response in time
+    fn expire_connections(&mut self, router: &Router) {
+        let now = Instant::now();
+        let mut timed_out_reads = Vec::new();
+        let mut timed_out_writes = Vec::new();
+
+        for (token, connection) in &self.connections {
+            match connection.state {
+                ConnectionState::Reading { deadline, .. } if deadline <= now => {
+                    timed_out_reads.push((*token, connection.client, deadline))
+                }
+                ConnectionState::Writing { deadline, .. } if deadline <= now => {
+                    timed_out_writes.push(*token)
+                }
+                _ => {}
+            }
+        }
+
+        for (token, client, deadline) in timed_out_reads {
+            let started = deadline - self.read_timeout;
+            let mut response = Vec::new();
+            if router
+                .reject(ParseError::Timeout, client, started)
+                .write(&mut response)
+                .is_ok()
+            {
+                self.respond(token, response);
+            }
+        }
+        for token in timed_out_writes {
+            self.close(token);
+        }
+    }
+
+    fn close(&mut self, token: Token) {
+        if let Some(mut connection) = self.connections.remove(&token) {
+            let _ = self.poll.registry().deregister(&mut connection.stream);
+        }
+    }
+}
+
+/// Sends the response of a queued request back to the loop
+///
+/// If the job is rejected or dropped by the overload policy before it runs,
+/// 503 is sent instead so the connection does not wait forever
+struct PendingResponse {
+    token: Token,
+    sender: Option<Sender<(Token, Vec<u8>)>>,
+    waker: Arc<Waker>,
+}
+
+impl PendingResponse {
+    fn send(&mut self, response: Response) {
+        let Some(sender) = self.sender.take() else {
+            return;
+        };
+
+        let mut response_bytes = Vec::new();
+        if response.write(&mut response_bytes).is_ok()
+            && sender.send((self.token, response_bytes)).is_ok()
+        {
+            let _ = self.waker.wake();
+        }
+    }
+}
+
+impl Drop for PendingResponse {
+    fn drop(&mut self) {
+        self.send(overloaded_response());
+    }
+}
+
+/// Reads everything available without blocking, returns whether the peer has closed the connection
+///
+/// Stops once the buffer exceeds `max_size`, such a request is complete enough to be rejected
+/// by the parser, so a client can not make the server buffer more than the limits allow
+fn read_available(
+    stream: &mut EventStream,
+    buffer: &mut Vec<u8>,
+    max_size: usize,
+) -> Result<bool, io::Error> {
+    let mut chunk = [0; READ_BUFFER_SIZE];
+
+    while buffer.len() <= max_size {
+        match stream.read(&mut chunk) {
+            Ok(0) => return Ok(true),
+            Ok(read) => buffer.extend_from_slice(&chunk[..read]),
+            Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(false),
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(false)
+}
+
+impl EventListener {
+    fn from_listener(listener: &Listener) -> Result<EventListener, io::Error> {
+        match listener {
+            Listener::Tcp(listener) => {
+                let listener = listener.try_clone()?;
+                listener.set_nonblocking(true)?;
+                Ok(EventListener::Tcp(mio::net::TcpListener::from_std(
+                    listener,
+                )))
+            }
+            #[cfg(unix)]
+            Listener::Unix(listener, _) => {
+                let listener = listener.try_clone()?;
+                listener.set_nonblocking(true)?;
+                Ok(EventListener::Unix(mio::net::UnixListener::from_std(
+                    listener,
+                )))
+            }
+        }
+    }
+
+    /// Accepts a connection along with the IP address of the peer, if it has one
+    fn accept(&self) -> Result<(EventStream, Option<IpAddr>), io::Error> {
+        match self {
+            EventListener::Tcp(listener) => listener
+                .accept()
+                .map(|(stream, address)| (EventStream::Tcp(stream), Some(address.ip()))),
+            #[cfg(unix)]
+            EventListener::Unix(listener) => listener
+                .accept()
+                .map(|(stream, _)| (EventStream::Unix(stream), None)),
+        }
+    }
+}
+
+impl Source for EventListener {
+    fn register(
+        &mut self,
+        registry: &Registry,
+        token: Token,
+        interests: Interest,
+    ) -> io::Result<()> {
+        match self {
+            EventListener::Tcp(listener) => listener.register(registry, token, interests),
+            #[cfg(unix)]
+            EventListener::Unix(listener) => listener.register(registry, token, interests),
+        }
+    }
+
+    fn reregister(
+        &mut self,
+        registry: &Registry,
+        token: Token,
+        interests: Interest,
+    ) -> io::Result<()> {
+        match self {
+            EventListener::Tcp(listener) => listener.reregister(registry, token, interests),
+            #[cfg(unix)]
+            EventListener::Unix(listener) => listener.reregister(registry, token, interests),
+        }
+    }
+
+    fn deregister(&mut self, registry: &Registry) -> io::Result<()> {
+        match self {
+            EventListener::Tcp(listener) => listener.deregister(registry),
+            #[cfg(unix)]
+            EventListener::Unix(listener) => listener.deregister(registry),
+        }
+    }
+}
+
+impl Source for EventStream {
+    fn register(
+        &mut self,
+        registry: &Registry,
+        token: Token,
+        interests: Interest,
+    ) -> io::Result<()> {
+        match self {
+            EventStream::Tcp(stream) => stream.register(registry, token, interests),
+            #[cfg(unix)]
+            EventStream::Unix(stream) => stream.register(registry, token, interests),
+        }
+    }
+
+    fn reregister(
+        &mut self,
+        registry: &Registry,
+        token: Token,
+        interests: Interest,
+    ) -> io::Result<()> {
+        match self {
+            EventStream::Tcp(stream) => stream.reregister(registry, token, interests),
+            #[cfg(unix)]
+            EventStream::Unix(stream) => stream.reregister(registry, token, interests),
+        }
+    }
+
+    fn deregister(&mut self, registry: &Registry) -> io::Result<()> {
+        match self {
+            EventStream::Tcp(stream) => stream.deregister(registry),
+            #[cfg(unix)]
+            EventStream::Unix(stream) => stream.deregister(registry),
+        }
+    }
+}
+
+impl Read for EventStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            EventStream::Tcp(stream) => stream.read(buf),
+            #[cfg(unix)]
+            EventStream::Unix(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for EventStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            EventStream::Tcp(stream) => stream.write(buf),
+            #[cfg(unix)]
+            EventStream::Unix(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            EventStream::Tcp(stream) => stream.flush(),
+            #[cfg(unix)]
+            EventStream::Unix(stream) => stream.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{IpAddr, Ipv4Addr, SocketAddr, TcpStream},
+        thread,
+    };
+
+    use super::*;
+    use crate::{
+        concurrent::scheduler::OverloadPolicy,
+        http::{
+            listener::bind_tcp,
+            request::{limits::RequestLimits, matcher::RequestMatcher},
+            router::RequestHandler,
+        },
+    };
+
+    #[test]
+    fn event_loop_must_serve_requests_while_other_connections_are_idle() {
+        let listener =
+            bind_tcp(SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0), false).unwrap();
+        let address = match &listener {
+            Listener::Tcp(listener) => listener.local_addr().unwrap(),
+            #[cfg(unix)]
+            Listener::Unix(_, _) => unreachable!(),
+        };
+        let router = Arc::new(Router::new(
+            vec![RequestHandler::new(
+                RequestMatcher::get().url("/test").build(),
+                Box::new(|_| Response::builder().code(200).body("test_body").build()),
+            )],
+            RequestLimits::default(),
+        ));
+        let mut event_loop =
+            EventLoop::new(&[listener], Duration::from_secs(5), Duration::from_secs(5)).unwrap();
+        thread::spawn(move || {
+            let pool = ThreadPool::new(1);
+            event_loop.run(&pool, &router, &Lifecycle::default())
+        });
+
+        let mut idle_connections: Vec<TcpStream> = (0..32)
+            .map(|_| {
+                let mut stream = TcpStream::connect(address).unwrap();
+                stream.write_all(b"GET /test HTTP/1.1\r\n").unwrap();
+                stream
+            })
+            .collect();
+
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        stream.write_all(b"GET /test HTTP/1.1\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+
+        assert!(
+            response.starts_with("HTTP/1.1 200 OK\r\n") && response.ends_with("test_body"),
+            "Request must be served while other connections are idle"
+        );
+
+        for stream in &mut idle_connections {
+            stream
+                .set_read_timeout(Some(Duration::from_secs(5)))
+                .unwrap();
+            stream.write_all(b"\r\n").unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            assert!(
+                response.starts_with("HTTP/1.1 200 OK\r\n"),
+                "Every connection must be served"
+            );
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn read_available_must_stop_at_max_size() {
+        let (mut client, server) = mio::net::UnixStream::pair().unwrap();
+        client.write_all(&[b'a'; 64 * 1024]).unwrap();
+        let mut stream = EventStream::Unix(server);
+        let mut buffer = Vec::new();
+
+        let closed = read_available(&mut stream, &mut buffer, 1000).unwrap();
+
+        assert!(!closed, "Connection must not be reported as closed");
+        assert!(
+            buffer.len() > 1000 && buffer.len() <= 1000 + READ_BUFFER_SIZE,
+            "Reading must stop once the buffer exceeds the max size"
+        );
+    }
+
+    #[test]
+    fn event_loop_must_time_out_incomplete_requests() {
+        let listener =
+            bind_tcp(SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0), false).unwrap();
+        let address = match &listener {
+            Listener::Tcp(listener) => listener.local_addr().unwrap(),
+            #[cfg(unix)]
+            Listener::Unix(_, _) => unreachable!(),
+        };
+        let router = Arc::new(Router::new(Vec::new(), RequestLimits::default()));
+        let mut event_loop = EventLoop::new(
+            &[listener],
+            Duration::from_millis(100),
+            Duration::from_secs(5),
+        )
+        .unwrap();
+        thread::spawn(move || {
+            let pool = ThreadPool::new(1);
+            event_loop.run(&pool, &router, &Lifecycle::default())
+        });
+
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        stream.write_all(b"GET /test HTTP/1.1\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+
+        assert!(
+            response.starts_with("HTTP/1.1 408 Request Timeout\r\n"),
+            "Incomplete request must be answered with 408"
+        );
+    }
+
+    /// Sends three slow requests to a loop with a single worker and a single queue slot
+    fn send_to_overloaded_loop(overload_policy: OverloadPolicy) -> Vec<String> {
+        let listener =
+            bind_tcp(SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0), false).unwrap();
+        let address = match &listener {
+            Listener::Tcp(listener) => listener.local_addr().unwrap(),
+            #[cfg(unix)]
+            Listener::Unix(_, _) => unreachable!(),
+        };
+        let router = Arc::new(Router::new(
+            vec![RequestHandler::new(
+                RequestMatcher::get().url("/slow").build(),
+                Box::new(|_| {
+                    thread::sleep(Duration::from_millis(300));
+                    Response::builder().code(200).build()
+                }),
+            )],
+            RequestLimits::default(),
+        ));
+        let mut event_loop =
+            EventLoop::new(&[listener], Duration::from_secs(5), Duration::from_secs(5)).unwrap();
+        thread::spawn(move || {
+            let pool = ThreadPool::builder()
+                .size(1)
+                .queue_capacity(1)
+                .overload_policy(overload_policy)
+                .build();
+            event_loop.run(&pool, &router, &Lifecycle::default())
+        });
+
+        (0..3)
+            .map(|_| {
+                let mut stream = TcpStream::connect(address).unwrap();
+                stream
+                    .set_read_timeout(Some(Duration::from_secs(5)))
+                    .unwrap();
+                stream.write_all(b"GET /slow HTTP/1.1\r\n\r\n").unwrap();
+                stream
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .map(|mut stream| {
+                let mut response = String::new();
+                stream.read_to_string(&mut response).unwrap();
+                response
+            })
+            .collect()
+    }
+
+    #[test]
+    fn event_loop_must_respond_with_service_unavailable_when_queue_is_full() {
+        for overload_policy in [OverloadPolicy::Reject, OverloadPolicy::Block] {
+            let responses = send_to_overloaded_loop(overload_policy);
+
+            assert!(
+                responses.iter().any(|response| {
+                    response.starts_with("HTTP/1.1 503 Service Unavailable\r\n")
+                        && response.contains("Retry-After: 1\r\n")
+                }),
+                "Request exceeding the queue capacity must be answered with 503 under {:?}",
+                overload_policy
+            );
+            assert!(
+                responses
+                    .iter()
+                    .any(|response| response.starts_with("HTTP/1.1 200 OK\r\n")),
+                "Queued requests must still be served under {:?}",
+                overload_policy
+            );
+        }
+    }
+
+    #[test]
+    fn event_loop_must_close_listeners_and_drain_connections_once_it_stops_accepting() {
+        let listener =
+            bind_tcp(SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0), false).unwrap();
+        let address = match &listener {
+            Listener::Tcp(listener) => listener.local_addr().unwrap(),
+            #[cfg(unix)]
+            Listener::Unix(_, _) => unreachable!(),
+        };
+        let (started_sender, started) = mpsc::channel();
+        let (release, released) = mpsc::channel::<()>();
+        let released = std::sync::Mutex::new(released);
+        let router = Arc::new(Router::new(
+            vec![RequestHandler::new(
+                RequestMatcher::get().url("/slow").build(),
+                Box::new(move |_| {
+                    started_sender.send(()).unwrap();
+                    released.lock().unwrap().recv().unwrap();
+                    Response::builder().code(200).build()
+                }),
+            )],
+            RequestLimits::default(),
+        ));
+        let lifecycle = Lifecycle::default();
+        let mut event_loop =
+            EventLoop::new(&[listener], Duration::from_secs(5), Duration::from_secs(5)).unwrap();
+        let event_loop_lifecycle = lifecycle.clone();
+        let drained = thread::spawn(move || {
+            let pool = ThreadPool::new(1);
+            event_loop.run(&pool, &router, &event_loop_lifecycle)?;
+            event_loop.drain(&pool, &router, Duration::from_secs(5))
+        });
+
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        stream.write_all(b"GET /slow HTTP/1.1\r\n\r\n").unwrap();
+        started.recv().unwrap();
+        lifecycle.stop_accepting();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while TcpStream::connect(address).is_ok() && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        let refused = TcpStream::connect(address).is_err();
+        release.send(()).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+
+        assert!(refused, "Listener must be closed once accepting stops");
+        assert!(
+            drained.join().unwrap().unwrap(),
+            "Open connections must be drained"
+        );
+        assert!(
+            response.starts_with("HTTP/1.1 200 OK\r\n"),
+            "Request in flight must be answered"
+        );
+    }
+}
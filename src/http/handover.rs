@@ -0,0 +1,257 @@
+use std::{
+    ffi::OsString,
+    io::{self, Read, Write},
+    os::{
+        fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, RawFd},
+        unix::net::UnixStream,
+    },
+    process::{Child, Command},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Once,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use log::{info, warn};
+use socket2::{SockRef, Type};
+
+use crate::http::{health::Lifecycle, listener::Listener};
+
+/// How often a handover request is checked for
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+/// Environment variable naming the descriptor the new server process reports its readiness on
+const READY_FD_VAR: &str = "RWS_HANDOVER_READY_FD";
+/// How long the new server process may take to start serving
+const READY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Starts a new server process with the listeners on SIGUSR2, then shuts down gracefully
+/// once the new process serves them
+///
+/// The new process inherits the listening sockets through `--fd`, so no connection is refused
+/// while the server restarts, e.g. to run a new binary. If it exits or does not start serving
+/// in time, it is killed and this server keeps serving
+pub(crate) fn hand_over_on_sigusr2(
+    listeners: Vec<Listener>,
+    lifecycle: Lifecycle,
+) -> io::Result<()> {
+    let requested = Arc::new(AtomicBool::new(false));
+    signal_hook::flag::register(signal_hook::consts::SIGUSR2, Arc::clone(&requested))?;
+
+    thread::Builder::new()
+        .name(String::from("handover"))
+        .spawn(move || loop {
+            thread::sleep(POLL_INTERVAL);
+            // Closes the duplicated listeners along with the ones of the server
+            if lifecycle.is_shutting_down() {
+                return;
+            }
+            if !requested.swap(false, Ordering::SeqCst) {
+                continue;
+            }
+
+            let successor = spawn_successor(&listeners).and_then(|(mut child, ready)| {
+                match wait_until_ready(&mut child, ready) {
+                    Ok(()) => Ok(child),
+                    Err(e) => {
+                        let _ = child.kill();
+                        let _ = child.wait();
+                        Err(e)
+                    }
+                }
+            });
+            match successor {
+                Ok(child) => {
+                    info!("Handed the listeners over to process {}", child.id());
+                    lifecycle.hand_over();
+                    return;
+                }
+                Err(e) => warn!("Unable to start a new server process: {}", e),
+            }
+        })?;
+
+    Ok(())
+}
+
+/// Tells the server process that handed its listeners over that this one is serving them
+pub(crate) fn notify_ready() {
+    static NOTIFIED: Once = Once::new();
+
+    NOTIFIED.call_once(|| {
+        let Some(fd) = std::env::var(READY_FD_VAR)
+            .ok()
+            .and_then(|fd| fd.parse::<RawFd>().ok())
+            .filter(|&fd| fd >= 0)
+        else {
+            return;
+        };
+        // SAFETY: the descriptor is only borrowed for the check, which fails without closing it
+        // if it is not a socket
+        let borrowed = unsafe { BorrowedFd::borrow_raw(fd) };
+        if SockRef::from(&borrowed).r#type().ok() != Some(Type::STREAM) {
+            warn!("File descriptor {} is not a readiness socket", fd);
+            return;
+        }
+
+        // SAFETY: the previous process passes the descriptor for this report only
+        let mut ready = unsafe { UnixStream::from_raw_fd(fd) };
+        if let Err(e) = ready.write_all(&[1]) {
+            warn!(
+                "Unable to report readiness to the previous server process: {}",
+                e
+            );
+        }
+    });
+}
+
+/// Waits until the new server process reports that it serves the listeners
+fn wait_until_ready(child: &mut Child, mut ready: UnixStream) -> io::Result<()> {
+    let deadline = Instant::now() + READY_TIMEOUT;
+    ready.set_read_timeout(Some(POLL_INTERVAL))?;
+
+    loop {
+        match ready.read(&mut [0]) {
+            Ok(1) => return Ok(()),
+            Ok(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    format!("process {} has not reported its readiness", child.id()),
+                ));
+            }
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) => {}
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+
+        if let Some(status) = child.try_wait()? {
+            return Err(io::Error::other(format!(
+                "process {} exited with {}",
+                child.id(),
+                status
+            )));
+        }
+        if Instant::now() >= deadline {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!(
+                    "process {} has not started serving in {} seconds",
+                    child.id(),
+                    READY_TIMEOUT.as_secs()
+                ),
+            ));
+        }
+    }
+}
+
+/// Runs the server again with the same arguments, passing it the listening sockets
+/// and a socket to report its readiness on
+fn spawn_successor(listeners: &[Listener]) -> io::Result<(Child, UnixStream)> {
+    let mut args = std::env::args_os();
+    // The path the server has been started from rather than the current executable,
+    // which no longer exists once a new binary has been deployed over it
+    let program = match args.next() {
+        Some(program) => program,
+        None => std::env::current_exe()?.into_os_string(),
+    };
+    let fds: Vec<RawFd> = listeners
+        .iter()
+        .map(|listener| listener.as_fd().as_raw_fd())
+        .collect();
+
+    let (ready, successor_ready) = UnixStream::pair()?;
+
+    // The successor end is closed once the child has been spawned, so the descriptor
+    // is only left open in the child
+    SockRef::from(&successor_ready).set_cloexec(false)?;
+    for listener in listeners {
+        SockRef::from(listener).set_cloexec(false)?;
+    }
+    let child = Command::new(program)
+        .args(successor_args(args, &fds))
+        .env_remove("LISTEN_PID")
+        .env_remove("LISTEN_FDS")
+        .env(READY_FD_VAR, successor_ready.as_raw_fd().to_string())
+        .spawn();
+    for listener in listeners {
+        let _ = SockRef::from(listener).set_cloexec(true);
+    }
+
+    Ok((child?, ready))
+}
+
+/// Replaces the `--fd` options of the arguments with the descriptors of the listeners
+fn successor_args(args: impl IntoIterator<Item = OsString>, fds: &[RawFd]) -> Vec<OsString> {
+    let mut successor_args = Vec::new();
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        if arg == "--fd" {
+            args.next();
+        } else if !arg.to_string_lossy().starts_with("--fd=") {
+            successor_args.push(arg);
+        }
+    }
+    successor_args.extend(fds.iter().map(|fd| OsString::from(format!("--fd={}", fd))));
+
+    successor_args
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn successor_args_must_replace_inherited_descriptors() {
+        let args = [
+            "--port",
+            "8080",
+            "--fd",
+            "3",
+            "--fd=4",
+            "--config",
+            "server.toml",
+        ]
+        .map(OsString::from);
+
+        let successor_args = successor_args(args, &[5, 6]);
+
+        assert_eq!(
+            [
+                "--port",
+                "8080",
+                "--config",
+                "server.toml",
+                "--fd=5",
+                "--fd=6"
+            ]
+            .map(OsString::from),
+            successor_args.as_slice(),
+            "Only the descriptors of the listeners must be passed"
+        );
+    }
+
+    #[test]
+    fn wait_until_ready_must_wait_for_the_report_of_the_successor() {
+        let (ready, _successor_ready) = UnixStream::pair().unwrap();
+        let mut exiting = Command::new("sh").args(["-c", "exit 3"]).spawn().unwrap();
+        let exited = wait_until_ready(&mut exiting, ready);
+
+        let (ready, mut successor_ready) = UnixStream::pair().unwrap();
+        let mut serving = Command::new("sleep").arg("5").spawn().unwrap();
+        successor_ready.write_all(&[1]).unwrap();
+        let reported = wait_until_ready(&mut serving, ready);
+        serving.kill().unwrap();
+        serving.wait().unwrap();
+
+        assert!(exited.is_err(), "Exited successor must not take over");
+        assert!(
+            reported.is_ok(),
+            "Successor reporting readiness must take over"
+        );
+    }
+}
@@ -0,0 +1,283 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{
+    concurrent::thread_pool::PoolHandle,
+    http::{request::matcher::RequestMatcher, response::Response, router::RequestHandler},
+    logging::json::JsonObject,
+};
+
+/// A health check, it returns the reason the application is not ready as the error
+pub type HealthCheckFn = Box<dyn Fn() -> Result<(), String> + Send + Sync + 'static>;
+
+/// Whether the server is shutting down, shared by the server and its readiness check
+#[derive(Clone, Default)]
+pub struct Lifecycle {
+    shutting_down: Arc<AtomicBool>,
+    /// Set once the shutdown delay has passed, the listeners are closed then
+    stopped_accepting: Arc<AtomicBool>,
+    /// Set once the listeners have been handed over to a new server process
+    handed_over: Arc<AtomicBool>,
+}
+
+/// Named checks whose results are aggregated into the readiness report
+#[derive(Default)]
+pub struct HealthChecks {
+    checks: Vec<(String, HealthCheckFn)>,
+}
+
+/// Results of every health check
+pub struct HealthReport {
+    results: Vec<(String, Result<(), String>)>,
+}
+
+/// How often the shutdown and the remaining work are checked for while draining
+pub(crate) const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+impl Lifecycle {
+    /// Marks the server as shutting down, from then on it reports not being ready
+    pub fn begin_shutdown(&self) {
+        self.shutting_down.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutting_down.load(Ordering::SeqCst)
+    }
+
+    /// Tells the server to stop accepting connections and close its listeners
+    pub(crate) fn stop_accepting(&self) {
+        self.stopped_accepting.store(true, Ordering::SeqCst);
+    }
+
+    pub(crate) fn is_accepting(&self) -> bool {
+        !self.stopped_accepting.load(Ordering::SeqCst)
+    }
+
+    /// Begins the shutdown after the listeners have been handed over to a new server process,
+    /// which keeps using their socket files
+    pub(crate) fn hand_over(&self) {
+        self.handed_over.store(true, Ordering::SeqCst);
+        self.begin_shutdown();
+    }
+
+    pub(crate) fn is_handed_over(&self) -> bool {
+        self.handed_over.load(Ordering::SeqCst)
+    }
+
+    /// Begins the shutdown once the process receives SIGTERM
+    #[cfg(unix)]
+    pub(crate) fn shut_down_on_sigterm(&self) -> std::io::Result<()> {
+        signal_hook::flag::register(
+            signal_hook::consts::SIGTERM,
+            Arc::clone(&self.shutting_down),
+        )?;
+
+        Ok(())
+    }
+
+    /// Blocks until the shutdown begins
+    pub(crate) fn wait_for_shutdown(&self) {
+        while !self.is_shutting_down() {
+            std::thread::sleep(DRAIN_POLL_INTERVAL);
+        }
+    }
+
+    /// A check that fails once the shutdown has begun
+    pub(crate) fn check(&self) -> HealthCheckFn {
+        let lifecycle = self.clone();
+
+        Box::new(move || {
+            if lifecycle.is_shutting_down() {
+                Err(String::from("Server is shutting down"))
+            } else {
+                Ok(())
+            }
+        })
+    }
+}
+
+impl HealthChecks {
+    pub fn new() -> HealthChecks {
+        HealthChecks::default()
+    }
+
+    pub fn add(&mut self, name: impl Into<String>, check: HealthCheckFn) {
+        self.checks.push((name.into(), check));
+    }
+
+    /// Moves the checks of `other` after the checks of this one
+    pub(crate) fn append(&mut self, mut other: HealthChecks) {
+        self.checks.append(&mut other.checks);
+    }
+
+    pub fn run(&self) -> HealthReport {
+        HealthReport {
+            results: self
+                .checks
+                .iter()
+                .map(|(name, check)| (name.clone(), check()))
+                .collect(),
+        }
+    }
+}
+
+impl HealthReport {
+    pub fn is_healthy(&self) -> bool {
+        self.results.iter().all(|(_, result)| result.is_ok())
+    }
+
+    /// Formats the report as `{"status":"fail","checks":{"<name>":{"status":"fail","message":"<reason>"}}}`
+    pub fn to_json(&self) -> String {
+        let checks = self
+            .results
+            .iter()
+            .fold(JsonObject::new(), |checks, (name, result)| {
+                let check = match result {
+                    Ok(()) => JsonObject::new().string("status", "pass"),
+                    Err(reason) => JsonObject::new()
+                        .string("status", "fail")
+                        .string("message", reason),
+                };
+                checks.object(name, check)
+            });
+
+        JsonObject::new()
+            .string("status", status(self.is_healthy()))
+            .object("checks", checks)
+            .build()
+    }
+}
+
+/// A check that fails while more jobs than the threshold wait for a free pool worker
+pub(crate) fn queue_check(pool: PoolHandle, threshold: usize) -> HealthCheckFn {
+    Box::new(move || {
+        let queued = pool.stats().queued;
+        if queued > threshold {
+            Err(format!(
+                "{} queued jobs exceed the threshold of {}",
+                queued, threshold
+            ))
+        } else {
+            Ok(())
+        }
+    })
+}
+
+/// Waits until the pool has neither running nor queued jobs, or the timeout expires
+///
+/// Returns whether the pool has been drained
+pub(crate) fn drain(pool: &PoolHandle, timeout: Duration) -> bool {
+    let deadline = Instant::now() + timeout;
+
+    loop {
+        let stats = pool.stats();
+        if stats.active == 0 && stats.queued == 0 {
+            return true;
+        }
+        if Instant::now() >= deadline {
+            return false;
+        }
+        std::thread::sleep(DRAIN_POLL_INTERVAL);
+    }
+}
+
+/// Answers liveness probes, the server is alive as long as it can answer at all
+pub(crate) fn health_handler(path: String) -> RequestHandler {
+    RequestHandler::new(
+        RequestMatcher::get().url(path).build(),
+        Box::new(|_| json_response(true, JsonObject::new().string("status", "pass").build())),
+    )
+}
+
+/// Answers readiness probes with the report of every check, with 503 if any of them fails
+pub(crate) fn readiness_handler(path: String, checks: HealthChecks) -> RequestHandler {
+    RequestHandler::new(
+        RequestMatcher::get().url(path).build(),
+        Box::new(move |_| {
+            let report = checks.run();
+            json_response(report.is_healthy(), report.to_json())
+        }),
+    )
+}
+
+fn json_response(healthy: bool, body: String) -> Response {
+    Response::builder()
+        .code(if healthy { 200 } else { 503 })
+        .add_header("Content-Type", "application/json")
+        .add_header("Cache-Control", "no-store")
+        .body(body)
+        .build()
+}
+
+fn status(healthy: bool) -> &'static str {
+    if healthy {
+        "pass"
+    } else {
+        "fail"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::concurrent::thread_pool::ThreadPool;
+
+    #[test]
+    fn health_report_must_aggregate_checks() {
+        let lifecycle = Lifecycle::default();
+        let mut checks = HealthChecks::new();
+        checks.add("shutdown", lifecycle.check());
+        checks.add("database", Box::new(|| Ok(())));
+
+        let ready = checks.run();
+        lifecycle.begin_shutdown();
+        let shutting_down = checks.run();
+
+        assert!(ready.is_healthy(), "Passing checks must be healthy");
+        assert_eq!(
+            r#"{"status":"pass","checks":{"shutdown":{"status":"pass"},"database":{"status":"pass"}}}"#,
+            ready.to_json(),
+            "Report must contain every check"
+        );
+        assert!(
+            !shutting_down.is_healthy(),
+            "Shutting down server must not be ready"
+        );
+        assert_eq!(
+            r#"{"status":"fail","checks":{"shutdown":{"status":"fail","message":"Server is shutting down"},"database":{"status":"pass"}}}"#,
+            shutting_down.to_json(),
+            "Report must contain the reason of the failed check"
+        );
+    }
+
+    #[test]
+    fn queue_check_must_fail_above_threshold() {
+        let pool = ThreadPool::new(1);
+        let (started_sender, started) = std::sync::mpsc::channel();
+        let (release, released) = std::sync::mpsc::channel::<()>();
+        pool.execute(move || {
+            started_sender.send(()).unwrap();
+            released.recv().unwrap();
+        })
+        .unwrap();
+        started.recv().unwrap();
+        pool.execute(|| {}).unwrap();
+        pool.execute(|| {}).unwrap();
+
+        let below = queue_check(PoolHandle::clone(&pool), 2)();
+        let above = queue_check(PoolHandle::clone(&pool), 1)();
+        release.send(()).unwrap();
+
+        assert!(below.is_ok(), "Queue at the threshold must pass");
+        assert!(above.is_err(), "Queue above the threshold must fail");
+        assert!(
+            drain(&pool, Duration::from_secs(5)),
+            "Pool must be drained once the jobs are done"
+        );
+    }
+}
@@ -0,0 +1,528 @@
+use std::{
+    fmt::Display,
+    io::{self, Read, Write},
+    net::{IpAddr, SocketAddr, TcpListener, TcpStream},
+    time::Duration,
+};
+#[cfg(unix)]
+use std::{
+    fs::{self, Permissions},
+    os::unix::{
+        fs::{FileTypeExt, PermissionsExt},
+        io::{AsFd, BorrowedFd, FromRawFd, RawFd},
+        net::{UnixListener, UnixStream},
+    },
+    path::{Path, PathBuf},
+};
+
+use log::warn;
+use mio::{Events, Interest, Poll, Token};
+#[cfg(unix)]
+use socket2::SockRef;
+use socket2::{Domain, Protocol, Socket, Type};
+
+/// A listening socket the server accepts connections from
+pub enum Listener {
+    Tcp(TcpListener),
+    /// The socket file is removed once the listener is dropped, unless the socket is inherited
+    #[cfg(unix)]
+    Unix(UnixListener, Option<PathBuf>),
+}
+
+/// Accepts connections from all listeners on the calling thread
+///
+/// Waits for connections with a timeout, so the caller can stop accepting in between
+pub struct Acceptor {
+    poll: Poll,
+    events: Events,
+    listeners: Vec<PolledListener>,
+}
+
+enum PolledListener {
+    Tcp(mio::net::TcpListener),
+    #[cfg(unix)]
+    Unix(mio::net::UnixListener),
+}
+
+/// An accepted connection
+pub enum Stream {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+/// First descriptor passed by systemd socket activation
+#[cfg(unix)]
+const SD_LISTEN_FDS_START: RawFd = 3;
+
+#[cfg(unix)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnixSocketConfig {
+    pub path: PathBuf,
+    /// Permissions of the socket file, e.g. `0o660`
+    pub mode: u32,
+}
+
+impl Listener {
+    pub fn accept(&self) -> Result<Stream, io::Error> {
+        match self {
+            Listener::Tcp(listener) => listener.accept().map(|(stream, _)| Stream::Tcp(stream)),
+            #[cfg(unix)]
+            Listener::Unix(listener, _) => {
+                listener.accept().map(|(stream, _)| Stream::Unix(stream))
+            }
+        }
+    }
+
+    /// Leaves the socket file in place once the listener is dropped,
+    /// e.g. because another process keeps listening on it
+    pub fn keep_socket_file(&mut self) {
+        #[cfg(unix)]
+        if let Listener::Unix(_, path) = self {
+            *path = None;
+        }
+    }
+
+    /// A listener on the same socket, dropping it leaves the socket file in place
+    pub fn try_clone(&self) -> Result<Listener, io::Error> {
+        match self {
+            Listener::Tcp(listener) => listener.try_clone().map(Listener::Tcp),
+            #[cfg(unix)]
+            Listener::Unix(listener, _) => listener
+                .try_clone()
+                .map(|listener| Listener::Unix(listener, None)),
+        }
+    }
+}
+
+impl Display for Listener {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Listener::Tcp(listener) => match listener.local_addr() {
+                Ok(address) => write!(f, "{}", address),
+                Err(_) => write!(f, "unknown address"),
+            },
+            #[cfg(unix)]
+            Listener::Unix(listener, _) => match listener.local_addr() {
+                Ok(address) => match address.as_pathname() {
+                    Some(path) => write!(f, "unix:{}", path.display()),
+                    None => write!(f, "unix:unnamed"),
+                },
+                Err(_) => write!(f, "unknown address"),
+            },
+        }
+    }
+}
+
+impl Acceptor {
+    /// Registers duplicates of the listeners, dropping the acceptor closes them
+    pub fn new(listeners: &[Listener]) -> Result<Acceptor, io::Error> {
+        let poll = Poll::new()?;
+
+        let mut polled_listeners = Vec::with_capacity(listeners.len());
+        for (index, listener) in listeners.iter().enumerate() {
+            let mut polled_listener = match listener {
+                Listener::Tcp(listener) => {
+                    let listener = listener.try_clone()?;
+                    listener.set_nonblocking(true)?;
+                    PolledListener::Tcp(mio::net::TcpListener::from_std(listener))
+                }
+                #[cfg(unix)]
+                Listener::Unix(listener, _) => {
+                    let listener = listener.try_clone()?;
+                    listener.set_nonblocking(true)?;
+                    PolledListener::Unix(mio::net::UnixListener::from_std(listener))
+                }
+            };
+            let registry = poll.registry();
+            match &mut polled_listener {
+                PolledListener::Tcp(listener) => {
+                    registry.register(listener, Token(index), Interest::READABLE)?
+                }
+                #[cfg(unix)]
+                PolledListener::Unix(listener) => {
+                    registry.register(listener, Token(index), Interest::READABLE)?
+                }
+            }
+            polled_listeners.push(polled_listener);
+        }
+
+        Ok(Acceptor {
+            poll,
+            events: Events::with_capacity(polled_listeners.len().max(1)),
+            listeners: polled_listeners,
+        })
+    }
+
+    /// Waits up to the timeout for connections and passes every accepted one on
+    pub fn accept(
+        &mut self,
+        timeout: Duration,
+        mut accepted: impl FnMut(Stream),
+    ) -> Result<(), io::Error> {
+        match self.poll.poll(&mut self.events, Some(timeout)) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => return Ok(()),
+            Err(e) => return Err(e),
+        }
+
+        for event in self.events.iter() {
+            let listener = &self.listeners[event.token().0];
+            loop {
+                match listener.accept() {
+                    Ok(stream) => accepted(stream),
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                    Err(e) => {
+                        warn!("Unable to accept a connection: {}", e);
+                        break;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl PolledListener {
+    /// Accepts a connection as a blocking stream
+    fn accept(&self) -> Result<Stream, io::Error> {
+        match self {
+            PolledListener::Tcp(listener) => {
+                let stream = TcpStream::from(listener.accept()?.0);
+                stream.set_nonblocking(false)?;
+                Ok(Stream::Tcp(stream))
+            }
+            #[cfg(unix)]
+            PolledListener::Unix(listener) => {
+                let stream = UnixStream::from(listener.accept()?.0);
+                stream.set_nonblocking(false)?;
+                Ok(Stream::Unix(stream))
+            }
+        }
+    }
+}
+
+impl Stream {
+    /// The IP address of the peer, Unix domain socket peers have none
+    pub fn peer_ip(&self) -> Option<IpAddr> {
+        match self {
+            Stream::Tcp(stream) => stream.peer_addr().ok().map(|address| address.ip()),
+            #[cfg(unix)]
+            Stream::Unix(_) => None,
+        }
+    }
+
+    pub fn set_timeouts(&self, read_timeout: Duration, write_timeout: Duration) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => {
+                stream.set_read_timeout(Some(read_timeout))?;
+                stream.set_write_timeout(Some(write_timeout))
+            }
+            #[cfg(unix)]
+            Stream::Unix(stream) => {
+                stream.set_read_timeout(Some(read_timeout))?;
+                stream.set_write_timeout(Some(write_timeout))
+            }
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.read(buf),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.write(buf),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.flush(),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.flush(),
+        }
+    }
+}
+
+/// Binds a TCP listener to the address
+///
+/// An IPv6 listener accepts IPv4 connections as well unless `only_v6` is set,
+/// which is required to bind an IPv4 listener to the same port
+pub fn bind_tcp(address: SocketAddr, only_v6: bool) -> Result<Listener, io::Error> {
+    let socket = Socket::new(
+        Domain::for_address(address),
+        Type::STREAM,
+        Some(Protocol::TCP),
+    )?;
+    if address.is_ipv6() {
+        socket.set_only_v6(only_v6)?;
+    }
+    socket.set_reuse_address(true)?;
+    socket.bind(&address.into())?;
+    socket.listen(128)?;
+
+    Ok(Listener::Tcp(socket.into()))
+}
+
+/// Binds a Unix domain socket listener to the path
+///
+/// A socket file left behind by a previous run is removed,
+/// unless another process is still accepting connections on it
+#[cfg(unix)]
+pub fn bind_unix(config: &UnixSocketConfig) -> Result<Listener, io::Error> {
+    remove_stale_socket(&config.path)?;
+
+    let listener = UnixListener::bind(&config.path)?;
+    fs::set_permissions(&config.path, Permissions::from_mode(config.mode))?;
+
+    Ok(Listener::Unix(listener, Some(config.path.clone())))
+}
+
+/// Takes over a listening TCP or Unix domain socket inherited from the parent process
+///
+/// The descriptor is checked to be a listening stream socket before it is taken over,
+/// so it is left open if it is rejected
+///
+/// # Safety
+///
+/// The descriptor must not be owned by anything else in this process, as it is closed
+/// once the listener is dropped
+#[cfg(unix)]
+pub unsafe fn inherit(fd: RawFd) -> Result<Listener, io::Error> {
+    let not_a_listener = || {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("File descriptor {} is not a listening socket", fd),
+        )
+    };
+    if fd < 0 {
+        return Err(not_a_listener());
+    }
+    // SAFETY: the descriptor is only borrowed for the checks, an invalid or non-socket
+    // descriptor makes them fail without being closed
+    let borrowed = unsafe { BorrowedFd::borrow_raw(fd) };
+    let socket = SockRef::from(&borrowed);
+    let address = socket.local_addr().map_err(|_| not_a_listener())?;
+    if socket.r#type()? != Type::STREAM || !(address.is_unix() || address.as_socket().is_some()) {
+        return Err(not_a_listener());
+    }
+    #[cfg(target_os = "linux")]
+    if !socket.is_listener()? {
+        return Err(not_a_listener());
+    }
+
+    // SAFETY: the caller guarantees nothing else owns the descriptor
+    let socket = unsafe { Socket::from_raw_fd(fd) };
+    socket.set_cloexec(true)?;
+    if address.is_unix() {
+        Ok(Listener::Unix(socket.into(), None))
+    } else {
+        Ok(Listener::Tcp(socket.into()))
+    }
+}
+
+/// Descriptors of the sockets passed by systemd socket activation, see sd_listen_fds(3)
+///
+/// Empty unless `LISTEN_PID` is the ID of this process
+#[cfg(unix)]
+pub fn systemd_listen_fds() -> Vec<RawFd> {
+    listen_fds(
+        std::env::var("LISTEN_PID").ok().as_deref(),
+        std::env::var("LISTEN_FDS").ok().as_deref(),
+        std::process::id(),
+    )
+}
+
+#[cfg(unix)]
+fn listen_fds(listen_pid: Option<&str>, listen_fds: Option<&str>, pid: u32) -> Vec<RawFd> {
+    if listen_pid.and_then(|listen_pid| listen_pid.parse().ok()) != Some(pid) {
+        return Vec::new();
+    }
+    let count: RawFd = listen_fds
+        .and_then(|listen_fds| listen_fds.parse().ok())
+        .unwrap_or(0);
+
+    (SD_LISTEN_FDS_START..SD_LISTEN_FDS_START + count).collect()
+}
+
+#[cfg(unix)]
+impl AsFd for Listener {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        match self {
+            Listener::Tcp(listener) => listener.as_fd(),
+            Listener::Unix(listener, _) => listener.as_fd(),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn remove_stale_socket(path: &Path) -> Result<(), io::Error> {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+
+    if !metadata.file_type().is_socket() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} exists and is not a socket", path.display()),
+        ));
+    }
+    if UnixStream::connect(path).is_ok() {
+        return Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            format!("{} is in use by another process", path.display()),
+        ));
+    }
+
+    fs::remove_file(path)
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if let Listener::Unix(_, Some(path)) = self {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+    use super::*;
+
+    #[test]
+    fn bind_tcp_must_allow_ipv4_and_ipv6_listeners_on_the_same_port() {
+        let ipv4_listener =
+            bind_tcp(SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0), false).unwrap();
+        let port = match &ipv4_listener {
+            Listener::Tcp(listener) => listener.local_addr().unwrap().port(),
+            #[cfg(unix)]
+            Listener::Unix(_, _) => unreachable!(),
+        };
+
+        let ipv6_listener = bind_tcp(
+            SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), port),
+            true,
+        );
+
+        assert!(
+            ipv6_listener.is_ok(),
+            "IPv6 listener must be bound to the same port"
+        );
+        assert!(
+            TcpStream::connect((Ipv6Addr::LOCALHOST, port)).is_ok(),
+            "IPv6 listener must accept connections"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn bind_unix_must_set_permissions_and_replace_stale_socket() {
+        let path =
+            std::env::temp_dir().join(format!("rust_web_server_{}.sock", std::process::id()));
+        let config = UnixSocketConfig {
+            path: path.clone(),
+            mode: 0o600,
+        };
+        drop(UnixListener::bind(&path).unwrap());
+
+        let listener = bind_unix(&config);
+
+        assert!(listener.is_ok(), "Stale socket must be replaced");
+        let mode = fs::metadata(&path).unwrap().permissions().mode() & 0o777;
+        assert_eq!(0o600, mode, "Socket permissions must be 0o600");
+        assert!(
+            bind_unix(&config).is_err_and(|e| e.kind() == io::ErrorKind::AddrInUse),
+            "Socket in use must not be replaced"
+        );
+
+        drop(listener);
+        assert!(!path.exists(), "Socket file must be removed on drop");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn listen_fds_must_only_be_taken_for_this_process() {
+        assert_eq!(
+            vec![3, 4],
+            listen_fds(Some("42"), Some("2"), 42),
+            "Sockets passed to this process must be taken"
+        );
+        assert!(
+            listen_fds(Some("41"), Some("2"), 42).is_empty(),
+            "Sockets passed to another process must be ignored"
+        );
+        assert!(
+            listen_fds(None, None, 42).is_empty(),
+            "No sockets must be taken without socket activation"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn inherit_must_take_over_listening_sockets() {
+        use std::os::fd::{AsRawFd, IntoRawFd};
+
+        let tcp_listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let address = tcp_listener.local_addr().unwrap();
+        let path = std::env::temp_dir().join(format!(
+            "rust_web_server_inherited_{}.sock",
+            std::process::id()
+        ));
+        let _ = fs::remove_file(&path);
+        let unix_listener = UnixListener::bind(&path).unwrap();
+
+        let file = fs::File::open("/dev/null").unwrap();
+
+        let tcp_listener = unsafe { inherit(tcp_listener.into_raw_fd()) }.unwrap();
+        let unix_listener = unsafe { inherit(unix_listener.into_raw_fd()) }.unwrap();
+        let not_a_socket = unsafe { inherit(file.as_raw_fd()) };
+
+        assert_eq!(
+            address.to_string(),
+            tcp_listener.to_string(),
+            "TCP socket must be inherited"
+        );
+        assert!(
+            TcpStream::connect(address).is_ok() && tcp_listener.accept().is_ok(),
+            "Inherited TCP socket must accept connections"
+        );
+        assert_eq!(
+            format!("unix:{}", path.display()),
+            unix_listener.to_string(),
+            "Unix domain socket must be inherited"
+        );
+        drop(unix_listener);
+        assert!(path.exists(), "Inherited socket file must be kept on drop");
+        assert!(not_a_socket.is_err(), "Other files must be rejected");
+        assert!(
+            file.metadata().is_ok(),
+            "Rejected file must be left open for its owner"
+        );
+        #[cfg(target_os = "linux")]
+        {
+            let unbound = Socket::new(Domain::IPV4, Type::STREAM, None).unwrap();
+            assert!(
+                unsafe { inherit(unbound.as_raw_fd()) }.is_err(),
+                "Sockets not listening must be rejected"
+            );
+        }
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,196 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+    time::Instant,
+};
+
+use crate::{
+    concurrent::thread_pool::PoolHandle,
+    http::{request::RequestMethod, response::Response},
+    metrics::{Counter, Gauge, Histogram, Registry, DEFAULT_BUCKETS},
+};
+
+/// Route label of requests no handler matches, so unknown URLs do not create new series
+const UNMATCHED_ROUTE: &str = "unmatched";
+
+const METHODS: [RequestMethod; 4] = [
+    RequestMethod::GET,
+    RequestMethod::POST,
+    RequestMethod::PUT,
+    RequestMethod::DELETE,
+];
+
+/// Records the requests the router handles into a registry
+pub(crate) struct HttpMetrics {
+    registry: Arc<Registry>,
+    in_flight: Arc<Gauge>,
+    /// Metrics of the requests matching each handler, in the order of the handlers
+    routes: Vec<RouteMetrics>,
+    /// Metrics of the requests no handler matches, by method
+    unmatched: Vec<RouteMetrics>,
+}
+
+/// The metrics of the requests with one method and route, looked up once
+/// so recording a request neither locks the registry nor allocates labels
+struct RouteMetrics {
+    registry: Arc<Registry>,
+    method: RequestMethod,
+    route: String,
+    duration: Arc<Histogram>,
+    /// Request counters by response status, registered on first use
+    requests: RwLock<HashMap<u16, Arc<Counter>>>,
+}
+
+/// A request being handled, it stops counting as in flight once dropped
+pub(crate) struct InFlightRequest<'a> {
+    in_flight: &'a Gauge,
+    route: &'a RouteMetrics,
+    started: Instant,
+}
+
+impl HttpMetrics {
+    /// `routes` are the method and URL of every handler, in the order they are matched in
+    pub(crate) fn new<'a>(
+        registry: Arc<Registry>,
+        routes: impl IntoIterator<Item = (RequestMethod, &'a str)>,
+    ) -> HttpMetrics {
+        let in_flight = registry.gauge("http_requests_in_flight", "Requests being handled", &[]);
+
+        HttpMetrics {
+            routes: routes
+                .into_iter()
+                .map(|(method, route)| RouteMetrics::new(&registry, method, route))
+                .collect(),
+            unmatched: METHODS
+                .iter()
+                .map(|method| RouteMetrics::new(&registry, *method, UNMATCHED_ROUTE))
+                .collect(),
+            registry,
+            in_flight,
+        }
+    }
+
+    /// Starts tracking a request, `route` is the index of the matching handler if there is one
+    pub(crate) fn start(
+        &self,
+        method: &RequestMethod,
+        route: Option<usize>,
+    ) -> InFlightRequest<'_> {
+        self.in_flight.inc();
+        let route = match route {
+            Some(index) => &self.routes[index],
+            None => self
+                .unmatched
+                .iter()
+                .find(|route| route.method == *method)
+                .expect("Every method must have unmatched route metrics"),
+        };
+
+        InFlightRequest {
+            in_flight: &self.in_flight,
+            route,
+            started: Instant::now(),
+        }
+    }
+
+    /// Counts a request that could not be read, by the status it is answered with
+    pub(crate) fn parse_error(&self, status: u16) {
+        self.registry
+            .counter(
+                "http_request_parse_errors_total",
+                "Requests that could not be read or parsed",
+                &[("status", &status.to_string())],
+            )
+            .inc();
+    }
+}
+
+impl RouteMetrics {
+    fn new(registry: &Arc<Registry>, method: RequestMethod, route: &str) -> RouteMetrics {
+        let duration = registry.histogram(
+            "http_request_duration_seconds",
+            "Time spent handling requests",
+            &[("method", &method.to_string()), ("route", route)],
+            &DEFAULT_BUCKETS,
+        );
+
+        RouteMetrics {
+            registry: Arc::clone(registry),
+            method,
+            route: String::from(route),
+            duration,
+            requests: RwLock::new(HashMap::new()),
+        }
+    }
+
+    fn requests(&self, status: u16) -> Arc<Counter> {
+        if let Some(counter) = self.requests.read().unwrap().get(&status) {
+            return Arc::clone(counter);
+        }
+
+        let counter = self.registry.counter(
+            "http_requests_total",
+            "Handled requests",
+            &[
+                ("method", &self.method.to_string()),
+                ("route", &self.route),
+                ("status", &status.to_string()),
+            ],
+        );
+        self.requests
+            .write()
+            .unwrap()
+            .insert(status, Arc::clone(&counter));
+
+        counter
+    }
+}
+
+impl InFlightRequest<'_> {
+    pub(crate) fn finish(self, response: &Response) {
+        self.route.requests(response.code()).inc();
+        self.route
+            .duration
+            .observe(self.started.elapsed().as_secs_f64());
+    }
+}
+
+impl Drop for InFlightRequest<'_> {
+    fn drop(&mut self) {
+        self.in_flight.dec();
+    }
+}
+
+/// Exposes the queue depth and the busy and idle workers of the pool
+pub(crate) fn register_pool_metrics(registry: &Registry, pool: &PoolHandle) {
+    let stats = |pool: &PoolHandle| {
+        let pool = pool.clone();
+        move || pool.stats()
+    };
+
+    let workers = stats(pool);
+    registry.gauge_fn("thread_pool_workers", "Live pool workers", &[], move || {
+        workers().workers as f64
+    });
+    let busy = stats(pool);
+    registry.gauge_fn(
+        "thread_pool_busy_workers",
+        "Pool workers running a job",
+        &[],
+        move || busy().active as f64,
+    );
+    let idle = stats(pool);
+    registry.gauge_fn(
+        "thread_pool_idle_workers",
+        "Pool workers waiting for a job",
+        &[],
+        move || idle().idle as f64,
+    );
+    let queued = stats(pool);
+    registry.gauge_fn(
+        "thread_pool_queue_depth",
+        "Jobs waiting for a free pool worker",
+        &[],
+        move || queued().queued as f64,
+    );
+}
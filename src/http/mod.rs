@@ -1,3 +1,21 @@
+pub mod access_log;
+#[cfg(feature = "async")]
+mod async_server;
+pub mod config;
+pub mod connection;
+mod event_loop;
+#[cfg(unix)]
+mod handover;
+pub mod health;
+pub mod listener;
+pub(crate) mod metrics;
+pub mod proxy;
+pub(crate) mod reload;
 pub mod request;
 pub mod response;
+pub mod router;
 pub mod server;
+pub mod test_client;
+#[cfg(feature = "tls")]
+pub mod tls;
+pub mod version;
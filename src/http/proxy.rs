@@ -0,0 +1,663 @@
+use std::{
+    collections::HashMap,
+    io::{self, BufRead, BufReader, ErrorKind, Read, Write},
+    net::{IpAddr, TcpStream, ToSocketAddrs},
+    time::Duration,
+};
+
+use log::warn;
+
+use crate::http::{request::Request, response::Response};
+
+/// Headers that only apply to a single connection, they are not forwarded in either direction
+const HOP_BY_HOP_HEADERS: [&str; 9] = [
+    "Connection",
+    "Keep-Alive",
+    "Proxy-Authenticate",
+    "Proxy-Authorization",
+    "Proxy-Connection",
+    "TE",
+    "Trailer",
+    "Transfer-Encoding",
+    "Upgrade",
+];
+
+/// Request headers the proxy sets itself instead of forwarding the client's
+const REPLACED_REQUEST_HEADERS: [&str; 10] = [
+    "Host",
+    "Content-Length",
+    "Content-Encoding",
+    "X-Forwarded-For",
+    "X-Forwarded-Proto",
+    "X-Forwarded-Host",
+    "Forwarded",
+    "X-Request-Id",
+    "traceparent",
+    "tracestate",
+];
+
+/// The max length of the status line, a header line or a chunk size line of the upstream response
+const MAX_LINE_LENGTH: usize = 8192;
+
+/// The max amount of headers of the upstream response
+const MAX_HEADER_COUNT: usize = 100;
+
+/// Forwards requests to an upstream HTTP/1.1 server and streams its responses back
+///
+/// The upstream server sees the original client in the `X-Forwarded-*` and `Forwarded` headers,
+/// and the trace of the request is continued through `traceparent`
+pub struct ProxyHandler {
+    upstream: String,
+    connect_timeout: Duration,
+    read_timeout: Duration,
+    forwarded_proto: String,
+}
+
+pub struct ProxyHandlerBuilder {
+    upstream: String,
+    connect_timeout: Duration,
+    read_timeout: Duration,
+    forwarded_proto: String,
+}
+
+/// Reads the body of a response sent with chunked transfer encoding
+struct ChunkedReader<R> {
+    reader: R,
+    /// Bytes left in the current chunk
+    remaining: usize,
+    is_done: bool,
+}
+
+impl ProxyHandler {
+    /// `upstream` is the `<host>:<port>` of the server the requests are forwarded to
+    pub fn builder(upstream: impl Into<String>) -> ProxyHandlerBuilder {
+        ProxyHandlerBuilder {
+            upstream: Into::into(upstream),
+            connect_timeout: Duration::from_secs(5),
+            read_timeout: Duration::from_secs(30),
+            forwarded_proto: String::from("http"),
+        }
+    }
+
+    fn new(builder: ProxyHandlerBuilder) -> ProxyHandler {
+        ProxyHandler {
+            upstream: builder.upstream,
+            connect_timeout: builder.connect_timeout,
+            read_timeout: builder.read_timeout,
+            forwarded_proto: builder.forwarded_proto,
+        }
+    }
+
+    /// Forwards the request and returns the response of the upstream server
+    ///
+    /// Answers 504 if the upstream server times out and 502 if it can not be reached
+    /// or its response is invalid
+    pub fn handle(&self, request: Request) -> Response {
+        match self.forward(&request) {
+            Ok(response) => response,
+            Err(e) => {
+                warn!(
+                    "Unable to proxy {} {} to {}: {}",
+                    request.method(),
+                    request.url(),
+                    self.upstream,
+                    e
+                );
+                let code = match e.kind() {
+                    ErrorKind::TimedOut | ErrorKind::WouldBlock => 504,
+                    _ => 502,
+                };
+                Response::builder().code(code).build()
+            }
+        }
+    }
+
+    fn forward(&self, request: &Request) -> io::Result<Response> {
+        let mut stream = self.connect()?;
+        stream.set_read_timeout(Some(self.read_timeout))?;
+        stream.set_write_timeout(Some(self.read_timeout))?;
+
+        stream.write_all(self.upstream_request(request).as_bytes())?;
+        stream.flush()?;
+
+        read_response(BufReader::new(stream))
+    }
+
+    fn connect(&self) -> io::Result<TcpStream> {
+        let mut last_error = io::Error::new(
+            ErrorKind::NotFound,
+            format!("{} does not resolve to any address", self.upstream),
+        );
+        for address in self.upstream.to_socket_addrs()? {
+            match TcpStream::connect_timeout(&address, self.connect_timeout) {
+                Ok(stream) => return Ok(stream),
+                Err(e) => last_error = e,
+            }
+        }
+
+        Err(last_error)
+    }
+
+    /// Formats the request sent upstream, with the headers describing the original client
+    fn upstream_request(&self, request: &Request) -> String {
+        let mut upstream_request = format!("{} {}", request.method(), request.url());
+        if let Some(query) = request.query() {
+            upstream_request.push('?');
+            upstream_request.push_str(query);
+        }
+        upstream_request.push_str(" HTTP/1.1\r\n");
+
+        let headers = request.headers();
+        let connection_headers = connection_headers(find_header(headers, "Connection"));
+        for (name, value) in headers {
+            let is_replaced = REPLACED_REQUEST_HEADERS
+                .iter()
+                .any(|replaced| replaced.eq_ignore_ascii_case(name));
+            if !is_replaced && !is_hop_by_hop(name, &connection_headers) {
+                upstream_request.push_str(&format!("{}: {}\r\n", name, value));
+            }
+        }
+
+        let mut add_header = |name: &str, value: &str| {
+            upstream_request.push_str(&format!("{}: {}\r\n", name, value));
+        };
+        let host = find_header(headers, "Host");
+        add_header("Host", &self.upstream);
+        if let Some(forwarded_for) = append_value(
+            find_header(headers, "X-Forwarded-For"),
+            request.client().map(|client| client.to_string()),
+        ) {
+            add_header("X-Forwarded-For", &forwarded_for);
+        }
+        add_header("X-Forwarded-Proto", &self.forwarded_proto);
+        if let Some(host) = host {
+            add_header("X-Forwarded-Host", host);
+        }
+        let forwarded = forwarded_element(request.client(), host, &self.forwarded_proto);
+        if let Some(forwarded) = append_value(find_header(headers, "Forwarded"), Some(forwarded)) {
+            add_header("Forwarded", &forwarded);
+        }
+        if !request.id().is_empty() {
+            add_header("X-Request-Id", request.id());
+        }
+        if let Some(trace_context) = request.trace_context() {
+            add_header("traceparent", &trace_context.traceparent());
+            if let Some(trace_state) = &trace_context.trace_state {
+                add_header("tracestate", trace_state);
+            }
+        }
+        if request.method().can_have_body() || !request.body().is_empty() {
+            add_header("Content-Length", &request.body().len().to_string());
+        }
+        add_header("Connection", "close");
+
+        upstream_request.push_str("\r\n");
+        upstream_request.push_str(request.body());
+
+        upstream_request
+    }
+}
+
+impl ProxyHandlerBuilder {
+    /// How long to wait for the connection to the upstream server, 5 seconds by default
+    pub fn connect_timeout(mut self, connect_timeout: Duration) -> ProxyHandlerBuilder {
+        self.connect_timeout = connect_timeout;
+
+        self
+    }
+
+    /// How long to wait for every read from and write to the upstream server, 30 seconds by default
+    pub fn read_timeout(mut self, read_timeout: Duration) -> ProxyHandlerBuilder {
+        self.read_timeout = read_timeout;
+
+        self
+    }
+
+    /// The protocol clients use to reach this server, `http` by default
+    pub fn forwarded_proto(mut self, forwarded_proto: impl Into<String>) -> ProxyHandlerBuilder {
+        self.forwarded_proto = Into::into(forwarded_proto);
+
+        self
+    }
+
+    pub fn build(self) -> ProxyHandler {
+        ProxyHandler::new(self)
+    }
+}
+
+impl<R: BufRead> ChunkedReader<R> {
+    fn new(reader: R) -> ChunkedReader<R> {
+        ChunkedReader {
+            reader,
+            remaining: 0,
+            is_done: false,
+        }
+    }
+
+    fn read_chunk_size(&mut self) -> io::Result<usize> {
+        let line = read_line(&mut self.reader)?;
+        let size = line.split(';').next().unwrap_or_default().trim();
+
+        usize::from_str_radix(size, 16).map_err(|_| invalid_response("invalid chunk size", &line))
+    }
+}
+
+impl<R: BufRead> Read for ChunkedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.is_done || buf.is_empty() {
+            return Ok(0);
+        }
+        if self.remaining == 0 {
+            self.remaining = self.read_chunk_size()?;
+            if self.remaining == 0 {
+                // Trailers are not forwarded
+                while !read_line(&mut self.reader)?.is_empty() {}
+                self.is_done = true;
+                return Ok(0);
+            }
+        }
+
+        let max_read = buf.len().min(self.remaining);
+        let read = self.reader.read(&mut buf[..max_read])?;
+        if read == 0 {
+            return Err(io::Error::new(
+                ErrorKind::UnexpectedEof,
+                "upstream closed the connection within a chunk",
+            ));
+        }
+        self.remaining -= read;
+        if self.remaining == 0 && !read_line(&mut self.reader)?.is_empty() {
+            return Err(invalid_response("chunk is longer than its size", ""));
+        }
+
+        Ok(read)
+    }
+}
+
+/// Reads the head of the upstream response, the body is streamed while the response is written
+fn read_response(mut reader: BufReader<TcpStream>) -> io::Result<Response> {
+    let (code, headers, cookies) = loop {
+        let status_line = read_line(&mut reader)?;
+        let code = parse_status_line(&status_line)?;
+        let (headers, cookies) = read_headers(&mut reader)?;
+
+        // Interim responses such as 100 Continue are answered by the final one
+        if !(100..200).contains(&code) {
+            break (code, headers, cookies);
+        }
+    };
+
+    let connection_headers = connection_headers(find_header(&headers, "Connection"));
+    let is_chunked = find_header(&headers, "Transfer-Encoding").is_some_and(|encoding| {
+        encoding
+            .rsplit(',')
+            .next()
+            .is_some_and(|last| last.trim().eq_ignore_ascii_case("chunked"))
+    });
+    let content_length = find_header(&headers, "Content-Length")
+        .map(|length| {
+            length
+                .trim()
+                .parse::<u64>()
+                .map_err(|_| invalid_response("invalid Content-Length", length))
+        })
+        .transpose()?;
+
+    // The length is set under its canonical name, so the response is never framed twice
+    let mut builder = Response::builder().code(code);
+    for (name, value) in &headers {
+        let is_framing = name.eq_ignore_ascii_case("Content-Length");
+        if !is_framing && !is_hop_by_hop(name, &connection_headers) {
+            builder = builder.add_header(name, value);
+        }
+    }
+    for (name, value) in cookies {
+        builder = builder.append_header(name, value);
+    }
+    if let Some(content_length) = content_length.filter(|_| !is_chunked) {
+        builder = builder.add_header("Content-Length", content_length.to_string());
+    }
+
+    let builder = if code == 204 || code == 304 {
+        builder
+    } else if is_chunked {
+        builder.body_stream(ChunkedReader::new(reader))
+    } else if let Some(content_length) = content_length {
+        builder.body_stream(reader.take(content_length))
+    } else {
+        builder.body_stream(reader)
+    };
+
+    Ok(builder.build())
+}
+
+/// Headers of an upstream response and its `Set-Cookie` headers
+type UpstreamHeaders = (HashMap<String, String>, Vec<(String, String)>);
+
+/// Parses `HTTP/1.1 200 OK` into the status code
+fn parse_status_line(status_line: &str) -> io::Result<u16> {
+    let mut parts = status_line.splitn(3, ' ');
+    let version = parts.next().unwrap_or_default();
+    let code = parts.next().and_then(|code| code.parse::<u16>().ok());
+
+    match code {
+        Some(code) if version.starts_with("HTTP/1.") && (100..600).contains(&code) => Ok(code),
+        _ => Err(invalid_response("invalid status line", status_line)),
+    }
+}
+
+/// Reads the headers, values of repeated headers are joined with commas
+///
+/// `Set-Cookie` headers are returned apart, one per line,
+/// as cookie values may contain commas and must not be folded, see RFC 6265
+fn read_headers(reader: &mut impl BufRead) -> io::Result<UpstreamHeaders> {
+    let mut headers: HashMap<String, String> = HashMap::new();
+    let mut cookies = Vec::new();
+
+    loop {
+        let line = read_line(reader)?;
+        if line.is_empty() {
+            return Ok((headers, cookies));
+        }
+        if headers.len() + cookies.len() == MAX_HEADER_COUNT {
+            return Err(invalid_response("too many headers", ""));
+        }
+
+        let Some((name, value)) = line.split_once(':') else {
+            return Err(invalid_response("invalid header", &line));
+        };
+        let value = value.trim();
+        if name.eq_ignore_ascii_case("Set-Cookie") {
+            cookies.push((String::from(name), String::from(value)));
+            continue;
+        }
+        headers
+            .entry(String::from(name))
+            .and_modify(|values| {
+                values.push_str(", ");
+                values.push_str(value);
+            })
+            .or_insert_with(|| String::from(value));
+    }
+}
+
+/// Reads a line without the line terminator
+fn read_line(reader: &mut impl BufRead) -> io::Result<String> {
+    let mut line = Vec::new();
+    let read = reader
+        .by_ref()
+        .take(MAX_LINE_LENGTH as u64 + 1)
+        .read_until(b'\n', &mut line)?;
+
+    if read == 0 {
+        return Err(io::Error::new(
+            ErrorKind::UnexpectedEof,
+            "upstream closed the connection",
+        ));
+    }
+    if read > MAX_LINE_LENGTH {
+        return Err(invalid_response("line is too long", ""));
+    }
+
+    Ok(String::from_utf8_lossy(&line)
+        .trim_end_matches(['\r', '\n'])
+        .to_string())
+}
+
+fn invalid_response(reason: &str, line: &str) -> io::Error {
+    io::Error::new(
+        ErrorKind::InvalidData,
+        format!("invalid upstream response, {}: '{}'", reason, line),
+    )
+}
+
+fn find_header<'a>(headers: &'a HashMap<String, String>, name: &str) -> Option<&'a String> {
+    headers
+        .iter()
+        .find(|(header_name, _)| header_name.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value)
+}
+
+/// Names of the headers the `Connection` header marks as hop-by-hop
+fn connection_headers(connection: Option<&String>) -> Vec<String> {
+    connection
+        .map(|connection| {
+            connection
+                .split(',')
+                .map(|name| name.trim().to_ascii_lowercase())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn is_hop_by_hop(name: &str, connection_headers: &[String]) -> bool {
+    HOP_BY_HOP_HEADERS
+        .iter()
+        .any(|hop_by_hop| hop_by_hop.eq_ignore_ascii_case(name))
+        || connection_headers.contains(&name.to_ascii_lowercase())
+}
+
+fn append_value(values: Option<&String>, value: Option<String>) -> Option<String> {
+    match (values, value) {
+        (Some(values), Some(value)) => Some(format!("{}, {}", values, value)),
+        (Some(values), None) => Some(values.clone()),
+        (None, value) => value,
+    }
+}
+
+/// Formats the `Forwarded` element describing this hop, see RFC 7239
+fn forwarded_element(client: Option<IpAddr>, host: Option<&String>, proto: &str) -> String {
+    let client = match client {
+        Some(IpAddr::V4(client)) => client.to_string(),
+        Some(IpAddr::V6(client)) => format!("\"[{}]\"", client),
+        None => String::from("unknown"),
+    };
+    let mut element = format!("for={}", client);
+    if let Some(host) = host {
+        element.push_str(&format!(";host=\"{}\"", host.replace(['"', '\\'], "")));
+    }
+    element.push_str(&format!(";proto={}", proto));
+
+    element
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{Ipv4Addr, TcpListener},
+        thread,
+    };
+
+    use super::*;
+    use crate::http::request::RequestMethod;
+
+    /// Answers a single connection with the response and returns the request it has received
+    fn start_upstream(response: &'static str) -> (u16, thread::JoinHandle<String>) {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let upstream = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut request = String::new();
+            let mut content_length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if let Some(length) = line.strip_prefix("Content-Length: ") {
+                    content_length = length.trim().parse().unwrap();
+                }
+                request.push_str(&line);
+                if line == "\r\n" {
+                    break;
+                }
+            }
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).unwrap();
+            request.push_str(&String::from_utf8(body).unwrap());
+
+            stream.write_all(response.as_bytes()).unwrap();
+            request
+        });
+
+        (port, upstream)
+    }
+
+    #[test]
+    fn handle_must_forward_request_and_stream_response() {
+        let (port, upstream) = start_upstream(
+            "HTTP/1.1 201 Created\r\nContent-Type: text/plain\r\nTransfer-Encoding: chunked\r\n\
+             Connection: close, X-Upstream-Hop\r\nX-Upstream-Hop: 1\r\n\r\n\
+             4\r\ntest\r\n5\r\n_body\r\n0\r\n\r\n",
+        );
+        let proxy = ProxyHandler::builder(format!("127.0.0.1:{}", port)).build();
+        let mut request = Request::builder()
+            .method(RequestMethod::POST)
+            .url("/test")
+            .add_header("Host", "example.com")
+            .add_header("X-Forwarded-For", "203.0.113.1")
+            .add_header("Keep-Alive", "timeout=5")
+            .add_header("Accept", "text/plain")
+            .body("test_request")
+            .build();
+        request.set_client(Some(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1))));
+
+        let mut response_bytes = Vec::new();
+        proxy.handle(request).write(&mut response_bytes).unwrap();
+        let upstream_request = upstream.join().unwrap();
+        let response = String::from_utf8(response_bytes).unwrap();
+
+        for expected in [
+            "POST /test HTTP/1.1\r\n",
+            &format!("Host: 127.0.0.1:{}\r\n", port),
+            "X-Forwarded-For: 203.0.113.1, 192.0.2.1\r\n",
+            "X-Forwarded-Proto: http\r\n",
+            "X-Forwarded-Host: example.com\r\n",
+            "Forwarded: for=192.0.2.1;host=\"example.com\";proto=http\r\n",
+            "Accept: text/plain\r\n",
+            "Content-Length: 12\r\n",
+            "\r\n\r\ntest_request",
+        ] {
+            assert!(
+                upstream_request.contains(expected),
+                "Upstream request must contain '{}': {}",
+                expected.trim(),
+                upstream_request
+            );
+        }
+        assert!(
+            !upstream_request.contains("Keep-Alive"),
+            "Hop-by-hop request headers must be stripped"
+        );
+        assert!(
+            response.starts_with("HTTP/1.1 201 Created\r\n"),
+            "Upstream status must be kept: {}",
+            response
+        );
+        assert!(
+            response.contains("Content-Type: text/plain\r\n"),
+            "Upstream headers must be kept"
+        );
+        assert!(
+            !response.contains("X-Upstream-Hop"),
+            "Headers listed in Connection must be stripped"
+        );
+        assert!(
+            response.ends_with("\r\n\r\n4\r\ntest\r\n5\r\n_body\r\n0\r\n\r\n"),
+            "Upstream body must be streamed: {}",
+            response
+        );
+    }
+
+    #[test]
+    fn handle_must_frame_response_once_when_upstream_uses_lowercase_content_length() {
+        let (port, upstream) =
+            start_upstream("HTTP/1.1 200 OK\r\ncontent-length: 9\r\n\r\ntest_body");
+        let proxy = ProxyHandler::builder(format!("127.0.0.1:{}", port)).build();
+
+        let mut response_bytes = Vec::new();
+        proxy
+            .handle(Request::builder().url("/test").build())
+            .write(&mut response_bytes)
+            .unwrap();
+        upstream.join().unwrap();
+        let response = String::from_utf8(response_bytes).unwrap();
+
+        assert!(
+            response.contains("Content-Length: 9\r\n") && !response.contains("content-length"),
+            "Content-Length must be sent under its canonical name: {}",
+            response
+        );
+        assert!(
+            !response.contains("Transfer-Encoding"),
+            "Response with Content-Length must not be chunked: {}",
+            response
+        );
+        assert!(
+            response.ends_with("\r\n\r\ntest_body"),
+            "Upstream body must be sent as is: {}",
+            response
+        );
+    }
+
+    #[test]
+    fn handle_must_keep_upstream_cookies_on_separate_lines() {
+        let (port, upstream) = start_upstream(
+            "HTTP/1.1 200 OK\r\nSet-Cookie: id=1; Expires=Wed, 21 Oct 2026 07:28:00 GMT\r\n\
+             set-cookie: theme=dark\r\nContent-Length: 0\r\n\r\n",
+        );
+        let proxy = ProxyHandler::builder(format!("127.0.0.1:{}", port)).build();
+
+        let mut response_bytes = Vec::new();
+        proxy
+            .handle(Request::builder().url("/test").build())
+            .write(&mut response_bytes)
+            .unwrap();
+        upstream.join().unwrap();
+        let response = String::from_utf8(response_bytes).unwrap();
+
+        for expected in [
+            "\r\nSet-Cookie: id=1; Expires=Wed, 21 Oct 2026 07:28:00 GMT\r\n",
+            "\r\nset-cookie: theme=dark\r\n",
+        ] {
+            assert!(
+                response.contains(expected),
+                "Cookie must be sent on a line of its own '{}': {}",
+                expected.trim(),
+                response
+            );
+        }
+    }
+
+    #[test]
+    fn handle_must_answer_bad_gateway_when_upstream_is_unreachable() {
+        let port = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let proxy = ProxyHandler::builder(format!("127.0.0.1:{}", port))
+            .connect_timeout(Duration::from_secs(1))
+            .build();
+
+        let response = proxy.handle(Request::builder().url("/test").build());
+
+        assert_eq!(
+            502,
+            response.code(),
+            "Unreachable upstream must be a bad gateway"
+        );
+    }
+
+    #[test]
+    fn handle_must_answer_gateway_timeout_when_upstream_does_not_respond() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let proxy = ProxyHandler::builder(listener.local_addr().unwrap().to_string())
+            .read_timeout(Duration::from_millis(100))
+            .build();
+
+        let response = proxy.handle(Request::builder().url("/test").build());
+
+        assert_eq!(504, response.code(), "Silent upstream must time out");
+    }
+}
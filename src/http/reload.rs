@@ -0,0 +1,217 @@
+use std::{
+    collections::BTreeSet,
+    ffi::OsString,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use clap::FromArgMatches;
+use log::{info, warn};
+use toml::Table;
+
+use crate::http::{config, router::Router, server::Config};
+
+/// Options applied to the running server on reload, changing any other one requires a restart
+const RELOADABLE: [&str; 6] = [
+    "log_level",
+    "max_request_line_length",
+    "max_header_count",
+    "max_header_size",
+    "max_body_size",
+    "max_decompressed_body_size",
+];
+
+/// How often the config file is checked for changes
+const WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Loads the configuration again and applies the options that can change while the server runs
+pub(crate) struct ConfigReloader {
+    args: Vec<OsString>,
+    env: fn(&str) -> Option<String>,
+    router: Arc<Router>,
+    /// Effective configuration of the running server
+    current: Table,
+}
+
+impl ConfigReloader {
+    /// Loads the configuration the server is running with from the same arguments and environment
+    pub(crate) fn new(
+        args: Vec<OsString>,
+        env: fn(&str) -> Option<String>,
+        router: &Arc<Router>,
+    ) -> Result<ConfigReloader, clap::Error> {
+        let matches = config::load_matches(&args, env)?;
+
+        Ok(ConfigReloader {
+            current: config::effective_config(&matches),
+            args,
+            env,
+            router: Arc::clone(router),
+        })
+    }
+
+    /// Validates the configuration and applies it, nothing is applied if it is invalid
+    ///
+    /// Requests being handled keep the limits they have been parsed with.
+    /// Returns the changed options that only take effect after a restart
+    pub(crate) fn reload(&mut self) -> Result<Vec<String>, clap::Error> {
+        let matches = config::load_matches(&self.args, self.env)?;
+        let config = Config::from_arg_matches(&matches)?;
+        let reloaded = config::effective_config(&matches);
+
+        log::set_max_level(config.log_level);
+        self.router.set_limits(config.limits());
+
+        let keys: BTreeSet<String> = self
+            .current
+            .keys()
+            .chain(reloaded.keys())
+            .cloned()
+            .collect();
+        let mut restart_required = Vec::new();
+        for key in keys {
+            let value = reloaded.get(&key);
+            if self.current.get(&key) == value {
+                continue;
+            }
+
+            if !RELOADABLE.contains(&key.as_str()) {
+                restart_required.push(key);
+            } else if let Some(value) = value {
+                self.current.insert(key, value.clone());
+            } else {
+                self.current.remove(&key);
+            }
+        }
+
+        Ok(restart_required)
+    }
+
+    /// Reloads the configuration on SIGHUP and whenever the config file is modified
+    pub(crate) fn watch(mut self, path: PathBuf) -> io::Result<()> {
+        let hangup = Arc::new(AtomicBool::new(false));
+        #[cfg(unix)]
+        signal_hook::flag::register(signal_hook::consts::SIGHUP, Arc::clone(&hangup))?;
+        let mut modified = modified_time(&path);
+
+        thread::Builder::new()
+            .name(String::from("config-reloader"))
+            .spawn(move || loop {
+                thread::sleep(WATCH_INTERVAL);
+
+                let last_modified = modified_time(&path);
+                if !hangup.swap(false, Ordering::SeqCst) && last_modified == modified {
+                    continue;
+                }
+                modified = last_modified;
+
+                match self.reload() {
+                    Ok(restart_required) => {
+                        info!("Configuration has been reloaded from {}", path.display());
+                        for key in restart_required {
+                            warn!("Option '{}' has changed but requires a restart", key);
+                        }
+                    }
+                    Err(e) => warn!(
+                        "Unable to reload the configuration, keeping the current one: {}",
+                        e.to_string().lines().next().unwrap_or_default()
+                    ),
+                }
+            })?;
+
+        Ok(())
+    }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::request::limits::RequestLimits;
+
+    fn no_env(_: &str) -> Option<String> {
+        None
+    }
+
+    #[test]
+    fn reload_must_apply_limits_and_report_restart_required_options() {
+        let path = std::env::temp_dir().join(format!(
+            "rust_web_server_reload_{}.toml",
+            std::process::id()
+        ));
+        fs::write(&path, "port = 9000\nmax_body_size = 100\n").unwrap();
+        let args = vec![
+            OsString::from("rws"),
+            OsString::from("--config"),
+            OsString::from(&path),
+        ];
+        let router = Arc::new(Router::new(Vec::new(), RequestLimits::default()));
+        let mut reloader = ConfigReloader::new(args, no_env, &router).unwrap();
+
+        let in_flight_limits = router.limits();
+        fs::write(&path, "port = 9001\nmax_body_size = 200\n").unwrap();
+        let restart_required = reloader.reload().unwrap();
+        fs::write(&path, "port = 0\nmax_body_size = 300\n").unwrap();
+        let invalid = reloader.reload();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            vec![String::from("port")],
+            restart_required,
+            "Changed bind address must be reported"
+        );
+        assert_eq!(
+            200,
+            router.limits().max_body_size,
+            "Valid limits must be applied, invalid ones ignored"
+        );
+        assert_eq!(
+            RequestLimits::default().max_body_size,
+            in_flight_limits.max_body_size,
+            "In-flight requests must keep their limits"
+        );
+        assert!(invalid.is_err(), "Invalid configuration must be rejected");
+    }
+
+    #[test]
+    fn reload_must_forget_removed_reloadable_options() {
+        let path = std::env::temp_dir().join(format!(
+            "rust_web_server_reload_removed_{}.toml",
+            std::process::id()
+        ));
+        fs::write(&path, "max_body_size = 100\n").unwrap();
+        let args = vec![
+            OsString::from("rws"),
+            OsString::from("--config"),
+            OsString::from(&path),
+        ];
+        let router = Arc::new(Router::new(Vec::new(), RequestLimits::default()));
+        let mut reloader = ConfigReloader::new(args.clone(), no_env, &router).unwrap();
+
+        fs::write(&path, "").unwrap();
+        reloader.reload().unwrap();
+        let reloaded = config::effective_config(&config::load_matches(&args, no_env).unwrap());
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            RequestLimits::default().max_body_size,
+            router.limits().max_body_size,
+            "Removed limit must fall back to its default"
+        );
+        assert_eq!(
+            reloaded, reloader.current,
+            "Removed option must not be kept as the current value"
+        );
+    }
+}
@@ -0,0 +1,105 @@
+use std::{
+    fmt::Display,
+    io::{self, ErrorKind},
+};
+
+/// An error that occurred while parsing an incoming request
+#[derive(Debug)]
+pub enum ParseError {
+    /// The request line is not in the `<method> <path> <version>` form
+    BadRequestLine(String),
+    /// The request method is not supported by the server
+    UnsupportedMethod(String),
+    /// The protocol version is not supported by the server
+    UnsupportedVersion(String),
+    /// A query param is not in the `<name>=<value>[,<value>]` form
+    BadQueryParam(String),
+    /// A header line is not in the `<name>: <value>` form
+    BadHeader(String),
+    /// The `Content-Length` header is not a valid number
+    InvalidContentLength(String),
+    /// The body can not be decoded into a string
+    BadBody(String),
+    /// The connection has been closed before the whole body has been received
+    IncompleteBody,
+    /// The request line exceeds the configured limit
+    RequestLineTooLong(usize),
+    /// The request has too many headers or one of them is too long
+    HeadersTooLarge(usize),
+    /// The body exceeds the configured limit
+    BodyTooLarge(usize),
+    /// The body is encoded with an unsupported `Content-Encoding`
+    UnsupportedEncoding(String),
+    /// The client has not sent the request in time
+    Timeout,
+    /// Reading from the connection failed
+    Io(io::Error),
+}
+
+impl ParseError {
+    /// Returns the status code of the response that should be sent back to the client
+    pub fn status_code(&self) -> u16 {
+        match self {
+            ParseError::BadRequestLine(_)
+            | ParseError::BadQueryParam(_)
+            | ParseError::BadHeader(_)
+            | ParseError::InvalidContentLength(_)
+            | ParseError::BadBody(_)
+            | ParseError::IncompleteBody => 400,
+            ParseError::Timeout => 408,
+            ParseError::BodyTooLarge(_) => 413,
+            ParseError::RequestLineTooLong(_) => 414,
+            ParseError::UnsupportedEncoding(_) => 415,
+            ParseError::HeadersTooLarge(_) => 431,
+            ParseError::Io(_) => 500,
+            ParseError::UnsupportedMethod(_) => 501,
+            ParseError::UnsupportedVersion(_) => 505,
+        }
+    }
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseError::BadRequestLine(request_line) => {
+                write!(f, "Invalid request line: {}", request_line)
+            }
+            ParseError::UnsupportedMethod(method) => write!(f, "Unsupported method: {}", method),
+            ParseError::UnsupportedVersion(version) => {
+                write!(f, "Unsupported HTTP version: {}", version)
+            }
+            ParseError::BadQueryParam(query_param) => {
+                write!(f, "Invalid query param: {}", query_param)
+            }
+            ParseError::BadHeader(header) => write!(f, "Invalid header: {}", header),
+            ParseError::InvalidContentLength(content_length) => {
+                write!(f, "Invalid Content-Length: {}", content_length)
+            }
+            ParseError::BadBody(message) => write!(f, "Invalid request body: {}", message),
+            ParseError::IncompleteBody => write!(f, "Request body is incomplete"),
+            ParseError::RequestLineTooLong(limit) => {
+                write!(f, "Request line exceeds {} bytes", limit)
+            }
+            ParseError::HeadersTooLarge(limit) => {
+                write!(f, "Request headers exceed the limit of {}", limit)
+            }
+            ParseError::BodyTooLarge(limit) => write!(f, "Request body exceeds {} bytes", limit),
+            ParseError::UnsupportedEncoding(encoding) => {
+                write!(f, "Unsupported content encoding: {}", encoding)
+            }
+            ParseError::Timeout => write!(f, "Timed out while reading the request"),
+            ParseError::Io(e) => write!(f, "Unable to read the request: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl From<io::Error> for ParseError {
+    fn from(error: io::Error) -> Self {
+        match error.kind() {
+            ErrorKind::WouldBlock | ErrorKind::TimedOut => ParseError::Timeout,
+            _ => ParseError::Io(error),
+        }
+    }
+}
@@ -0,0 +1,41 @@
+/// Limits applied while an incoming request is being parsed
+///
+/// Every limit is checked before the corresponding part of the request is buffered,
+/// so a single client can not make the server allocate more than allowed
+#[derive(Debug, Clone, PartialEq)]
+pub struct RequestLimits {
+    /// The max length of the request line in bytes
+    pub max_request_line_length: usize,
+    /// The max amount of header lines
+    pub max_header_count: usize,
+    /// The max length of a single header line in bytes
+    pub max_header_size: usize,
+    /// The max value of the `Content-Length` header
+    pub max_body_size: usize,
+    /// The max size of the body after it has been decompressed
+    pub max_decompressed_body_size: usize,
+}
+
+impl Default for RequestLimits {
+    fn default() -> Self {
+        RequestLimits {
+            max_request_line_length: 8 * 1024,
+            max_header_count: 100,
+            max_header_size: 8 * 1024,
+            max_body_size: 10 * 1024 * 1024,
+            max_decompressed_body_size: 10 * 1024 * 1024,
+        }
+    }
+}
+
+impl RequestLimits {
+    /// The max size of the request line and the headers, including the empty line ending them
+    pub fn max_head_size(&self) -> usize {
+        self.max_request_line_length + self.max_header_count * self.max_header_size + 2
+    }
+
+    /// The max amount of bytes a request can take up before it is rejected
+    pub fn max_request_size(&self) -> usize {
+        self.max_head_size() + self.max_body_size
+    }
+}
@@ -34,6 +34,14 @@ impl RequestMatcher {
         RequestMatcherBuilder::new(RequestMethod::PUT)
     }
 
+    pub fn method(&self) -> &RequestMethod {
+        &self.method
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
     pub fn matches(&self, request: &Request) -> bool {
         self.method == request.method && self.url == request.url
     }
@@ -1,12 +1,25 @@
 use std::{
     collections::HashMap,
-    io::{BufRead, BufReader, Error, Read},
-    net::TcpStream,
+    fmt::Display,
+    io::{BufRead, BufReader, ErrorKind, Read},
+    net::IpAddr,
 };
 
+use flate2::read::{GzDecoder, ZlibDecoder};
+
+use crate::{
+    http::{
+        request::{error::ParseError, limits::RequestLimits},
+        version::HttpVersion,
+    },
+    trace::SpanContext,
+};
+
+pub mod error;
+pub mod limits;
 pub mod matcher;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum RequestMethod {
     GET,
     POST,
@@ -17,14 +30,20 @@ pub enum RequestMethod {
 pub struct Request {
     url: String,
     method: RequestMethod,
+    version: HttpVersion,
     headers: HashMap<String, String>,
     query_params: HashMap<String, Vec<String>>,
+    query: Option<String>,
     body: String,
+    id: String,
+    trace_context: Option<SpanContext>,
+    client: Option<IpAddr>,
 }
 
 pub struct RequestBuilder {
     url: String,
     method: RequestMethod,
+    version: HttpVersion,
     headers: HashMap<String, String>,
     query_params: HashMap<String, Vec<String>>,
     body: String,
@@ -46,56 +65,93 @@ impl RequestMethod {
     }
 }
 
+impl Display for RequestMethod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RequestMethod::GET => write!(f, "GET"),
+            RequestMethod::POST => write!(f, "POST"),
+            RequestMethod::PUT => write!(f, "PUT"),
+            RequestMethod::DELETE => write!(f, "DELETE"),
+        }
+    }
+}
+
 impl Request {
     fn new(builder: RequestBuilder) -> Request {
         Request {
             url: builder.url,
             method: builder.method,
+            version: builder.version,
             headers: builder.headers,
             query_params: builder.query_params,
+            query: None,
             body: builder.body,
+            id: String::default(),
+            trace_context: None,
+            client: None,
         }
     }
 
-    pub fn parse(stream: &mut TcpStream) -> Result<Request, Error> {
-        let parser_error = parser_error(String::from("Unable to parse an incoming request"));
+    pub fn parse(stream: &mut impl Read, limits: &RequestLimits) -> Result<Request, ParseError> {
         let mut reader = BufReader::new(stream);
-        let mut request_line = String::default();
 
-        let (method, path) = match reader.read_line(&mut request_line) {
-            Ok(_) => parse_request_line(request_line.trim())?,
-            Err(_) => return Err(parser_error),
-        };
+        let request_line = read_line(&mut reader, limits.max_request_line_length)?.ok_or(
+            ParseError::RequestLineTooLong(limits.max_request_line_length),
+        )?;
+        let request_line = String::from_utf8(request_line).map_err(|e| {
+            ParseError::BadRequestLine(String::from_utf8_lossy(e.as_bytes()).into_owned())
+        })?;
+        let (method, path, version) = parse_request_line(request_line.trim())?;
         let (url, query_params) = parse_path(&path)?;
+        let query = path
+            .split_once('?')
+            .map(|(_, query)| String::from(query))
+            .filter(|query| !query.is_empty());
 
         let mut header_lines = Vec::new();
-        let mut header_line = String::default();
-        while reader.read_line(&mut header_line).is_ok() {
+        loop {
+            let header_line = read_line(&mut reader, limits.max_header_size)?
+                .ok_or(ParseError::HeadersTooLarge(limits.max_header_size))?;
+            let header_line = String::from_utf8(header_line).map_err(|e| {
+                ParseError::BadHeader(String::from_utf8_lossy(e.as_bytes()).into_owned())
+            })?;
             let trimmed_header_line = String::from(header_line.trim());
             if trimmed_header_line.is_empty() {
                 break;
             }
+            if header_lines.len() == limits.max_header_count {
+                return Err(ParseError::HeadersTooLarge(limits.max_header_count));
+            }
 
             header_lines.push(trimmed_header_line);
-            header_line.clear();
         }
 
         let headers = parse_headers(header_lines)?;
 
         let body = if method.can_have_body()
-            && let Some(content_length) = headers.get("Content-Length")
+            && let Some(content_length) = find_header(&headers, "Content-Length")
         {
-            let content_length: Result<usize, _> = content_length.parse::<usize>();
-            if let Ok(content_length) = content_length {
-                let mut body_buf = vec![0; content_length];
-                reader.read_exact(&mut body_buf)?;
-                match String::from_utf8(body_buf) {
-                    Ok(body) => body,
-                    Err(_) => return Err(parser_error),
-                }
-            } else {
-                String::default()
+            let content_length: usize = content_length
+                .parse()
+                .map_err(|_| ParseError::InvalidContentLength(content_length.clone()))?;
+            if content_length > limits.max_body_size {
+                return Err(ParseError::BodyTooLarge(limits.max_body_size));
             }
+
+            let mut body_buf = vec![0; content_length];
+            reader
+                .read_exact(&mut body_buf)
+                .map_err(|e| match e.kind() {
+                    ErrorKind::UnexpectedEof => ParseError::IncompleteBody,
+                    _ => ParseError::from(e),
+                })?;
+            let body_buf = decode_body(
+                find_header(&headers, "Content-Encoding"),
+                body_buf,
+                limits.max_decompressed_body_size,
+            )?;
+            String::from_utf8(body_buf)
+                .map_err(|_| ParseError::BadBody(String::from("body is not valid UTF-8")))?
         } else {
             String::default()
         };
@@ -103,9 +159,14 @@ impl Request {
         Ok(Request {
             url,
             method,
+            version,
             headers,
             query_params,
+            query,
             body,
+            id: String::default(),
+            trace_context: None,
+            client: None,
         })
     }
 
@@ -113,63 +174,106 @@ impl Request {
         RequestBuilder {
             url: String::default(),
             method: RequestMethod::GET,
+            version: HttpVersion::default(),
             headers: HashMap::new(),
             query_params: HashMap::new(),
             body: String::default(),
         }
     }
 
+    pub fn method(&self) -> &RequestMethod {
+        &self.method
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn version(&self) -> HttpVersion {
+        self.version
+    }
+
+    /// Looks the header up by name, ignoring the case of the name
     pub fn get_header(&self, header_name: &str) -> Option<&String> {
-        self.headers.get(header_name)
+        find_header(&self.headers, header_name)
+    }
+
+    pub fn headers(&self) -> &HashMap<String, String> {
+        &self.headers
     }
 
     pub fn get_query_param(&self, query_param_name: &str) -> Option<&Vec<String>> {
         self.query_params.get(query_param_name)
     }
-}
 
-fn parse_request_line(request_line: &str) -> Result<(RequestMethod, String), Error> {
-    let parse_error = parser_error(format!("Invalid request line: {}", request_line));
+    /// The query string as received, without the leading `?`
+    pub fn query(&self) -> Option<&str> {
+        self.query.as_deref()
+    }
 
-    let mut request_line_parts = request_line.split(" ");
+    pub fn body(&self) -> &str {
+        &self.body
+    }
 
-    let request_method = match request_line_parts.next() {
-        Some(request_method_str) => RequestMethod::parse(request_method_str),
-        None => return Err(parse_error),
-    };
-    let request_method = match request_method {
-        Some(request_method) => request_method,
-        None => return Err(parse_error),
-    };
+    /// The unique ID of the request, taken from `X-Request-Id` or generated when it is dispatched
+    pub fn id(&self) -> &str {
+        &self.id
+    }
 
-    let path = match request_line_parts.next() {
-        Some(path) => String::from(path),
-        None => return Err(parse_error),
-    };
+    /// The context of the span the request is handled in, to propagate the trace
+    /// to other services with `SpanContext::traceparent`
+    pub fn trace_context(&self) -> Option<&SpanContext> {
+        self.trace_context.as_ref()
+    }
+
+    /// The IP address of the peer that sent the request, Unix domain socket peers have none
+    pub fn client(&self) -> Option<IpAddr> {
+        self.client
+    }
 
-    Ok((request_method, path))
+    pub(crate) fn set_client(&mut self, client: Option<IpAddr>) {
+        self.client = client;
+    }
+
+    pub(crate) fn set_trace(&mut self, id: String, trace_context: SpanContext) {
+        self.id = id;
+        self.trace_context = Some(trace_context);
+    }
 }
 
-fn parse_path(path: &str) -> Result<(String, HashMap<String, Vec<String>>), Error> {
-    let parse_error = parser_error(format!("Invalid path: {}", path));
+fn parse_request_line(
+    request_line: &str,
+) -> Result<(RequestMethod, String, HttpVersion), ParseError> {
+    let request_line_parts: Vec<&str> = request_line.split(' ').collect();
+    let (request_method, path, version) = match request_line_parts[..] {
+        [request_method, path, version] if !path.is_empty() => (request_method, path, version),
+        _ => return Err(ParseError::BadRequestLine(String::from(request_line))),
+    };
 
-    let mut path_parts = path.split('?');
+    let request_method = match RequestMethod::parse(request_method) {
+        Some(request_method) => request_method,
+        None => return Err(ParseError::UnsupportedMethod(String::from(request_method))),
+    };
 
-    let url = match path_parts.next() {
-        Some(url) => String::from(url),
-        None => return Err(parse_error),
+    let version = match HttpVersion::parse(version) {
+        Some(version) => version,
+        None => return Err(ParseError::UnsupportedVersion(String::from(version))),
     };
 
-    let query_params = match path_parts.next() {
-        Some("") => HashMap::default(),
-        Some(query_str) => parse_query_params(query_str)?,
-        None => HashMap::default(),
+    Ok((request_method, String::from(path), version))
+}
+
+fn parse_path(path: &str) -> Result<(String, HashMap<String, Vec<String>>), ParseError> {
+    let (url, query_params) = match path.split_once('?') {
+        Some((url, "")) => (url, HashMap::default()),
+        Some((url, query_str)) => (url, parse_query_params(query_str)?),
+        None => (path, HashMap::default()),
     };
 
-    Ok((url, query_params))
+    Ok((String::from(url), query_params))
 }
 
-fn parse_query_params(query_str: &str) -> Result<HashMap<String, Vec<String>>, Error> {
+fn parse_query_params(query_str: &str) -> Result<HashMap<String, Vec<String>>, ParseError> {
     let query_params: Vec<&str> = query_str.split('&').collect();
 
     let mut result: HashMap<String, Vec<String>> = HashMap::with_capacity(query_params.len());
@@ -184,8 +288,8 @@ fn parse_query_params(query_str: &str) -> Result<HashMap<String, Vec<String>>, E
     Ok(result)
 }
 
-fn parse_query_param(query_param: &str) -> Result<(String, Vec<String>), Error> {
-    let parse_error = parser_error(format!("Invalid query param: {}", query_param));
+fn parse_query_param(query_param: &str) -> Result<(String, Vec<String>), ParseError> {
+    let parse_error = ParseError::BadQueryParam(String::from(query_param));
     let mut query_param_parts = query_param.split("=");
     let param_name = match query_param_parts.next() {
         Some(param_name) => String::from(param_name),
@@ -199,8 +303,8 @@ fn parse_query_param(query_param: &str) -> Result<(String, Vec<String>), Error>
     Ok((param_name, param_values))
 }
 
-fn parse_query_param_values(query_param_values: &str) -> Result<Vec<String>, Error> {
-    let parse_error = parser_error(format!("Invalid query param value: {}", query_param_values));
+fn parse_query_param_values(query_param_values: &str) -> Result<Vec<String>, ParseError> {
+    let parse_error = ParseError::BadQueryParam(String::from(query_param_values));
 
     let values: Vec<String> = query_param_values.split(",").map(String::from).collect();
 
@@ -211,31 +315,139 @@ fn parse_query_param_values(query_param_values: &str) -> Result<Vec<String>, Err
     }
 }
 
-fn parse_headers(header_lines: Vec<String>) -> Result<HashMap<String, String>, Error> {
-    let parse_error = parser_error(String::from("Invalid headers"));
+fn parse_headers(header_lines: Vec<String>) -> Result<HashMap<String, String>, ParseError> {
     let mut result: HashMap<String, String> = HashMap::with_capacity(header_lines.len());
 
     for header_line in header_lines {
-        let mut header_parts = header_line.split(": ");
-
-        let header_name = match header_parts.next() {
-            Some(name) => String::from(name),
-            None => return Err(parse_error),
-        };
-
-        let header_value = match header_parts.next() {
-            Some(value) => String::from(value),
-            None => String::default(),
+        let (header_name, header_value) = match header_line.split_once(':') {
+            Some((name, value)) if is_valid_header_name(name) => (name, value.trim()),
+            _ => return Err(ParseError::BadHeader(header_line)),
         };
 
-        result.insert(header_name, header_value);
+        result.insert(String::from(header_name), String::from(header_value));
     }
 
     Ok(result)
 }
 
-fn parser_error(error_message: String) -> Error {
-    Error::new(std::io::ErrorKind::InvalidData, error_message)
+/// Looks the header up by name, ignoring the case of the name
+fn find_header<'a>(headers: &'a HashMap<String, String>, header_name: &str) -> Option<&'a String> {
+    headers.get(header_name).or_else(|| {
+        headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(header_name))
+            .map(|(_, value)| value)
+    })
+}
+
+/// Checks whether the buffer holds the whole request or enough of it to reject it
+///
+/// The request itself is parsed later by the router, so anything that can not be
+/// interpreted here is considered complete and left to the parser to report
+pub(crate) fn is_request_complete(buffer: &[u8], limits: &RequestLimits) -> bool {
+    let head_end = match find_head_end(buffer) {
+        Some(head_end) => head_end,
+        None => return buffer.len() > limits.max_head_size(),
+    };
+    if head_end > limits.max_head_size() {
+        return true;
+    }
+
+    let head = String::from_utf8_lossy(&buffer[..head_end]);
+    let content_length = head.lines().skip(1).find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        name.eq_ignore_ascii_case("Content-Length")
+            .then(|| value.trim().parse::<usize>())
+    });
+
+    match content_length {
+        Some(Ok(content_length)) if content_length <= limits.max_body_size => {
+            buffer.len() >= head_end + content_length
+        }
+        _ => true,
+    }
+}
+
+/// Returns the position right after the empty line terminating the headers
+fn find_head_end(buffer: &[u8]) -> Option<usize> {
+    buffer
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .map(|position| position + 4)
+        .or_else(|| {
+            buffer
+                .windows(2)
+                .position(|window| window == b"\n\n")
+                .map(|position| position + 2)
+        })
+}
+
+fn is_valid_header_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_graphic() && !b"()<>@,;:\\\"/[]?={}".contains(&b))
+}
+
+/// Reads a single line, including the line terminator
+///
+/// Returns `None` if the line is longer than `max_length` bytes
+fn read_line(reader: &mut impl BufRead, max_length: usize) -> Result<Option<Vec<u8>>, ParseError> {
+    let mut line = Vec::new();
+    let read = reader
+        .by_ref()
+        .take(max_length as u64 + 1)
+        .read_until(b'\n', &mut line)?;
+
+    if read > max_length {
+        Ok(None)
+    } else {
+        Ok(Some(line))
+    }
+}
+
+/// Decodes the body according to the `Content-Encoding` header
+///
+/// Encodings are listed in the order they have been applied, so they are undone in reverse
+fn decode_body(
+    content_encoding: Option<&String>,
+    body: Vec<u8>,
+    max_decompressed_size: usize,
+) -> Result<Vec<u8>, ParseError> {
+    let content_encoding = match content_encoding {
+        Some(content_encoding) => content_encoding,
+        None => return Ok(body),
+    };
+
+    let mut body = body;
+    for encoding in content_encoding.split(',').rev() {
+        body = match encoding.trim().to_ascii_lowercase().as_str() {
+            "" | "identity" => body,
+            "gzip" | "x-gzip" => {
+                decompress(GzDecoder::new(body.as_slice()), max_decompressed_size)?
+            }
+            "deflate" => decompress(ZlibDecoder::new(body.as_slice()), max_decompressed_size)?,
+            unsupported => {
+                return Err(ParseError::UnsupportedEncoding(String::from(unsupported)));
+            }
+        };
+    }
+
+    Ok(body)
+}
+
+fn decompress(decoder: impl Read, max_decompressed_size: usize) -> Result<Vec<u8>, ParseError> {
+    let mut decompressed = Vec::new();
+    decoder
+        .take(max_decompressed_size as u64 + 1)
+        .read_to_end(&mut decompressed)
+        .map_err(|e| ParseError::BadBody(format!("unable to decompress: {}", e)))?;
+
+    if decompressed.len() > max_decompressed_size {
+        return Err(ParseError::BodyTooLarge(max_decompressed_size));
+    }
+
+    Ok(decompressed)
 }
 
 impl RequestBuilder {
@@ -249,6 +461,11 @@ impl RequestBuilder {
         self
     }
 
+    pub fn version(mut self, version: HttpVersion) -> Self {
+        self.version = version;
+        self
+    }
+
     pub fn add_header(
         mut self,
         header_name: impl Into<String>,
@@ -289,6 +506,13 @@ impl RequestBuilder {
 
 #[cfg(test)]
 mod tests {
+    use std::io::Write;
+
+    use flate2::{
+        write::{GzEncoder, ZlibEncoder},
+        Compression,
+    };
+
     use super::*;
 
     #[test]
@@ -381,4 +605,380 @@ mod tests {
         );
         assert!(unknown.is_none(), "Unknown method must be parsed into None");
     }
+
+    #[test]
+    fn decode_body_must_decompress_gzip_and_deflate() {
+        let mut gzip = GzEncoder::new(Vec::new(), Compression::default());
+        gzip.write_all(b"test_body").unwrap();
+        let gzip = gzip.finish().unwrap();
+
+        let mut deflate = ZlibEncoder::new(Vec::new(), Compression::default());
+        deflate.write_all(b"test_body").unwrap();
+        let deflate = deflate.finish().unwrap();
+
+        let gzip_body = decode_body(Some(&String::from("gzip")), gzip, 1024);
+        let deflate_body = decode_body(Some(&String::from("deflate")), deflate, 1024);
+        let identity_body = decode_body(None, b"test_body".to_vec(), 1024);
+
+        assert!(
+            gzip_body.is_ok_and(|b| b == b"test_body"),
+            "gzip body must be decompressed"
+        );
+        assert!(
+            deflate_body.is_ok_and(|b| b == b"test_body"),
+            "deflate body must be decompressed"
+        );
+        assert!(
+            identity_body.is_ok_and(|b| b == b"test_body"),
+            "Body without encoding must be left as is"
+        );
+    }
+
+    #[test]
+    fn decode_body_must_reject_unsupported_encoding() {
+        let body = decode_body(Some(&String::from("br")), b"test_body".to_vec(), 1024);
+
+        assert!(
+            body.is_err_and(|e| matches!(e, ParseError::UnsupportedEncoding(_))),
+            "Unsupported encoding must be rejected"
+        );
+    }
+
+    #[test]
+    fn decode_body_must_reject_too_large_body() {
+        let mut gzip = GzEncoder::new(Vec::new(), Compression::best());
+        gzip.write_all(&[0; 1025]).unwrap();
+        let gzip = gzip.finish().unwrap();
+
+        let body = decode_body(Some(&String::from("gzip")), gzip, 1024);
+
+        assert!(
+            body.is_err_and(|e| matches!(e, ParseError::BodyTooLarge(1024))),
+            "Body exceeding decompressed size cap must be rejected"
+        );
+    }
+
+    #[test]
+    fn read_line_must_respect_max_length() {
+        let mut short_line = "GET / HTTP/1.1\r\n".as_bytes();
+        let mut long_line = "GET /very/long/path HTTP/1.1\r\n".as_bytes();
+
+        let short_line = read_line(&mut short_line, 16);
+        let long_line = read_line(&mut long_line, 16);
+
+        assert!(
+            short_line.is_ok_and(|l| l.is_some_and(|l| l == b"GET / HTTP/1.1\r\n")),
+            "Line within the limit must be read"
+        );
+        assert!(
+            long_line.is_ok_and(|l| l.is_none()),
+            "Line exceeding the limit must be rejected"
+        );
+    }
+
+    fn parse(raw_request: &[u8]) -> Result<Request, ParseError> {
+        let limits = RequestLimits {
+            max_request_line_length: 64,
+            max_header_count: 2,
+            max_header_size: 64,
+            max_body_size: 16,
+            max_decompressed_body_size: 16,
+        };
+
+        Request::parse(&mut &raw_request[..], &limits)
+    }
+
+    fn assert_parse_error(
+        raw_request: &[u8],
+        expected_code: u16,
+        is_expected: fn(&ParseError) -> bool,
+    ) {
+        let raw_request_str = String::from_utf8_lossy(raw_request);
+
+        match parse(raw_request) {
+            Ok(_) => panic!("Request {:?} must not be parsed", raw_request_str),
+            Err(e) => {
+                assert!(
+                    is_expected(&e),
+                    "Unexpected error for {:?}: {:?}",
+                    raw_request_str,
+                    e
+                );
+                assert_eq!(
+                    expected_code,
+                    e.status_code(),
+                    "Status code must be {}",
+                    expected_code
+                );
+            }
+        }
+    }
+
+    struct FailingReader(ErrorKind);
+
+    impl Read for FailingReader {
+        fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+            Err(std::io::Error::from(self.0))
+        }
+    }
+
+    #[test]
+    fn parse_must_parse_valid_request() {
+        let request = parse(
+            b"POST /test?qp=1,2 HTTP/1.1\r\nContent-Length: 9\r\nX-Test:value\r\n\r\ntest_body",
+        );
+
+        assert!(request.is_ok(), "Valid request must be parsed");
+        let request = request.unwrap();
+        assert_eq!(
+            RequestMethod::POST,
+            request.method,
+            "Request method must be 'POST'"
+        );
+        assert_eq!("/test", request.url, "Request url must be '/test'");
+        assert_eq!(
+            HttpVersion::Http11,
+            request.version(),
+            "Request version must be 'HTTP/1.1'"
+        );
+        assert_eq!(
+            "test_body",
+            request.body(),
+            "Request body must be 'test_body'"
+        );
+        assert!(
+            request.get_header("X-Test").is_some_and(|h| h == "value"),
+            "Header 'X-Test' must be 'value'"
+        );
+        assert!(
+            request
+                .get_query_param("qp")
+                .is_some_and(|qp| qp == &vec![String::from("1"), String::from("2")]),
+            "Query param 'qp' must contain values '1' and '2'"
+        );
+    }
+
+    #[test]
+    fn parse_must_read_body_headers_case_insensitively() {
+        let mut gzip = GzEncoder::new(Vec::new(), Compression::default());
+        gzip.write_all(b"test_body").unwrap();
+        let gzip = gzip.finish().unwrap();
+        let mut raw_request = format!(
+            "POST /test HTTP/1.1\r\ncontent-length: {}\r\ncontent-encoding: gzip\r\n\r\n",
+            gzip.len()
+        )
+        .into_bytes();
+        raw_request.extend_from_slice(&gzip);
+
+        let request = Request::parse(&mut &raw_request[..], &RequestLimits::default());
+
+        assert!(
+            request.is_ok_and(|request| request.body() == "test_body"),
+            "Body must be read and decoded with lowercase header names"
+        );
+        assert_parse_error(
+            b"POST /test HTTP/1.1\r\ncontent-length: 17\r\n\r\n",
+            413,
+            |e| matches!(e, ParseError::BodyTooLarge(16)),
+        );
+        assert_parse_error(
+            b"POST /test HTTP/1.1\r\ncontent-length: 4\r\ncontent-encoding: br\r\n\r\ntest",
+            415,
+            |e| matches!(e, ParseError::UnsupportedEncoding(_)),
+        );
+    }
+
+    #[test]
+    fn parse_must_reject_bad_request_line() {
+        assert_parse_error(b"GET /test\r\n\r\n", 400, |e| {
+            matches!(e, ParseError::BadRequestLine(_))
+        });
+        assert_parse_error(b"GET \xff HTTP/1.1\r\n\r\n", 400, |e| {
+            matches!(e, ParseError::BadRequestLine(_))
+        });
+        assert_parse_error(b"", 400, |e| matches!(e, ParseError::BadRequestLine(_)));
+    }
+
+    #[test]
+    fn parse_must_reject_unsupported_method() {
+        assert_parse_error(b"BREW /test HTTP/1.1\r\n\r\n", 501, |e| {
+            matches!(e, ParseError::UnsupportedMethod(_))
+        });
+    }
+
+    #[test]
+    fn parse_must_reject_unsupported_version() {
+        assert_parse_error(b"GET /test HTTP/2.0\r\n\r\n", 505, |e| {
+            matches!(e, ParseError::UnsupportedVersion(_))
+        });
+        assert_parse_error(b"GET /test FTP/1.1\r\n\r\n", 505, |e| {
+            matches!(e, ParseError::UnsupportedVersion(_))
+        });
+    }
+
+    #[test]
+    fn parse_must_reject_bad_query_param() {
+        assert_parse_error(b"GET /test?qp HTTP/1.1\r\n\r\n", 400, |e| {
+            matches!(e, ParseError::BadQueryParam(_))
+        });
+    }
+
+    #[test]
+    fn parse_must_reject_bad_header() {
+        assert_parse_error(b"GET /test HTTP/1.1\r\nX-Test\r\n\r\n", 400, |e| {
+            matches!(e, ParseError::BadHeader(_))
+        });
+        assert_parse_error(b"GET /test HTTP/1.1\r\nX Test: value\r\n\r\n", 400, |e| {
+            matches!(e, ParseError::BadHeader(_))
+        });
+    }
+
+    #[test]
+    fn parse_must_reject_invalid_content_length() {
+        assert_parse_error(
+            b"POST /test HTTP/1.1\r\nContent-Length: ten\r\n\r\n",
+            400,
+            |e| matches!(e, ParseError::InvalidContentLength(_)),
+        );
+    }
+
+    #[test]
+    fn parse_must_reject_bad_body() {
+        assert_parse_error(
+            b"POST /test HTTP/1.1\r\nContent-Length: 1\r\n\r\n\xff",
+            400,
+            |e| matches!(e, ParseError::BadBody(_)),
+        );
+        assert_parse_error(
+            b"POST /test HTTP/1.1\r\nContent-Length: 4\r\nContent-Encoding: gzip\r\n\r\ntest",
+            400,
+            |e| matches!(e, ParseError::BadBody(_)),
+        );
+    }
+
+    #[test]
+    fn parse_must_reject_incomplete_body() {
+        assert_parse_error(
+            b"POST /test HTTP/1.1\r\nContent-Length: 9\r\n\r\ntest",
+            400,
+            |e| matches!(e, ParseError::IncompleteBody),
+        );
+    }
+
+    #[test]
+    fn parse_must_reject_too_long_request_line() {
+        let raw_request = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(64));
+
+        assert_parse_error(raw_request.as_bytes(), 414, |e| {
+            matches!(e, ParseError::RequestLineTooLong(64))
+        });
+    }
+
+    #[test]
+    fn parse_must_reject_too_large_headers() {
+        let raw_request = format!("GET /test HTTP/1.1\r\nX-Test: {}\r\n\r\n", "a".repeat(64));
+
+        assert_parse_error(raw_request.as_bytes(), 431, |e| {
+            matches!(e, ParseError::HeadersTooLarge(64))
+        });
+        assert_parse_error(
+            b"GET /test HTTP/1.1\r\nA: 1\r\nB: 2\r\nC: 3\r\n\r\n",
+            431,
+            |e| matches!(e, ParseError::HeadersTooLarge(2)),
+        );
+    }
+
+    #[test]
+    fn parse_must_reject_too_large_body() {
+        assert_parse_error(
+            b"POST /test HTTP/1.1\r\nContent-Length: 17\r\n\r\n",
+            413,
+            |e| matches!(e, ParseError::BodyTooLarge(16)),
+        );
+    }
+
+    #[test]
+    fn parse_must_reject_unsupported_encoding() {
+        assert_parse_error(
+            b"POST /test HTTP/1.1\r\nContent-Length: 4\r\nContent-Encoding: br\r\n\r\ntest",
+            415,
+            |e| matches!(e, ParseError::UnsupportedEncoding(_)),
+        );
+    }
+
+    #[test]
+    fn parse_must_report_timeout() {
+        let result = Request::parse(
+            &mut FailingReader(ErrorKind::WouldBlock),
+            &RequestLimits::default(),
+        );
+
+        assert!(
+            result.is_err_and(|e| matches!(e, ParseError::Timeout) && e.status_code() == 408),
+            "Read timeout must be reported as 408"
+        );
+    }
+
+    #[test]
+    fn parse_must_report_io_error() {
+        let result = Request::parse(
+            &mut FailingReader(ErrorKind::ConnectionReset),
+            &RequestLimits::default(),
+        );
+
+        assert!(
+            result.is_err_and(|e| matches!(e, ParseError::Io(_)) && e.status_code() == 500),
+            "I/O error must be reported as 500"
+        );
+    }
+
+    #[test]
+    fn is_request_complete_must_wait_for_headers_and_body() {
+        let limits = RequestLimits::default();
+
+        assert!(!is_request_complete(b"GET / HTTP/1.1\r\n", &limits));
+        assert!(is_request_complete(b"GET / HTTP/1.1\r\n\r\n", &limits));
+        assert!(!is_request_complete(
+            b"POST / HTTP/1.1\r\nContent-Length: 4\r\n\r\nte",
+            &limits
+        ));
+        assert!(is_request_complete(
+            b"POST / HTTP/1.1\r\nContent-Length: 4\r\n\r\ntest",
+            &limits
+        ));
+        assert!(
+            !is_request_complete(b"POST / HTTP/1.1\r\ncontent-length: 4\r\n\r\nte", &limits),
+            "Lowercase Content-Length must be waited for"
+        );
+        assert!(
+            is_request_complete(b"POST / HTTP/1.1\r\nContent-Length: ten\r\n\r\n", &limits),
+            "Invalid Content-Length must be left to the parser"
+        );
+    }
+
+    #[test]
+    fn is_request_complete_must_stop_buffering_oversized_head() {
+        let limits = RequestLimits {
+            max_request_line_length: 8,
+            max_header_count: 1,
+            max_header_size: 8,
+            ..RequestLimits::default()
+        };
+
+        assert!(
+            is_request_complete(format!("GET /{}", "a".repeat(32)).as_bytes(), &limits),
+            "Head exceeding the limits must be passed to the parser"
+        );
+        assert!(
+            is_request_complete(
+                format!(
+                    "GET / HTTP/1.1\r\nA: {}\r\nContent-Length: 4\r\n\r\n",
+                    "a".repeat(32)
+                )
+                .as_bytes(),
+                &limits
+            ),
+            "Whole head exceeding the limits must not wait for the body"
+        );
+    }
 }
@@ -1,23 +1,36 @@
 use std::{
     collections::HashMap,
     fmt::Display,
-    io::{Error, Write},
-    net::TcpStream,
+    io::{Error, ErrorKind, Read, Write},
 };
 
+use crate::http::version::HttpVersion;
+
 pub struct Response {
     code: u16,
+    version: HttpVersion,
     body: String,
     headers: HashMap<String, String>,
+    repeated_headers: Vec<(String, String)>,
+    body_stream: Option<BodyStream>,
 }
 
 #[derive(Default)]
 pub struct ResponseBuilder {
     code: u16,
+    version: HttpVersion,
     body: String,
     headers: HashMap<String, String>,
+    repeated_headers: Vec<(String, String)>,
+    body_stream: Option<BodyStream>,
 }
 
+/// A body that is read while the response is written
+type BodyStream = Box<dyn Read + Send + 'static>;
+
+/// Size of the chunks a streamed body is written in
+const STREAM_CHUNK_SIZE: usize = 8192;
+
 impl Response {
     pub fn builder() -> ResponseBuilder {
         ResponseBuilder::default()
@@ -26,8 +39,11 @@ impl Response {
     fn new(builder: ResponseBuilder) -> Response {
         Response {
             code: builder.code,
+            version: builder.version,
             body: builder.body,
             headers: builder.headers,
+            repeated_headers: builder.repeated_headers,
+            body_stream: builder.body_stream,
         }
     }
 
@@ -35,13 +51,138 @@ impl Response {
         self.code
     }
 
+    pub fn version(&self) -> HttpVersion {
+        self.version
+    }
+
     pub fn body(&self) -> &str {
         &self.body
     }
 
-    pub fn write(self, stream: &mut TcpStream) -> Result<(), Error> {
-        let response_string = self.to_string();
-        stream.write_all(response_string.as_bytes())
+    /// Looks the header up by name, ignoring the case of the name
+    pub fn get_header(&self, header_name: &str) -> Option<&String> {
+        self.headers
+            .get(header_name)
+            .or_else(|| {
+                self.headers
+                    .iter()
+                    .find(|(name, _)| name.eq_ignore_ascii_case(header_name))
+                    .map(|(_, value)| value)
+            })
+            .or_else(|| {
+                self.repeated_headers
+                    .iter()
+                    .find(|(name, _)| name.eq_ignore_ascii_case(header_name))
+                    .map(|(_, value)| value)
+            })
+    }
+
+    pub fn headers(&self) -> &HashMap<String, String> {
+        &self.headers
+    }
+
+    /// Headers sent as lines of their own, in the order they were appended
+    pub fn repeated_headers(&self) -> &[(String, String)] {
+        &self.repeated_headers
+    }
+
+    /// Sets the header, replacing the value the handler has set
+    pub fn with_header(
+        mut self,
+        header_name: impl Into<String>,
+        header_value: impl Into<String>,
+    ) -> Response {
+        let header_name = Into::into(header_name);
+        self.repeated_headers
+            .retain(|(name, _)| !name.eq_ignore_ascii_case(&header_name));
+        self.headers.insert(header_name, Into::into(header_value));
+
+        self
+    }
+
+    pub(crate) fn has_body_stream(&self) -> bool {
+        self.body_stream.is_some()
+    }
+
+    /// Wraps the streamed body, if there is one
+    pub(crate) fn map_body_stream<R: Read + Send + 'static>(
+        mut self,
+        map: impl FnOnce(BodyStream) -> R,
+    ) -> Response {
+        self.body_stream = self
+            .body_stream
+            .take()
+            .map(|body| Box::new(map(body)) as BodyStream);
+
+        self
+    }
+
+    /// Adapts the response to the protocol version of the request it answers
+    ///
+    /// HTTP/1.0 clients do not understand chunked encoding and expect the connection
+    /// to be closed after the response unless they asked to keep it alive
+    pub fn for_version(mut self, version: HttpVersion) -> Response {
+        self.version = version;
+
+        if !version.supports_chunked_encoding() {
+            self.headers
+                .retain(|name, _| !name.eq_ignore_ascii_case("Transfer-Encoding"));
+        }
+        if !version.is_persistent_by_default() && self.get_header("Connection").is_none() {
+            self.headers
+                .insert(String::from("Connection"), String::from("close"));
+        }
+
+        self
+    }
+
+    /// Writes the response, a streamed body is copied to the stream as it is read
+    ///
+    /// Without a `Content-Length` header a streamed body is sent in chunks,
+    /// or delimited by closing the connection if the client does not support chunked encoding
+    pub fn write(mut self, stream: &mut impl Write) -> Result<(), Error> {
+        if self.body_stream.is_none() {
+            let response_string = self.to_string();
+            stream.write_all(response_string.as_bytes())?;
+            return stream.flush();
+        }
+
+        let has_content_length = self.get_header("Content-Length").is_some();
+        let is_chunked = !has_content_length && self.version.supports_chunked_encoding();
+        if is_chunked {
+            self.headers
+                .insert(String::from("Transfer-Encoding"), String::from("chunked"));
+        } else if !has_content_length {
+            self.headers
+                .insert(String::from("Connection"), String::from("close"));
+        }
+        stream.write_all(self.to_string().as_bytes())?;
+
+        let Some(mut body) = self.body_stream.take() else {
+            return stream.flush();
+        };
+        let mut chunk = vec![0; STREAM_CHUNK_SIZE];
+        loop {
+            let read = match body.read(&mut chunk) {
+                Ok(0) => break,
+                Ok(read) => read,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+
+            if is_chunked {
+                write!(stream, "{:x}\r\n", read)?;
+                stream.write_all(&chunk[..read])?;
+                stream.write_all(b"\r\n")?;
+            } else {
+                stream.write_all(&chunk[..read])?;
+            }
+        }
+        if is_chunked {
+            stream.write_all(b"0\r\n\r\n")?;
+        }
+
+        stream.flush()
     }
 }
 
@@ -53,8 +194,67 @@ impl Display for Response {
             .fold(String::new(), |acc, (name, value)| {
                 acc + name + ": " + value + "\r\n"
             });
+        let headers = self
+            .repeated_headers
+            .iter()
+            .fold(headers, |acc, (name, value)| {
+                acc + name + ": " + value + "\r\n"
+            });
+        let content_length =
+            if self.get_header("Content-Length").is_some() || self.body_stream.is_some() {
+                String::default()
+            } else {
+                format!("Content-Length: {}\r\n", self.body.len())
+            };
+
+        write!(
+            f,
+            "{} {} {}\r\n{}{}\r\n{}",
+            self.version,
+            self.code,
+            reason_phrase(self.code),
+            headers,
+            content_length,
+            self.body
+        )
+    }
+}
 
-        write!(f, "HTTP/1.1 {}\r\n{}\r\n{}", self.code, headers, self.body)
+fn reason_phrase(code: u16) -> &'static str {
+    match code {
+        100 => "Continue",
+        101 => "Switching Protocols",
+        200 => "OK",
+        201 => "Created",
+        202 => "Accepted",
+        204 => "No Content",
+        206 => "Partial Content",
+        301 => "Moved Permanently",
+        302 => "Found",
+        303 => "See Other",
+        304 => "Not Modified",
+        307 => "Temporary Redirect",
+        308 => "Permanent Redirect",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        409 => "Conflict",
+        411 => "Length Required",
+        413 => "Content Too Large",
+        414 => "URI Too Long",
+        415 => "Unsupported Media Type",
+        429 => "Too Many Requests",
+        431 => "Request Header Fields Too Large",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        504 => "Gateway Timeout",
+        505 => "HTTP Version Not Supported",
+        _ => "",
     }
 }
 
@@ -65,12 +265,25 @@ impl ResponseBuilder {
         self
     }
 
+    pub fn version(mut self, version: HttpVersion) -> ResponseBuilder {
+        self.version = version;
+
+        self
+    }
+
     pub fn body(mut self, body: impl Into<String>) -> ResponseBuilder {
         self.body = Into::into(body);
 
         self
     }
 
+    /// Streams the body from the reader while the response is written, instead of the body
+    pub fn body_stream(mut self, body: impl Read + Send + 'static) -> ResponseBuilder {
+        self.body_stream = Some(Box::new(body));
+
+        self
+    }
+
     pub fn add_header(
         mut self,
         header_name: impl Into<String>,
@@ -82,6 +295,20 @@ impl ResponseBuilder {
         self
     }
 
+    /// Adds the header as a line of its own, even if a header of that name was added before
+    ///
+    /// Used for headers that must not be folded into one line, such as `Set-Cookie`
+    pub fn append_header(
+        mut self,
+        header_name: impl Into<String>,
+        header_value: impl Into<String>,
+    ) -> ResponseBuilder {
+        self.repeated_headers
+            .push((Into::into(header_name), Into::into(header_value)));
+
+        self
+    }
+
     pub fn build(self) -> Response {
         Response::new(self)
     }
@@ -102,4 +329,81 @@ mod tests {
             "Response body must be 'test_body"
         );
     }
+
+    #[test]
+    fn response_must_be_written_with_status_line_and_content_length() {
+        let response = Response::builder().code(404).body("test_body").build();
+
+        assert_eq!(
+            "HTTP/1.1 404 Not Found\r\nContent-Length: 9\r\n\r\ntest_body",
+            response.to_string(),
+            "Response must be written correctly"
+        );
+    }
+
+    #[test]
+    fn response_must_stream_body_in_chunks() {
+        let mut output = Vec::new();
+
+        Response::builder()
+            .code(200)
+            .body_stream(&b"test_body"[..])
+            .build()
+            .write(&mut output)
+            .unwrap();
+
+        assert_eq!(
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n9\r\ntest_body\r\n0\r\n\r\n",
+            String::from_utf8(output).unwrap(),
+            "Streamed body must be chunked"
+        );
+    }
+
+    #[test]
+    fn response_must_not_chunk_streamed_body_with_content_length_in_any_case() {
+        let mut output = Vec::new();
+        Response::builder()
+            .code(200)
+            .add_header("content-length", "9")
+            .body_stream(&b"test_body"[..])
+            .build()
+            .write(&mut output)
+            .unwrap();
+
+        assert_eq!(
+            "HTTP/1.1 200 OK\r\ncontent-length: 9\r\n\r\ntest_body",
+            String::from_utf8(output).unwrap(),
+            "Streamed body with a length must not be chunked"
+        );
+    }
+
+    #[test]
+    fn response_for_http_10_must_close_connection_and_not_be_chunked() {
+        let response = Response::builder()
+            .code(200)
+            .add_header("Transfer-Encoding", "chunked")
+            .body("test_body")
+            .build()
+            .for_version(HttpVersion::Http10);
+
+        assert_eq!(
+            HttpVersion::Http10,
+            response.version(),
+            "Response version must be 'HTTP/1.0'"
+        );
+        assert!(
+            response
+                .get_header("Connection")
+                .is_some_and(|h| h == "close"),
+            "Connection must be closed by default"
+        );
+        assert!(
+            response.get_header("Transfer-Encoding").is_none(),
+            "Response must not be chunked"
+        );
+        assert!(
+            response.to_string().starts_with("HTTP/1.0 200 OK\r\n"),
+            "Status line must contain 'HTTP/1.0'"
+        );
+    }
 }
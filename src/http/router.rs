@@ -0,0 +1,626 @@
+use std::{
+    error::Error,
+    net::IpAddr,
+    panic::{self, AssertUnwindSafe},
+    sync::{Arc, RwLock},
+    time::Instant,
+};
+#[cfg(feature = "async")]
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{mpsc, OnceLock},
+};
+
+use log::{error, warn};
+#[cfg(feature = "async")]
+use tokio::runtime::{Handle, Runtime, RuntimeFlavor};
+
+use crate::{
+    http::{
+        access_log::{AccessLog, PendingEntry},
+        connection::Connection,
+        metrics::HttpMetrics,
+        request::{error::ParseError, limits::RequestLimits, matcher::RequestMatcher, Request},
+        response::Response,
+    },
+    metrics::Registry,
+    trace::{generate_request_id, Span, SpanContext},
+};
+
+/// Header carrying the request ID, it is echoed in the response
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+/// The longest incoming request ID that is kept, longer ones are replaced by a generated one
+const MAX_REQUEST_ID_LENGTH: usize = 200;
+
+pub type HandlerFn = Box<dyn Fn(Request) -> Response + Send + Sync + 'static>;
+#[cfg(feature = "async")]
+pub type ResponseFuture = Pin<Box<dyn Future<Output = Response> + Send + 'static>>;
+#[cfg(feature = "async")]
+pub type AsyncHandlerFn = Box<dyn Fn(Request) -> ResponseFuture + Send + Sync + 'static>;
+
+/// A registered handler function, either returning the response or a future resolving to it
+pub enum Handler {
+    Sync(HandlerFn),
+    #[cfg(feature = "async")]
+    Async(AsyncHandlerFn),
+}
+
+/// What a handler passed to `ServerBuilder::register_handler` returns
+///
+/// Implemented for `Response` and, with the `async` feature, for futures resolving to one,
+/// so plain and async functions are registered the same way
+pub trait HandlerResponse: Send + 'static {
+    fn into_handler(handler_fn: impl Fn(Request) -> Self + Send + Sync + 'static) -> Handler
+    where
+        Self: Sized;
+}
+
+pub(crate) struct RequestHandler {
+    matcher: RequestMatcher,
+    handler: Handler,
+}
+
+/// Turns incoming requests into responses using the registered handlers
+///
+/// Shared by every transport the server listens on and by the test client
+pub(crate) struct Router {
+    handlers: Vec<RequestHandler>,
+    /// Replaced as a whole on reload, requests keep the snapshot they have been parsed with
+    limits: RwLock<Arc<RequestLimits>>,
+    access_log: Option<Arc<AccessLog>>,
+    metrics: HttpMetrics,
+}
+
+impl HandlerResponse for Response {
+    fn into_handler(handler_fn: impl Fn(Request) -> Self + Send + Sync + 'static) -> Handler {
+        Handler::Sync(Box::new(handler_fn))
+    }
+}
+
+#[cfg(feature = "async")]
+impl<F> HandlerResponse for F
+where
+    F: Future<Output = Response> + Send + 'static,
+{
+    fn into_handler(handler_fn: impl Fn(Request) -> Self + Send + Sync + 'static) -> Handler {
+        Handler::Async(Box::new(move |request| Box::pin(handler_fn(request))))
+    }
+}
+
+impl RequestHandler {
+    /// Registers a plain or async handler function
+    pub(crate) fn from_fn<R: HandlerResponse>(
+        matcher: RequestMatcher,
+        handler_fn: impl Fn(Request) -> R + Send + Sync + 'static,
+    ) -> RequestHandler {
+        RequestHandler {
+            matcher,
+            handler: R::into_handler(handler_fn),
+        }
+    }
+
+    pub(crate) fn new(matcher: RequestMatcher, handler_fn: HandlerFn) -> RequestHandler {
+        RequestHandler {
+            matcher,
+            handler: Handler::Sync(handler_fn),
+        }
+    }
+}
+
+impl Router {
+    pub(crate) fn new(handlers: Vec<RequestHandler>, limits: RequestLimits) -> Router {
+        Router {
+            metrics: http_metrics(Arc::new(Registry::new()), &handlers),
+            handlers,
+            limits: RwLock::new(Arc::new(limits)),
+            access_log: None,
+        }
+    }
+
+    /// Records the handled requests into the registry instead of a private one
+    pub(crate) fn with_metrics(mut self, registry: Arc<Registry>) -> Router {
+        self.metrics = http_metrics(registry, &self.handlers);
+
+        self
+    }
+
+    /// Records every request processed from a connection in the access log
+    pub(crate) fn with_access_log(mut self, access_log: Arc<AccessLog>) -> Router {
+        self.access_log = Some(access_log);
+
+        self
+    }
+
+    pub(crate) fn limits(&self) -> Arc<RequestLimits> {
+        Arc::clone(&self.limits.read().unwrap())
+    }
+
+    /// Applies the limits to the requests read from now on
+    pub(crate) fn set_limits(&self, limits: RequestLimits) {
+        *self.limits.write().unwrap() = Arc::new(limits);
+    }
+
+    /// Reads a request from the connection and writes the response back
+    ///
+    /// `client` is the address of the peer for the access log, if it has one
+    pub(crate) fn handle_connection(
+        &self,
+        connection: &mut impl Connection,
+        client: Option<IpAddr>,
+    ) {
+        let response = self.process(connection, client);
+
+        if let Err(e) = response.write(connection) {
+            warn!("Unable to write a response: {}", e);
+        }
+    }
+
+    /// Reads a request from the connection and produces the response to it
+    pub(crate) fn process(
+        &self,
+        connection: &mut impl Connection,
+        client: Option<IpAddr>,
+    ) -> Response {
+        let started = Instant::now();
+
+        match Request::parse(connection, &self.limits()) {
+            Ok(mut request) => {
+                request.set_client(client);
+                let entry = self.pending_entry(client, started, Some(&request));
+                let response = self.dispatch(request);
+                self.log_access(entry, response)
+            }
+            Err(e) => self.reject(e, client, started),
+        }
+    }
+
+    /// Parses the buffered request and produces the response to it from within a tokio runtime
+    #[cfg(feature = "async")]
+    pub(crate) async fn process_async(
+        &self,
+        raw_request: &[u8],
+        client: Option<IpAddr>,
+    ) -> Response {
+        let started = Instant::now();
+
+        match Request::parse(&mut &raw_request[..], &self.limits()) {
+            Ok(mut request) => {
+                request.set_client(client);
+                let entry = self.pending_entry(client, started, Some(&request));
+                let response = self.dispatch_async(request).await;
+                self.log_access(entry, response)
+            }
+            Err(e) => self.reject(e, client, started),
+        }
+    }
+
+    /// Answers a request that could not be read, `started` is when reading it began
+    pub(crate) fn reject(
+        &self,
+        error: ParseError,
+        client: Option<IpAddr>,
+        started: Instant,
+    ) -> Response {
+        self.metrics.parse_error(error.status_code());
+        let entry = self.pending_entry(client, started, None);
+        self.log_access(entry, error_response(error))
+    }
+
+    /// Passes the request to the first matching handler within a span of its trace
+    ///
+    /// A panicking handler results in 500 instead of taking the worker thread down.
+    /// Async handlers are driven to completion on a shared fallback runtime
+    pub(crate) fn dispatch(&self, mut request: Request) -> Response {
+        let version = request.version();
+        let span = start_span(&mut request);
+        let handler = self.find_handler(&request);
+        let in_flight = self
+            .metrics
+            .start(request.method(), handler.map(|(index, _)| index));
+
+        let response = match handler.map(|(_, h)| &h.handler) {
+            Some(Handler::Sync(handler_fn)) => {
+                let _entered = span.enter();
+                call_sync(handler_fn, request)
+            }
+            #[cfg(feature = "async")]
+            Some(Handler::Async(handler_fn)) => {
+                match panic::catch_unwind(AssertUnwindSafe(|| handler_fn(request))) {
+                    Ok(future) => block_on(span.instrument(future)),
+                    Err(_) => internal_error_response(),
+                }
+            }
+            None => not_found_response(),
+        };
+        in_flight.finish(&response);
+        let response = response.with_header(REQUEST_ID_HEADER, span.request_id());
+        span.finish();
+
+        response.for_version(version)
+    }
+
+    /// Passes the request to the first matching handler from within a tokio runtime
+    ///
+    /// Async handlers run as separate tasks so a panic only fails their own request,
+    /// sync handlers are allowed to block the current worker thread
+    #[cfg(feature = "async")]
+    pub(crate) async fn dispatch_async(&self, mut request: Request) -> Response {
+        let version = request.version();
+        let span = start_span(&mut request);
+        let handler = self.find_handler(&request);
+        let in_flight = self
+            .metrics
+            .start(request.method(), handler.map(|(index, _)| index));
+
+        let response = match handler.map(|(_, h)| &h.handler) {
+            Some(Handler::Sync(handler_fn)) => tokio::task::block_in_place(|| {
+                let _entered = span.enter();
+                call_sync(handler_fn, request)
+            }),
+            Some(Handler::Async(handler_fn)) => {
+                match panic::catch_unwind(AssertUnwindSafe(|| handler_fn(request))) {
+                    Ok(future) => tokio::spawn(span.instrument(future))
+                        .await
+                        .unwrap_or_else(|_| internal_error_response()),
+                    Err(_) => internal_error_response(),
+                }
+            }
+            None => not_found_response(),
+        };
+        in_flight.finish(&response);
+        let response = response.with_header(REQUEST_ID_HEADER, span.request_id());
+        span.finish();
+
+        response.for_version(version)
+    }
+
+    fn pending_entry(
+        &self,
+        client: Option<IpAddr>,
+        started: Instant,
+        request: Option<&Request>,
+    ) -> Option<PendingEntry> {
+        self.access_log
+            .as_ref()
+            .map(|_| PendingEntry::new(client, started, request))
+    }
+
+    fn log_access(&self, entry: Option<PendingEntry>, response: Response) -> Response {
+        match (&self.access_log, entry) {
+            (Some(access_log), Some(entry)) => entry.log(access_log, response),
+            _ => response,
+        }
+    }
+
+    /// Returns the first matching handler along with its index
+    fn find_handler(&self, request: &Request) -> Option<(usize, &RequestHandler)> {
+        self.handlers
+            .iter()
+            .enumerate()
+            .find(|(_, h)| h.matcher.matches(request))
+    }
+}
+
+fn http_metrics(registry: Arc<Registry>, handlers: &[RequestHandler]) -> HttpMetrics {
+    HttpMetrics::new(
+        registry,
+        handlers
+            .iter()
+            .map(|h| (*h.matcher.method(), h.matcher.url())),
+    )
+}
+
+/// Assigns the request its ID and starts the span it is handled in,
+/// continuing the trace of the `traceparent` header if there is a valid one
+fn start_span(request: &mut Request) -> Span {
+    let request_id = request
+        .get_header(REQUEST_ID_HEADER)
+        .filter(|id| is_valid_request_id(id))
+        .cloned()
+        .unwrap_or_else(generate_request_id);
+    let parent = request.get_header("traceparent").and_then(|traceparent| {
+        SpanContext::parse(
+            traceparent,
+            request.get_header("tracestate").map(String::as_str),
+        )
+    });
+
+    let span = Span::new(
+        format!("{} {}", request.method(), request.url()),
+        request_id.clone(),
+        parent.as_ref(),
+    );
+    request.set_trace(request_id, span.context().clone());
+
+    span
+}
+
+fn is_valid_request_id(id: &str) -> bool {
+    !id.is_empty() && id.len() <= MAX_REQUEST_ID_LENGTH && id.chars().all(|c| c.is_ascii_graphic())
+}
+
+fn call_sync(handler_fn: &HandlerFn, request: Request) -> Response {
+    match panic::catch_unwind(AssertUnwindSafe(|| handler_fn(request))) {
+        Ok(response) => response,
+        Err(_) => internal_error_response(),
+    }
+}
+
+/// Drives the response of an async handler to completion on the fallback runtime
+///
+/// Blocking on a runtime panics from within another one, e.g. when a test client is used
+/// in an async test, so there the future is spawned and the thread waits for its result
+#[cfg(feature = "async")]
+fn block_on(future: impl Future<Output = Response> + Send + 'static) -> Response {
+    let Ok(handle) = Handle::try_current() else {
+        return panic::catch_unwind(AssertUnwindSafe(|| fallback_runtime().block_on(future)))
+            .unwrap_or_else(|_| internal_error_response());
+    };
+
+    let (sender, receiver) = mpsc::channel();
+    fallback_runtime().spawn(async move {
+        let _ = sender.send(future.await);
+    });
+    let received = match handle.runtime_flavor() {
+        RuntimeFlavor::MultiThread => tokio::task::block_in_place(|| receiver.recv()),
+        _ => receiver.recv(),
+    };
+
+    // The sender is dropped without a response if the future panics
+    received.unwrap_or_else(|_| internal_error_response())
+}
+
+/// Runtime for async handlers dispatched outside of the async I/O mode
+#[cfg(feature = "async")]
+fn fallback_runtime() -> &'static Runtime {
+    static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+
+    RUNTIME.get_or_init(|| {
+        tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .thread_name("async-handler")
+            .enable_all()
+            .build()
+            .expect("Async handler runtime must be created")
+    })
+}
+
+/// Seconds a client is asked to wait before retrying a request refused under overload
+const RETRY_AFTER_SECS: u64 = 1;
+
+/// Answers a request that has not been processed because the server is overloaded
+pub(crate) fn overloaded_response() -> Response {
+    Response::builder()
+        .code(503)
+        .add_header("Retry-After", RETRY_AFTER_SECS.to_string())
+        .body("Server is overloaded, try again later")
+        .build()
+}
+
+fn not_found_response() -> Response {
+    Response::builder()
+        .code(404)
+        .body("Requested page has not been found")
+        .build()
+}
+
+pub(crate) fn error_response(error: ParseError) -> Response {
+    match error.status_code() {
+        500 => server_error_response(error),
+        code => Response::builder()
+            .code(code)
+            .body(error.to_string())
+            .build(),
+    }
+}
+
+fn server_error_response<E>(error: E) -> Response
+where
+    E: Error,
+{
+    error!("Unable to process a request: {}", error);
+
+    internal_error_response()
+}
+
+fn internal_error_response() -> Response {
+    Response::builder()
+        .code(500)
+        .body("Something went wrong")
+        .build()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+    use crate::http::{
+        access_log::{AccessLogConfig, AccessLogFormat, AccessLogTarget},
+        connection::MemoryConnection,
+    };
+
+    fn router() -> Router {
+        let handlers = vec![RequestHandler::new(
+            RequestMatcher::post().url("/test").build(),
+            Box::new(|request| {
+                Response::builder()
+                    .code(200)
+                    .body(request.body().to_uppercase())
+                    .build()
+            }),
+        )];
+
+        Router::new(handlers, RequestLimits::default())
+    }
+
+    fn serve(raw_request: &str) -> String {
+        let mut connection = MemoryConnection::new(raw_request);
+
+        router().handle_connection(&mut connection, None);
+
+        String::from_utf8(connection.into_output()).unwrap()
+    }
+
+    #[test]
+    fn handle_connection_must_dispatch_request_to_matching_handler() {
+        let response = serve(
+            "POST /test HTTP/1.1\r\nX-Request-Id: test_id\r\nContent-Length: 9\r\n\r\ntest_body",
+        );
+
+        assert_eq!(
+            "HTTP/1.1 200 OK\r\nX-Request-Id: test_id\r\nContent-Length: 9\r\n\r\nTEST_BODY",
+            response,
+            "Request must be handled by the registered handler"
+        );
+    }
+
+    #[test]
+    fn handle_connection_must_respond_with_not_found() {
+        let response = serve("GET /unknown HTTP/1.1\r\n\r\n");
+
+        assert!(
+            response.starts_with("HTTP/1.1 404 Not Found\r\n"),
+            "Unknown route must be answered with 404"
+        );
+    }
+
+    #[test]
+    fn handle_connection_must_respond_with_parse_error_status() {
+        let response = serve("GET /test HTTP/3\r\n\r\n");
+
+        assert!(
+            response.starts_with("HTTP/1.1 505 HTTP Version Not Supported\r\n"),
+            "Parse error must be answered with its status code"
+        );
+    }
+
+    #[test]
+    fn handle_connection_must_close_http_10_connections() {
+        let response = serve("POST /test HTTP/1.0\r\nContent-Length: 9\r\n\r\ntest_body");
+
+        assert!(
+            response.starts_with("HTTP/1.0 200 OK\r\n")
+                && response.contains("Connection: close\r\n"),
+            "HTTP/1.0 response must close the connection"
+        );
+    }
+
+    #[test]
+    fn handle_connection_must_write_access_log() {
+        let path =
+            std::env::temp_dir().join(format!("rust_web_server_router_{}.log", std::process::id()));
+        let access_log = AccessLog::open(AccessLogConfig {
+            target: AccessLogTarget::File(path.clone()),
+            format: AccessLogFormat::Common,
+        })
+        .unwrap();
+        let router = router().with_access_log(Arc::new(access_log));
+        let client = Some(IpAddr::V4(Ipv4Addr::LOCALHOST));
+
+        router.handle_connection(
+            &mut MemoryConnection::new("POST /test HTTP/1.1\r\nContent-Length: 9\r\n\r\ntest_body"),
+            client,
+        );
+        router.handle_connection(&mut MemoryConnection::new("GET /test HTTP/3\r\n\r\n"), None);
+
+        let access_log = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let lines: Vec<&str> = access_log.lines().collect();
+        assert_eq!(2, lines.len(), "Every request must be logged");
+        assert!(
+            lines[0].starts_with("127.0.0.1 - - [")
+                && lines[0].ends_with("] \"POST /test HTTP/1.1\" 200 9"),
+            "Handled request must be logged with its client, request line, status and size"
+        );
+        assert!(
+            lines[1].starts_with("- - - [") && lines[1].contains("] \"-\" 505 "),
+            "Unparsed request must be logged with its status"
+        );
+    }
+
+    #[test]
+    fn dispatch_must_assign_request_id_and_continue_trace() {
+        let handlers = vec![RequestHandler::new(
+            RequestMatcher::get().url("/trace").build(),
+            Box::new(|request| {
+                let current_span = crate::trace::current_span().unwrap();
+                let trace_context = request.trace_context().unwrap();
+                Response::builder()
+                    .code(200)
+                    .body(format!(
+                        "{} {} {}",
+                        request.id(),
+                        current_span.request_id,
+                        trace_context.traceparent()
+                    ))
+                    .build()
+            }),
+        )];
+        let router = Router::new(handlers, RequestLimits::default());
+
+        let response = router.dispatch(
+            Request::builder()
+                .url("/trace")
+                .add_header(
+                    "traceparent",
+                    "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01",
+                )
+                .build(),
+        );
+
+        let request_id = response.get_header(REQUEST_ID_HEADER).unwrap();
+        let body: Vec<&str> = response.body().split(' ').collect();
+        assert_eq!(32, request_id.len(), "Request ID must be generated");
+        assert_eq!(
+            [request_id.as_str(), request_id.as_str()],
+            body[..2],
+            "Handler must see the request ID on the request and in the current span"
+        );
+        assert!(
+            body[2].starts_with("00-0af7651916cd43dd8448eb211c80319c-")
+                && !body[2].contains("b7ad6b7169203331")
+                && body[2].ends_with("-01"),
+            "Trace must be continued in a new span"
+        );
+    }
+
+    #[test]
+    fn dispatch_must_read_trace_headers_case_insensitively() {
+        let handlers = vec![RequestHandler::new(
+            RequestMatcher::get().url("/trace").build(),
+            Box::new(|request| {
+                let trace_context = request.trace_context().unwrap();
+                Response::builder()
+                    .code(200)
+                    .body(trace_context.traceparent())
+                    .build()
+            }),
+        )];
+        let router = Router::new(handlers, RequestLimits::default());
+
+        let response = router.dispatch(
+            Request::builder()
+                .url("/trace")
+                .add_header("x-request-id", "lowercase-id")
+                .add_header(
+                    "Traceparent",
+                    "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01",
+                )
+                .build(),
+        );
+
+        assert_eq!(
+            Some(&"lowercase-id".to_string()),
+            response.get_header(REQUEST_ID_HEADER),
+            "Request ID must be kept regardless of the header name case"
+        );
+        assert!(
+            response
+                .body()
+                .starts_with("00-0af7651916cd43dd8448eb211c80319c-"),
+            "Trace must be continued regardless of the header name case"
+        );
+    }
+}
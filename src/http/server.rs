@@ -1,48 +1,284 @@
-use clap::Parser;
+use clap::{FromArgMatches, Parser, ValueEnum};
+use log::{info, warn, LevelFilter};
+#[cfg(feature = "tls")]
+use std::io::Write;
 use std::{
-    error::Error,
-    net::{Ipv4Addr, SocketAddr, SocketAddrV4, TcpListener},
+    fmt::Display,
+    io,
+    net::{IpAddr, SocketAddr, ToSocketAddrs},
     ops::RangeInclusive,
+    path::{Path, PathBuf},
     sync::Arc,
+    thread,
+    time::Duration,
 };
 
+#[cfg(target_os = "linux")]
+use crate::concurrent::affinity;
+#[cfg(feature = "async")]
+use crate::http::async_server::AsyncServer;
+#[cfg(feature = "tls")]
+use crate::http::tls::{TlsAcceptor, TlsConfig, TlsError};
+#[cfg(unix)]
+use crate::http::{
+    handover,
+    listener::{self, bind_unix, UnixSocketConfig},
+};
 use crate::{
-    concurrent::thread_pool::ThreadPool,
+    concurrent::{
+        scheduler::OverloadPolicy,
+        thread_pool::{PoolConfig, PoolHandle, ThreadPool, DEFAULT_QUEUE_CAPACITY},
+    },
     http::{
-        request::{matcher::RequestMatcher, Request},
+        access_log::{AccessLog, AccessLogConfig, AccessLogFormat, AccessLogTarget},
+        config,
+        connection::Connection,
+        event_loop::EventLoop,
+        health::{self, HealthChecks, Lifecycle},
+        listener::{bind_tcp, Acceptor, Listener, Stream},
+        metrics::register_pool_metrics,
+        proxy::ProxyHandler,
+        reload::ConfigReloader,
+        request::{limits::RequestLimits, matcher::RequestMatcher, Request},
         response::Response,
+        router::{overloaded_response, HandlerResponse, RequestHandler, Router},
+        test_client::TestClient,
     },
+    logging::LogFormat,
+    metrics::Registry,
 };
+#[cfg(unix)]
+use std::os::fd::RawFd;
 
-pub type HandlerFn = Box<dyn Fn(Request) -> Response + Send + Sync + 'static>;
+/// How the server performs connection I/O
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Default)]
+pub enum IoMode {
+    /// Every connection is read and written by a pool thread
+    #[default]
+    Blocking,
+    /// Connections are multiplexed by a single event loop thread,
+    /// pool threads only run the handlers
+    EventLoop,
+    /// Connections are served by a tokio runtime with as many workers as the max pool size
+    #[cfg(feature = "async")]
+    Async,
+}
 
-struct RequestHandler {
-    matcher: RequestMatcher,
-    handler_fn: HandlerFn,
+impl IoMode {
+    /// Whether connections or handlers run on the thread pool
+    fn uses_pool(self) -> bool {
+        match self {
+            IoMode::Blocking | IoMode::EventLoop => true,
+            #[cfg(feature = "async")]
+            IoMode::Async => false,
+        }
+    }
 }
 
 pub struct Server {
-    pool: ThreadPool,
-    address: SocketAddr,
-    handlers: Arc<Vec<RequestHandler>>,
+    /// Not built in the async I/O mode, which runs everything on its runtime
+    pool: Option<ThreadPool>,
+    #[cfg(feature = "async")]
+    async_workers: usize,
+    io_mode: IoMode,
+    addresses: Vec<SocketAddr>,
+    #[cfg(unix)]
+    unix_socket: Option<UnixSocketConfig>,
+    #[cfg(unix)]
+    inherited_fds: Vec<RawFd>,
+    router: Arc<Router>,
+    read_timeout: Duration,
+    write_timeout: Duration,
+    #[cfg(feature = "tls")]
+    tls: Option<Arc<TlsAcceptor>>,
+    lifecycle: Lifecycle,
+    shutdown_delay: Duration,
+    shutdown_timeout: Duration,
+    config_file: Option<PathBuf>,
 }
 
 pub struct ServerBuilder {
-    pool_size: usize,
-    host: Ipv4Addr,
+    pool: PoolConfig,
+    #[cfg(target_os = "linux")]
+    pin_workers: bool,
+    io_mode: IoMode,
+    host: String,
     port: u16,
+    listen: Vec<String>,
+    #[cfg(unix)]
+    unix_socket: Option<UnixSocketConfig>,
+    #[cfg(unix)]
+    inherited_fds: Vec<RawFd>,
     handlers: Vec<RequestHandler>,
+    /// Whether a proxy is registered, its responses are streamed from the upstream server
+    has_proxies: bool,
+    limits: RequestLimits,
+    read_timeout: Duration,
+    write_timeout: Duration,
+    #[cfg(feature = "tls")]
+    tls: Option<TlsConfig>,
+    access_log: Option<AccessLogConfig>,
+    metrics: Arc<Registry>,
+    metrics_path: Option<String>,
+    health_checks: HealthChecks,
+    health_path: Option<String>,
+    readiness_path: Option<String>,
+    readiness_queue_threshold: usize,
+    shutdown_delay: Duration,
+    shutdown_timeout: Duration,
+    config_file: Option<PathBuf>,
+}
+
+/// Why a server can not be built
+#[derive(Debug)]
+pub enum BuildError {
+    /// The min pool size is greater than the max pool size
+    PoolSize(usize, usize),
+    Io(&'static str, io::Error),
+    #[cfg(feature = "tls")]
+    Tls(TlsError),
+    #[cfg(feature = "tls")]
+    TlsIoMode,
+    ProxyIoMode,
 }
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 pub struct Config {
-    #[arg(long, value_parser = valid_pool_size, default_value_t = 1)]
-    pub pool_size: usize,
-    #[arg(long, default_value = "127.0.0.1", value_parser = valid_address)]
-    pub host: Ipv4Addr,
+    /// TOML file to read options from, by their long names in snake case
+    ///
+    /// `RWS_`-prefixed environment variables override the file, e.g. `RWS_PORT`,
+    /// and are overridden by the command line.
+    /// The file is reloaded on SIGHUP or once modified, the log level and request limits
+    /// are applied to the running server while other changes require a restart
+    #[arg(long)]
+    pub config: Option<PathBuf>,
+    /// Validate the configuration, print the effective one and exit
+    #[arg(long)]
+    pub check_config: bool,
+    /// The most verbose level of log records to write: off, error, warn, info, debug or trace
+    #[arg(long, default_value_t = LevelFilter::Info)]
+    pub log_level: LevelFilter,
+    /// Format of log records
+    #[arg(long, value_enum, default_value_t = LogFormat::Text)]
+    pub log_format: LogFormat,
+    /// Pool threads that are kept alive even when there are no connections
+    #[arg(long, value_parser = valid_pool_size, default_value_t = PoolConfig::default().min_size)]
+    pub min_pool_size: usize,
+    /// The max amount of pool threads, new ones are started when connections queue up
+    #[arg(long, value_parser = valid_pool_size, default_value_t = PoolConfig::default().max_size)]
+    pub max_pool_size: usize,
+    /// Fixed amount of pool threads, overrides the min and max pool sizes
+    #[arg(long, value_parser = valid_pool_size)]
+    pub pool_size: Option<usize>,
+    /// How long a pool thread above the min pool size waits for a connection
+    /// before shutting down, in seconds
+    #[arg(long, default_value_t = PoolConfig::default().keep_alive.as_secs(), value_parser = valid_timeout)]
+    pub pool_keep_alive: u64,
+    /// The max amount of connections waiting for a free pool thread
+    #[arg(long, default_value_t = DEFAULT_QUEUE_CAPACITY, value_parser = valid_limit)]
+    pub queue_capacity: usize,
+    /// What happens to a new connection when the queue is full
+    #[arg(long, value_enum, default_value_t = OverloadPolicy::Block)]
+    pub overload_policy: OverloadPolicy,
+    /// Pin every pool thread to one of the CPUs the server is allowed to run on
+    #[cfg(target_os = "linux")]
+    #[arg(long)]
+    pub pin_workers: bool,
+    /// How connection I/O is performed
+    #[arg(long, value_enum, default_value_t = IoMode::Blocking)]
+    pub io_mode: IoMode,
+    /// IP address or hostname to listen on
+    #[arg(long, default_value = "127.0.0.1", value_parser = valid_host)]
+    pub host: String,
     #[arg(short, long, default_value_t = 8080, value_parser = port_in_range)]
     pub port: u16,
+    /// Address to listen on in the `<host>:<port>` form, can be repeated
+    ///
+    /// Overrides `--host` and `--port`, IPv6 addresses must be enclosed in brackets
+    #[arg(long, value_parser = valid_listen_address)]
+    pub listen: Vec<String>,
+    /// Path of a Unix domain socket to listen on
+    ///
+    /// TCP listeners are only started for explicit `--listen` addresses then
+    #[cfg(unix)]
+    #[arg(long)]
+    pub unix_socket: Option<PathBuf>,
+    /// Permissions of the Unix domain socket file in octal
+    #[cfg(unix)]
+    #[arg(long, default_value = "660", value_parser = valid_socket_mode)]
+    pub unix_socket_mode: u32,
+    /// Descriptor of an inherited listening socket to accept connections from, can be repeated
+    ///
+    /// Replaces the listeners the server would bind, as do the sockets passed by
+    /// systemd socket activation. On SIGUSR2 the server starts a new process
+    /// with its listeners passed this way, then shuts down gracefully once it serves them
+    #[cfg(unix)]
+    #[arg(long, value_parser = clap::value_parser!(RawFd).range(0..))]
+    pub fd: Vec<RawFd>,
+    /// The max length of the request line in bytes
+    #[arg(long, default_value_t = RequestLimits::default().max_request_line_length, value_parser = valid_limit)]
+    pub max_request_line_length: usize,
+    /// The max amount of request headers
+    #[arg(long, default_value_t = RequestLimits::default().max_header_count, value_parser = valid_limit)]
+    pub max_header_count: usize,
+    /// The max length of a single request header line in bytes
+    #[arg(long, default_value_t = RequestLimits::default().max_header_size, value_parser = valid_limit)]
+    pub max_header_size: usize,
+    /// The max size of a request body in bytes
+    #[arg(long, default_value_t = RequestLimits::default().max_body_size, value_parser = valid_limit)]
+    pub max_body_size: usize,
+    /// The max size of a request body after decompression in bytes
+    #[arg(long, default_value_t = RequestLimits::default().max_decompressed_body_size, value_parser = valid_limit)]
+    pub max_decompressed_body_size: usize,
+    /// Timeout for reading a request from a connection in seconds
+    #[arg(long, default_value_t = 30, value_parser = valid_timeout)]
+    pub read_timeout: u64,
+    /// Timeout for writing a response to a connection in seconds
+    #[arg(long, default_value_t = 30, value_parser = valid_timeout)]
+    pub write_timeout: u64,
+    /// Path to the PEM encoded certificate chain, enables HTTPS
+    #[cfg(feature = "tls")]
+    #[arg(long, requires = "tls_key")]
+    pub tls_cert: Option<PathBuf>,
+    /// Path to the PEM encoded private key of the certificate
+    #[cfg(feature = "tls")]
+    #[arg(long, requires = "tls_cert")]
+    pub tls_key: Option<PathBuf>,
+    /// File to append the access log to, `-` writes it to stdout
+    ///
+    /// The file is reopened on SIGHUP, so it can be rotated by logrotate
+    #[arg(long)]
+    pub access_log: Option<PathBuf>,
+    /// Format of the access log
+    #[arg(long, value_enum, default_value_t = AccessLogFormat::Combined)]
+    pub access_log_format: AccessLogFormat,
+    /// URL the metrics are served at in the Prometheus text format
+    #[arg(long, default_value = "/metrics")]
+    pub metrics_path: String,
+    /// Do not serve the metrics
+    #[arg(long)]
+    pub disable_metrics: bool,
+    /// URL of the liveness probe
+    #[arg(long, default_value = "/healthz")]
+    pub health_path: String,
+    /// URL of the readiness probe, it reports the results of every health check in JSON
+    #[arg(long, default_value = "/readyz")]
+    pub readiness_path: String,
+    /// Do not serve the liveness and readiness probes
+    #[arg(long)]
+    pub disable_health_checks: bool,
+    /// Queued connections above which the server reports not being ready,
+    /// half the queue capacity by default
+    #[arg(long)]
+    pub readiness_queue_threshold: Option<usize>,
+    /// Seconds the server keeps serving after SIGTERM while reporting not being ready,
+    /// so load balancers stop sending connections before it drains
+    #[arg(long, default_value_t = 5)]
+    pub shutdown_delay: u64,
+    /// Seconds to wait for queued and running requests to finish before exiting
+    #[arg(long, default_value_t = 30, value_parser = valid_timeout)]
+    pub shutdown_timeout: u64,
 }
 
 fn valid_pool_size(s: &str) -> Result<usize, String> {
@@ -57,12 +293,81 @@ fn valid_pool_size(s: &str) -> Result<usize, String> {
     }
 }
 
-fn valid_address(s: &str) -> Result<Ipv4Addr, String> {
-    s.parse()
-        .map_err(|_| format!("{s} is not a valid IPv4 string"))
+fn valid_host(s: &str) -> Result<String, String> {
+    let host = s
+        .strip_prefix('[')
+        .and_then(|host| host.strip_suffix(']'))
+        .unwrap_or(s);
+
+    if host.parse::<IpAddr>().is_ok() || is_hostname(host) {
+        Ok(host.to_string())
+    } else {
+        Err(format!("{s} is not a valid IP address or hostname"))
+    }
+}
+
+fn is_hostname(s: &str) -> bool {
+    !s.is_empty()
+        && s.len() <= 253
+        && s.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+}
+
+fn valid_listen_address(s: &str) -> Result<String, String> {
+    let (host, port) = s
+        .rsplit_once(':')
+        .ok_or_else(|| format!("{s} is not in the <host>:<port> form"))?;
+
+    if host.contains(':') && !host.starts_with('[') {
+        return Err(format!("IPv6 address in {s} must be enclosed in brackets"));
+    }
+    valid_host(host)?;
+    port_in_range(port)?;
+
+    Ok(s.to_string())
+}
+
+#[cfg(unix)]
+fn valid_socket_mode(s: &str) -> Result<u32, String> {
+    let mode = u32::from_str_radix(s, 8).map_err(|_| format!("{s} is not a valid octal mode"))?;
+
+    if mode <= 0o777 {
+        Ok(mode)
+    } else {
+        Err(format!("{s} is not in range [0 - 777]"))
+    }
+}
+
+fn valid_limit(s: &str) -> Result<usize, String> {
+    let limit: usize = s.parse().map_err(|_| format!("{s} is not a valid limit"))?;
+
+    if limit > 0 {
+        Ok(limit)
+    } else {
+        Err("Limit can not be less than 1".to_string())
+    }
+}
+
+fn valid_timeout(s: &str) -> Result<u64, String> {
+    let timeout: u64 = s
+        .parse()
+        .map_err(|_| format!("{s} is not a valid timeout"))?;
+
+    if timeout > 0 {
+        Ok(timeout)
+    } else {
+        Err("Timeout can not be less than 1 second".to_string())
+    }
 }
 
 const PORT_RANGE: RangeInclusive<u16> = 1..=65535;
+/// How often the accept loop checks whether the server has stopped accepting
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(200);
 
 fn port_in_range(s: &str) -> Result<u16, String> {
     let port: u16 = s
@@ -81,73 +386,552 @@ fn port_in_range(s: &str) -> Result<u16, String> {
 }
 
 impl Server {
-    fn new(builder: ServerBuilder) -> Server {
-        let thread_pool = ThreadPool::new(builder.pool_size);
-        let address = SocketAddrV4::new(builder.host, builder.port);
+    fn new(mut builder: ServerBuilder) -> Result<Server, BuildError> {
+        if builder.has_proxies && builder.io_mode != IoMode::Blocking {
+            return Err(BuildError::ProxyIoMode);
+        }
+        let thread_pool = if builder.io_mode.uses_pool() {
+            Some(builder.build_pool()?)
+        } else {
+            None
+        };
+        #[cfg(unix)]
+        if !builder.inherited_fds.is_empty() {
+            builder.unix_socket = None;
+            // A descriptor passed twice would be closed twice
+            builder.inherited_fds.sort_unstable();
+            builder.inherited_fds.dedup();
+        }
+        #[cfg(unix)]
+        let addresses = if !builder.inherited_fds.is_empty()
+            || (builder.unix_socket.is_some() && builder.listen.is_empty())
+        {
+            Vec::new()
+        } else {
+            resolve_addresses(&builder.host, builder.port, &builder.listen)
+                .map_err(|e| BuildError::Io("Unable to resolve listen addresses", e))?
+        };
+        #[cfg(not(unix))]
+        let addresses = resolve_addresses(&builder.host, builder.port, &builder.listen)
+            .map_err(|e| BuildError::Io("Unable to resolve listen addresses", e))?;
 
-        Server {
-            pool: thread_pool,
-            address: SocketAddr::V4(address),
-            handlers: Arc::new(builder.handlers),
+        #[cfg(feature = "tls")]
+        let tls = match builder.tls.take() {
+            Some(_) if builder.io_mode != IoMode::Blocking => return Err(BuildError::TlsIoMode),
+            Some(tls_config) => Some(Arc::new(
+                TlsAcceptor::new(tls_config).map_err(BuildError::Tls)?,
+            )),
+            None => None,
+        };
+
+        if let Some(thread_pool) = &thread_pool {
+            register_pool_metrics(&builder.metrics, thread_pool);
+        }
+        let lifecycle = Lifecycle::default();
+        let handlers = builder.take_routes(&lifecycle, thread_pool.as_deref());
+        let mut router = Router::new(handlers, builder.limits).with_metrics(builder.metrics);
+        if let Some(access_log_config) = builder.access_log {
+            let access_log = AccessLog::open(access_log_config)
+                .map_err(|e| BuildError::Io("Unable to open the access log", e))?;
+            #[cfg(unix)]
+            access_log
+                .reopen_on_sighup()
+                .map_err(|e| BuildError::Io("Unable to handle SIGHUP", e))?;
+            router = router.with_access_log(Arc::new(access_log));
         }
+
+        Ok(Server {
+            pool: thread_pool,
+            #[cfg(feature = "async")]
+            async_workers: builder.pool.max_size,
+            io_mode: builder.io_mode,
+            addresses,
+            #[cfg(unix)]
+            unix_socket: builder.unix_socket,
+            #[cfg(unix)]
+            inherited_fds: builder.inherited_fds,
+            router: Arc::new(router),
+            read_timeout: builder.read_timeout,
+            write_timeout: builder.write_timeout,
+            #[cfg(feature = "tls")]
+            tls,
+            lifecycle,
+            shutdown_delay: builder.shutdown_delay,
+            shutdown_timeout: builder.shutdown_timeout,
+            config_file: builder.config_file,
+        })
     }
 
     pub fn builder(config: Config) -> ServerBuilder {
+        let limits = config.limits();
+
         ServerBuilder {
-            pool_size: config.pool_size,
+            pool: PoolConfig {
+                min_size: config.pool_size.unwrap_or(config.min_pool_size),
+                max_size: config.pool_size.unwrap_or(config.max_pool_size),
+                keep_alive: Duration::from_secs(config.pool_keep_alive),
+                queue_capacity: config.queue_capacity,
+                overload_policy: config.overload_policy,
+            },
+            #[cfg(target_os = "linux")]
+            pin_workers: config.pin_workers,
+            io_mode: config.io_mode,
             host: config.host,
             port: config.port,
+            listen: config.listen,
+            #[cfg(unix)]
+            unix_socket: config.unix_socket.map(|path| UnixSocketConfig {
+                path,
+                mode: config.unix_socket_mode,
+            }),
+            // Descriptors named on the command line or passed by systemd are inherited
+            // from the parent process for the server alone
+            #[cfg(unix)]
+            inherited_fds: if config.fd.is_empty() {
+                listener::systemd_listen_fds()
+            } else {
+                config.fd
+            },
             handlers: Vec::new(),
+            has_proxies: false,
+            limits,
+            read_timeout: Duration::from_secs(config.read_timeout),
+            write_timeout: Duration::from_secs(config.write_timeout),
+            #[cfg(feature = "tls")]
+            tls: config
+                .tls_cert
+                .zip(config.tls_key)
+                .map(|(cert_path, key_path)| TlsConfig {
+                    cert_path,
+                    key_path,
+                }),
+            access_log: config.access_log.map(|path| AccessLogConfig {
+                target: if path.as_os_str() == "-" {
+                    AccessLogTarget::Stdout
+                } else {
+                    AccessLogTarget::File(path)
+                },
+                format: config.access_log_format,
+            }),
+            metrics: Arc::new(Registry::new()),
+            metrics_path: (!config.disable_metrics).then_some(config.metrics_path),
+            health_checks: HealthChecks::new(),
+            health_path: (!config.disable_health_checks).then_some(config.health_path),
+            readiness_path: (!config.disable_health_checks).then_some(config.readiness_path),
+            readiness_queue_threshold: config
+                .readiness_queue_threshold
+                .unwrap_or(config.queue_capacity / 2),
+            shutdown_delay: Duration::from_secs(config.shutdown_delay),
+            shutdown_timeout: Duration::from_secs(config.shutdown_timeout),
+            config_file: config.config,
         }
     }
 
+    /// The lifecycle of the server, beginning its shutdown stops and drains the server
+    /// once it has been started
+    pub fn lifecycle(&self) -> Lifecycle {
+        self.lifecycle.clone()
+    }
+
+    /// Serves until the shutdown has begun and the shutdown delay has passed,
+    /// then closes the listeners and returns once the connections in flight are done
+    /// The process exits with a failure status if they are not done within the shutdown timeout
+    /// The process exits if they are not done within the shutdown timeout
     pub fn start(&self) {
-        println!(
-            "Server is listening at {} (pool size={})",
-            self.address,
-            self.pool.size()
-        );
-        let listener = TcpListener::bind(self.address).unwrap();
-
-        for stream in listener.incoming() {
-            let mut stream = stream.unwrap();
-
-            let thread_handlers = Arc::clone(&self.handlers);
-            self.pool.execute(move || {
-                let request = Request::parse(&mut stream);
-
-                let response = match request {
-                    Ok(request) => {
-                        let handler = thread_handlers.iter().find(|h| h.matcher.matches(&request));
-                        match handler {
-                            Some(handler) => (handler.handler_fn)(request),
-                            None => not_found_response(),
-                        }
-                    }
-                    Err(e) => server_error_response(e),
-                };
+        self.spawn_shutdown_watcher();
+        if let Some(path) = &self.config_file {
+            self.spawn_config_reloader(path);
+        }
 
-                response.write(&mut stream).unwrap();
+        #[allow(unused_mut)]
+        let mut listeners: Vec<Listener> = self
+            .addresses
+            .iter()
+            .map(|address| {
+                let only_v6 = self
+                    .addresses
+                    .iter()
+                    .any(|other| other.is_ipv4() && other.port() == address.port());
+                bind_tcp(*address, only_v6)
+                    .unwrap_or_else(|e| panic!("Unable to listen at {}: {}", address, e))
+            })
+            .collect();
+        #[cfg(unix)]
+        if let Some(unix_socket) = &self.unix_socket {
+            let listener = bind_unix(unix_socket).unwrap_or_else(|e| {
+                panic!("Unable to listen at {}: {}", unix_socket.path.display(), e)
             });
+            listeners.push(listener);
+        }
+        #[cfg(unix)]
+        for &fd in &self.inherited_fds {
+            // SAFETY: the descriptors are owned by the server, see `ServerBuilder::inherited_fds`
+            let listener = unsafe { listener::inherit(fd) }
+                .unwrap_or_else(|e| panic!("Unable to listen at file descriptor {}: {}", fd, e));
+            listeners.push(listener);
+        }
+        #[cfg(unix)]
+        self.spawn_handover_watcher(&listeners);
+
+        for listener in &listeners {
+            match &self.pool {
+                Some(pool) => info!(
+                    "Server is listening at {} (pool size={}..{})",
+                    listener,
+                    pool.min_size(),
+                    pool.max_size()
+                ),
+                #[cfg(feature = "async")]
+                None => info!(
+                    "Server is listening at {} (async workers={})",
+                    listener, self.async_workers
+                ),
+                #[cfg(not(feature = "async"))]
+                None => info!("Server is listening at {}", listener),
+            }
+        }
+        #[cfg(unix)]
+        handover::notify_ready();
+
+        let drained = match self.io_mode {
+            IoMode::Blocking => {
+                let mut acceptor = Acceptor::new(&listeners)
+                    .unwrap_or_else(|e| panic!("Unable to accept connections: {}", e));
+                self.accept(&mut acceptor);
+                drop(acceptor);
+                self.close_listeners(listeners);
+                health::drain(self.pool(), self.shutdown_timeout)
+            }
+            IoMode::EventLoop => {
+                let mut event_loop =
+                    EventLoop::new(&listeners, self.read_timeout, self.write_timeout)
+                        .unwrap_or_else(|e| panic!("Unable to start the event loop: {}", e));
+                if let Err(e) = event_loop.run(self.pool(), &self.router, &self.lifecycle) {
+                    panic!("Event loop failed: {}", e);
+                }
+                self.close_listeners(listeners);
+                event_loop
+                    .drain(self.pool(), &self.router, self.shutdown_timeout)
+                    .unwrap_or_else(|e| panic!("Event loop failed: {}", e))
+            }
+            #[cfg(feature = "async")]
+            IoMode::Async => {
+                let server =
+                    AsyncServer::new(self.async_workers, self.read_timeout, self.write_timeout)
+                        .unwrap_or_else(|e| panic!("Unable to start the async runtime: {}", e));
+                if let Err(e) = server.run(&listeners, &self.router, &self.lifecycle) {
+                    panic!("Async server failed: {}", e);
+                }
+                self.close_listeners(listeners);
+                server.drain(self.shutdown_timeout)
+            }
+        };
+
+        if drained {
+            info!("Server has been drained");
+        } else {
+            warn!(
+                "Server has not been drained in {} seconds",
+                self.shutdown_timeout.as_secs()
+            );
+            std::process::exit(1);
+        }
+    }
+
+    /// Waits for the shutdown to begin, either through SIGTERM or the lifecycle,
+    /// then keeps serving for the shutdown delay and stops accepting connections
+    fn spawn_shutdown_watcher(&self) {
+        #[cfg(unix)]
+        self.lifecycle
+            .shut_down_on_sigterm()
+            .unwrap_or_else(|e| panic!("Unable to handle SIGTERM: {}", e));
+
+        let lifecycle = self.lifecycle.clone();
+        let delay = self.shutdown_delay;
+        thread::Builder::new()
+            .name(String::from("shutdown"))
+            .spawn(move || {
+                lifecycle.wait_for_shutdown();
+                info!(
+                    "Server is shutting down, draining in {} seconds",
+                    delay.as_secs()
+                );
+                thread::sleep(delay);
+                lifecycle.stop_accepting();
+            })
+            .unwrap_or_else(|e| panic!("Unable to start the shutdown watcher: {}", e));
+    }
+
+    /// Closes the listeners, the socket files are left to the process they have been handed over to
+    fn close_listeners(&self, mut listeners: Vec<Listener>) {
+        if self.lifecycle.is_handed_over() {
+            listeners.iter_mut().for_each(Listener::keep_socket_file);
+        }
+        info!("Server has stopped accepting connections");
+    }
+
+    /// Reloads the configuration on SIGHUP or once the config file changes
+    fn spawn_config_reloader(&self, path: &Path) {
+        let args = std::env::args_os().collect();
+        let reloader = ConfigReloader::new(args, |name| std::env::var(name).ok(), &self.router)
+            .unwrap_or_else(|e| panic!("Unable to load the configuration: {}", e));
+
+        reloader
+            .watch(path.to_path_buf())
+            .unwrap_or_else(|e| panic!("Unable to watch the config file: {}", e));
+    }
+
+    /// Hands the listeners over to a new server process on SIGUSR2
+    #[cfg(unix)]
+    fn spawn_handover_watcher(&self, listeners: &[Listener]) {
+        let listeners = listeners
+            .iter()
+            .map(Listener::try_clone)
+            .collect::<io::Result<Vec<_>>>()
+            .unwrap_or_else(|e| panic!("Unable to duplicate the listeners: {}", e));
+
+        handover::hand_over_on_sigusr2(listeners, self.lifecycle.clone())
+            .unwrap_or_else(|e| panic!("Unable to handle SIGUSR2: {}", e));
+    }
+
+    fn pool(&self) -> &ThreadPool {
+        self.pool
+            .as_ref()
+            .expect("Pool must be built outside of the async I/O mode")
+    }
+
+    /// Queues the accepted connections on the pool until the server stops accepting
+    fn accept(&self, acceptor: &mut Acceptor) {
+        while self.lifecycle.is_accepting() {
+            if let Err(e) = acceptor.accept(ACCEPT_POLL_INTERVAL, |stream| self.queue(stream)) {
+                panic!("Unable to accept connections: {}", e);
+            }
+        }
+    }
+
+    fn queue(&self, stream: Stream) {
+        if let Err(e) = stream.set_timeouts(self.read_timeout, self.write_timeout) {
+            warn!("Unable to set connection timeouts: {}", e);
+            return;
+        }
+
+        let client = stream.peer_ip();
+        let router = Arc::clone(&self.router);
+        #[cfg(feature = "tls")]
+        let stream = match (stream, &self.tls) {
+            (Stream::Tcp(stream), Some(acceptor)) => {
+                // A refused connection is closed without a response as there is no handshake yet
+                let acceptor = Arc::clone(acceptor);
+                let queued = self.pool().execute(move || match acceptor.accept(stream) {
+                    Ok(mut stream) => {
+                        router.handle_connection(&mut stream, client);
+                        stream.conn.send_close_notify();
+                        let _ = stream.flush();
+                    }
+                    Err(e) => warn!("TLS handshake failed: {}", e),
+                });
+                if let Err(e) = queued {
+                    warn!("Refused a connection: {}", e);
+                }
+                return;
+            }
+            (stream, _) => stream,
+        };
+
+        let mut connection = QueuedConnection::new(stream);
+        let queued = self.pool().execute(move || {
+            if let Some(mut stream) = connection.take() {
+                router.handle_connection(&mut stream, client);
+            }
+        });
+        if let Err(e) = queued {
+            warn!("Refused a connection: {}", e);
+        }
+    }
+}
+
+/// Serves the metrics of the registry in the Prometheus text exposition format
+fn metrics_handler(path: String, registry: Arc<Registry>) -> RequestHandler {
+    RequestHandler::new(
+        RequestMatcher::get().url(path).build(),
+        Box::new(move |_| {
+            Response::builder()
+                .code(200)
+                .add_header("Content-Type", "text/plain; version=0.0.4; charset=utf-8")
+                .body(registry.render())
+                .build()
+        }),
+    )
+}
+
+/// A connection waiting in the pool queue
+///
+/// If the job is rejected or dropped by the overload policy before it runs,
+/// the connection is answered with 503 instead of being closed silently
+struct QueuedConnection<C: Connection> {
+    connection: Option<C>,
+}
+
+impl<C: Connection> QueuedConnection<C> {
+    fn new(connection: C) -> QueuedConnection<C> {
+        QueuedConnection {
+            connection: Some(connection),
         }
     }
+
+    fn take(&mut self) -> Option<C> {
+        self.connection.take()
+    }
 }
 
+impl<C: Connection> Drop for QueuedConnection<C> {
+    fn drop(&mut self) {
+        if let Some(mut connection) = self.connection.take() {
+            let _ = overloaded_response().write(&mut connection);
+        }
+    }
+}
+
+/// Resolves the addresses to listen on
+///
+/// `listen` addresses take precedence over `host` and `port`,
+/// a hostname is expanded into every address it resolves to
+fn resolve_addresses(
+    host: &str,
+    port: u16,
+    listen: &[String],
+) -> Result<Vec<SocketAddr>, io::Error> {
+    let resolved = if listen.is_empty() {
+        (host, port).to_socket_addrs()?.collect::<Vec<_>>()
+    } else {
+        let mut resolved = Vec::new();
+        for address in listen {
+            resolved.extend(address.to_socket_addrs()?);
+        }
+        resolved
+    };
+
+    let mut addresses: Vec<SocketAddr> = Vec::with_capacity(resolved.len());
+    for address in resolved {
+        if !addresses.contains(&address) {
+            addresses.push(address);
+        }
+    }
+
+    Ok(addresses)
+}
+
+impl Display for BuildError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BuildError::PoolSize(min_size, max_size) => write!(
+                f,
+                "Min pool size {} is greater than max pool size {}",
+                min_size, max_size
+            ),
+            BuildError::Io(context, e) => write!(f, "{}: {}", context, e),
+            #[cfg(feature = "tls")]
+            BuildError::Tls(e) => write!(f, "Unable to configure TLS: {}", e),
+            #[cfg(feature = "tls")]
+            BuildError::TlsIoMode => write!(f, "TLS is only supported in the blocking I/O mode"),
+            BuildError::ProxyIoMode => {
+                write!(f, "Proxies are only supported in the blocking I/O mode")
+            }
+        }
+    }
+}
+
+impl std::error::Error for BuildError {}
+
 impl Drop for Server {
     fn drop(&mut self) {
-        println!("Server is shutting down");
+        info!("Server is shutting down");
     }
 }
 
 impl Config {
+    /// Loads the configuration from the command line, the environment and the config file
+    ///
+    /// Exits with the error if it is invalid, or after printing it with `--check-config`
     pub fn get_config() -> Config {
-        Config::parse()
+        let matches = config::load_matches(std::env::args_os(), |name| std::env::var(name).ok())
+            .unwrap_or_else(|e| e.exit());
+        let config = Config::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+
+        if config.check_config {
+            print!("{}", config::effective_config(&matches));
+            std::process::exit(0);
+        }
+
+        config
+    }
+
+    pub fn limits(&self) -> RequestLimits {
+        RequestLimits {
+            max_request_line_length: self.max_request_line_length,
+            max_header_count: self.max_header_count,
+            max_header_size: self.max_header_size,
+            max_body_size: self.max_body_size,
+            max_decompressed_body_size: self.max_decompressed_body_size,
+        }
+    }
+}
+
+impl Default for Config {
+    /// Returns the configuration the server has without any command line arguments
+    fn default() -> Self {
+        Config::parse_from([env!("CARGO_PKG_NAME")])
     }
 }
 
 impl ServerBuilder {
+    /// Sets a fixed pool size, the pool neither grows nor shrinks
     pub fn pool_size(mut self, pool_size: usize) -> ServerBuilder {
-        self.pool_size = pool_size;
+        self.pool.min_size = pool_size;
+        self.pool.max_size = pool_size;
+
+        self
+    }
+
+    pub fn min_pool_size(mut self, min_pool_size: usize) -> ServerBuilder {
+        self.pool.min_size = min_pool_size;
+
+        self
+    }
+
+    pub fn max_pool_size(mut self, max_pool_size: usize) -> ServerBuilder {
+        self.pool.max_size = max_pool_size;
+
+        self
+    }
+
+    pub fn pool_keep_alive(mut self, pool_keep_alive: Duration) -> ServerBuilder {
+        self.pool.keep_alive = pool_keep_alive;
+
+        self
+    }
+
+    pub fn queue_capacity(mut self, queue_capacity: usize) -> ServerBuilder {
+        self.pool.queue_capacity = queue_capacity;
+
+        self
+    }
+
+    pub fn overload_policy(mut self, overload_policy: OverloadPolicy) -> ServerBuilder {
+        self.pool.overload_policy = overload_policy;
+
+        self
+    }
+
+    #[cfg(target_os = "linux")]
+    pub fn pin_workers(mut self, pin_workers: bool) -> ServerBuilder {
+        self.pin_workers = pin_workers;
+
+        self
+    }
+
+    pub fn io_mode(mut self, io_mode: IoMode) -> ServerBuilder {
+        self.io_mode = io_mode;
 
         self
     }
@@ -158,44 +942,389 @@ impl ServerBuilder {
         self
     }
 
-    pub fn host(mut self, host: Ipv4Addr) -> ServerBuilder {
-        self.host = host;
+    pub fn host(mut self, host: impl Into<String>) -> ServerBuilder {
+        self.host = Into::into(host);
+
+        self
+    }
+
+    /// Adds an address to listen on in the `<host>:<port>` form
+    ///
+    /// Once any address is added, `host` and `port` are ignored
+    pub fn listen(mut self, address: impl Into<String>) -> ServerBuilder {
+        self.listen.push(Into::into(address));
 
         self
     }
 
-    pub fn register_handler(
+    #[cfg(unix)]
+    pub fn unix_socket(mut self, unix_socket: UnixSocketConfig) -> ServerBuilder {
+        self.unix_socket = Some(unix_socket);
+
+        self
+    }
+
+    /// Accepts connections from inherited listening sockets instead of binding new ones
+    ///
+    /// # Safety
+    ///
+    /// The descriptors must not be owned by anything else in this process,
+    /// the server closes them once it stops accepting
+    #[cfg(unix)]
+    pub unsafe fn inherited_fds(mut self, fds: Vec<RawFd>) -> ServerBuilder {
+        self.inherited_fds = fds;
+
+        self
+    }
+
+    pub fn limits(mut self, limits: RequestLimits) -> ServerBuilder {
+        self.limits = limits;
+
+        self
+    }
+
+    pub fn read_timeout(mut self, read_timeout: Duration) -> ServerBuilder {
+        self.read_timeout = read_timeout;
+
+        self
+    }
+
+    pub fn write_timeout(mut self, write_timeout: Duration) -> ServerBuilder {
+        self.write_timeout = write_timeout;
+
+        self
+    }
+
+    #[cfg(feature = "tls")]
+    pub fn tls(mut self, tls_config: TlsConfig) -> ServerBuilder {
+        self.tls = Some(tls_config);
+
+        self
+    }
+
+    pub fn access_log(mut self, access_log_config: AccessLogConfig) -> ServerBuilder {
+        self.access_log = Some(access_log_config);
+
+        self
+    }
+
+    /// Sets the URL the metrics are served at, `None` does not serve them
+    pub fn metrics_path(mut self, metrics_path: Option<String>) -> ServerBuilder {
+        self.metrics_path = metrics_path;
+
+        self
+    }
+
+    pub fn health_path(mut self, health_path: Option<String>) -> ServerBuilder {
+        self.health_path = health_path;
+
+        self
+    }
+
+    pub fn readiness_path(mut self, readiness_path: Option<String>) -> ServerBuilder {
+        self.readiness_path = readiness_path;
+
+        self
+    }
+
+    pub fn readiness_queue_threshold(mut self, readiness_queue_threshold: usize) -> ServerBuilder {
+        self.readiness_queue_threshold = readiness_queue_threshold;
+
+        self
+    }
+
+    pub fn shutdown_delay(mut self, shutdown_delay: Duration) -> ServerBuilder {
+        self.shutdown_delay = shutdown_delay;
+
+        self
+    }
+
+    pub fn shutdown_timeout(mut self, shutdown_timeout: Duration) -> ServerBuilder {
+        self.shutdown_timeout = shutdown_timeout;
+
+        self
+    }
+
+    /// Adds a check to the readiness report, the server is not ready while it returns an error
+    pub fn register_health_check(
+        mut self,
+        name: impl Into<String>,
+        check: impl Fn() -> Result<(), String> + Send + Sync + 'static,
+    ) -> ServerBuilder {
+        self.health_checks.add(name, Box::new(check));
+
+        self
+    }
+
+    /// The registry request metrics are recorded into,
+    /// handlers can register their own metrics in it to have them served as well
+    pub fn metrics(&self) -> Arc<Registry> {
+        Arc::clone(&self.metrics)
+    }
+
+    /// Registers a function returning the response, or with the `async` feature an async one
+    ///
+    /// Async handlers are awaited on the tokio runtime in the async I/O mode
+    /// and blocked on in the other modes
+    pub fn register_handler<R: HandlerResponse>(
         mut self,
         request_matcher: RequestMatcher,
-        request_handler: impl Fn(Request) -> Response + Send + Sync + 'static,
+        request_handler: impl Fn(Request) -> R + Send + Sync + 'static,
     ) -> ServerBuilder {
-        let handler = RequestHandler {
-            matcher: request_matcher,
-            handler_fn: Box::new(request_handler),
-        };
+        let handler = RequestHandler::from_fn(request_matcher, request_handler);
 
         self.handlers.push(handler);
 
         self
     }
 
+    /// Forwards the requests the matcher accepts to the upstream server of the proxy
+    ///
+    /// Only supported in the blocking I/O mode, which streams the upstream responses to the clients
+    /// instead of buffering them
+    pub fn register_proxy(
+        mut self,
+        request_matcher: RequestMatcher,
+        proxy: ProxyHandler,
+    ) -> ServerBuilder {
+        self.has_proxies = true;
+
+        self.register_handler(request_matcher, move |request| proxy.handle(request))
+    }
+
     pub fn build(self) -> Server {
+        self.try_build()
+            .unwrap_or_else(|e| panic!("Unable to build the server: {}", e))
+    }
+
+    /// Builds the server, returns the error if it can not be configured,
+    /// e.g. the TLS certificate is invalid
+    pub fn try_build(self) -> Result<Server, BuildError> {
         Server::new(self)
     }
-}
 
-fn not_found_response() -> Response {
-    Response::builder()
-        .code(404)
-        .body("Requested page has not been found")
-        .build()
+    fn build_pool(&self) -> Result<ThreadPool, BuildError> {
+        if self.pool.min_size > self.pool.max_size {
+            return Err(BuildError::PoolSize(self.pool.min_size, self.pool.max_size));
+        }
+        let thread_pool = ThreadPool::builder()
+            .config(self.pool.clone())
+            .thread_name("http-worker");
+        #[cfg(target_os = "linux")]
+        let thread_pool = if self.pin_workers {
+            let cpus = affinity::allowed_cpus()
+                .map_err(|e| BuildError::Io("Unable to get the CPUs to pin workers to", e))?;
+            thread_pool.cpu_affinity(cpus)
+        } else {
+            thread_pool
+        };
+
+        Ok(thread_pool.build())
+    }
+
+    /// Builds a client that dispatches requests to the registered handlers without opening sockets
+    pub fn test_client(mut self) -> TestClient {
+        let handlers = self.take_routes(&Lifecycle::default(), None);
+
+        TestClient::new(Router::new(handlers, self.limits).with_metrics(self.metrics))
+    }
+
+    /// Takes the registered handlers followed by the built-in metrics and health routes
+    ///
+    /// The readiness report starts with the shutdown and, if there is a pool, its queue check
+    fn take_routes(
+        &mut self,
+        lifecycle: &Lifecycle,
+        pool: Option<&PoolHandle>,
+    ) -> Vec<RequestHandler> {
+        let mut handlers = std::mem::take(&mut self.handlers);
+        if let Some(metrics_path) = self.metrics_path.take() {
+            handlers.push(metrics_handler(metrics_path, Arc::clone(&self.metrics)));
+        }
+        if let Some(health_path) = self.health_path.take() {
+            handlers.push(health::health_handler(health_path));
+        }
+        if let Some(readiness_path) = self.readiness_path.take() {
+            let mut checks = HealthChecks::new();
+            checks.add("shutdown", lifecycle.check());
+            if let Some(pool) = pool {
+                checks.add(
+                    "queue",
+                    health::queue_check(pool.clone(), self.readiness_queue_threshold),
+                );
+            }
+            checks.append(std::mem::take(&mut self.health_checks));
+            handlers.push(health::readiness_handler(readiness_path, checks));
+        }
+
+        handlers
+    }
 }
 
-fn server_error_response<E>(error: E) -> Response
-where
-    E: Error,
-{
-    let response_body = format!("Something went wrong: {}", error);
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, Ipv6Addr};
 
-    Response::builder().code(500).body(response_body).build()
+    use super::*;
+
+    #[test]
+    fn valid_host_must_accept_ip_addresses_and_hostnames() {
+        assert_eq!(Ok(String::from("127.0.0.1")), valid_host("127.0.0.1"));
+        assert_eq!(Ok(String::from("::1")), valid_host("::1"));
+        assert_eq!(Ok(String::from("::")), valid_host("[::]"));
+        assert_eq!(Ok(String::from("localhost")), valid_host("localhost"));
+        assert_eq!(
+            Ok(String::from("my-host.local")),
+            valid_host("my-host.local")
+        );
+        assert!(
+            valid_host("-host").is_err(),
+            "Invalid hostname must be rejected"
+        );
+        assert!(
+            valid_host("host name").is_err(),
+            "Invalid hostname must be rejected"
+        );
+    }
+
+    #[test]
+    fn valid_listen_address_must_require_host_and_port() {
+        assert!(valid_listen_address("0.0.0.0:8080").is_ok());
+        assert!(valid_listen_address("[::]:8080").is_ok());
+        assert!(valid_listen_address("localhost:8080").is_ok());
+        assert!(
+            valid_listen_address("0.0.0.0").is_err(),
+            "Port must be required"
+        );
+        assert!(
+            valid_listen_address(":::8080").is_err(),
+            "IPv6 must be enclosed in brackets"
+        );
+        assert!(
+            valid_listen_address("[::]:0").is_err(),
+            "Port must be in range"
+        );
+    }
+
+    #[test]
+    fn resolve_addresses_must_prefer_listen_addresses() {
+        let host_addresses = resolve_addresses("::1", 8080, &[]).unwrap();
+        let listen_addresses = resolve_addresses(
+            "::1",
+            8080,
+            &[
+                String::from("0.0.0.0:8081"),
+                String::from("[::]:8081"),
+                String::from("[::]:8081"),
+            ],
+        )
+        .unwrap();
+
+        assert_eq!(
+            vec![SocketAddr::new(IpAddr::V6(Ipv6Addr::LOCALHOST), 8080)],
+            host_addresses
+        );
+        assert_eq!(
+            vec![
+                SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 8081),
+                SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 8081)
+            ],
+            listen_addresses,
+            "Listen addresses must be resolved without duplicates"
+        );
+    }
+
+    #[cfg(feature = "tls")]
+    #[test]
+    fn try_build_must_return_invalid_tls_config() {
+        let missing = std::env::temp_dir().join("rust_web_server_missing.pem");
+
+        let server = Server::builder(Config::default())
+            .tls(TlsConfig {
+                cert_path: missing.clone(),
+                key_path: missing,
+            })
+            .try_build();
+
+        assert!(
+            matches!(server, Err(BuildError::Tls(TlsError::Io(_, _)))),
+            "Invalid TLS configuration must be returned"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn start_must_close_listeners_and_finish_requests_in_flight_on_shutdown() {
+        use std::{
+            io::{Read, Write},
+            os::unix::net::UnixStream,
+            sync::{mpsc, Mutex},
+            time::Instant,
+        };
+
+        let path = std::env::temp_dir().join(format!(
+            "rust_web_server_shutdown_{}.sock",
+            std::process::id()
+        ));
+        let (started_sender, started) = mpsc::channel();
+        let (release, released) = mpsc::channel::<()>();
+        let released = Mutex::new(released);
+        let server = Server::builder(Config::default())
+            .unix_socket(UnixSocketConfig {
+                path: path.clone(),
+                mode: 0o600,
+            })
+            .shutdown_delay(Duration::ZERO)
+            .register_handler(RequestMatcher::get().url("/slow").build(), move |_| {
+                started_sender.send(()).unwrap();
+                released.lock().unwrap().recv().unwrap();
+                Response::builder().code(200).build()
+            })
+            .build();
+        let lifecycle = server.lifecycle();
+        let server = thread::spawn(move || server.start());
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut stream = loop {
+            match UnixStream::connect(&path) {
+                Ok(stream) => break stream,
+                Err(_) if Instant::now() < deadline => thread::sleep(Duration::from_millis(10)),
+                Err(e) => panic!("Unable to connect to the server: {}", e),
+            }
+        };
+        stream.write_all(b"GET /slow HTTP/1.1\r\n\r\n").unwrap();
+        started.recv().unwrap();
+        lifecycle.begin_shutdown();
+        while UnixStream::connect(&path).is_ok() && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        let refused = UnixStream::connect(&path).is_err();
+        release.send(()).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        server.join().unwrap();
+
+        assert!(refused, "Listener must be closed before draining");
+        assert!(
+            response.starts_with("HTTP/1.1 200 OK\r\n"),
+            "Request in flight must be answered"
+        );
+        assert!(!path.exists(), "Socket file must be removed");
+    }
+
+    #[test]
+    fn try_build_must_reject_proxy_outside_of_blocking_io_mode() {
+        let server = Server::builder(Config::default())
+            .io_mode(IoMode::EventLoop)
+            .register_proxy(
+                RequestMatcher::get().url("/proxied").build(),
+                ProxyHandler::builder("127.0.0.1:8081").build(),
+            )
+            .try_build();
+
+        assert!(
+            matches!(server, Err(BuildError::ProxyIoMode)),
+            "Proxy must be rejected outside of the blocking I/O mode"
+        );
+    }
 }
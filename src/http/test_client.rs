@@ -0,0 +1,223 @@
+use crate::http::{
+    connection::MemoryConnection, request::Request, response::Response, router::Router,
+};
+
+/// Sends requests to the handlers of a server without starting it
+///
+/// Requests go through the same routing and error handling as on a real connection
+pub struct TestClient {
+    router: Router,
+}
+
+impl TestClient {
+    pub(crate) fn new(router: Router) -> TestClient {
+        TestClient { router }
+    }
+
+    /// Dispatches the request to the matching handler
+    pub fn send(&self, request: Request) -> Response {
+        self.router.dispatch(request)
+    }
+
+    /// Parses the raw request and dispatches it, as if it was received from a connection
+    pub fn send_raw(&self, raw_request: impl Into<Vec<u8>>) -> Response {
+        let mut connection = MemoryConnection::new(raw_request);
+
+        self.router.process(&mut connection, None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::http::{
+        request::{matcher::RequestMatcher, RequestMethod},
+        server::{Config, Server},
+    };
+
+    use super::*;
+
+    fn test_client() -> TestClient {
+        Server::builder(Config::default())
+            .register_handler(RequestMatcher::get().url("/test").build(), |request| {
+                let name = request
+                    .get_query_param("name")
+                    .and_then(|names| names.first().cloned())
+                    .unwrap_or_default();
+
+                Response::builder()
+                    .code(200)
+                    .add_header("Content-Type", "text/plain")
+                    .body(format!("Hello, {}", name))
+                    .build()
+            })
+            .register_handler(
+                RequestMatcher::get().url("/panic").build(),
+                |_| -> Response { panic!("Handler failure") },
+            )
+            .test_client()
+    }
+
+    #[test]
+    fn send_must_dispatch_request_to_handler() {
+        let response = test_client().send(
+            Request::builder()
+                .method(RequestMethod::GET)
+                .url("/test")
+                .add_query_param("name", "test")
+                .build(),
+        );
+
+        assert_eq!(200, response.code(), "Response code must be 200");
+        assert_eq!(
+            "Hello, test",
+            response.body(),
+            "Response body must be 'Hello, test'"
+        );
+        assert!(
+            response
+                .get_header("Content-Type")
+                .is_some_and(|h| h == "text/plain"),
+            "Content-Type must be 'text/plain'"
+        );
+    }
+
+    #[test]
+    fn send_must_respond_with_not_found_for_unknown_route() {
+        let response = test_client().send(Request::builder().url("/unknown").build());
+
+        assert_eq!(404, response.code(), "Response code must be 404");
+    }
+
+    #[test]
+    fn send_must_respond_with_server_error_when_handler_panics() {
+        let response = test_client().send(Request::builder().url("/panic").build());
+
+        assert_eq!(500, response.code(), "Response code must be 500");
+    }
+
+    #[test]
+    fn send_raw_must_parse_request() {
+        let client = test_client();
+
+        let response = client.send_raw("GET /test?name=raw HTTP/1.1\r\n\r\n");
+        let bad_response = client.send_raw("GET /test?name HTTP/1.1\r\n\r\n");
+
+        assert_eq!(200, response.code(), "Response code must be 200");
+        assert_eq!(
+            "Hello, raw",
+            response.body(),
+            "Response body must be 'Hello, raw'"
+        );
+        assert_eq!(
+            400,
+            bad_response.code(),
+            "Malformed request must be answered with 400"
+        );
+    }
+
+    #[test]
+    fn send_raw_must_serve_request_and_custom_metrics() {
+        let builder = Server::builder(Config::default());
+        let greetings = builder
+            .metrics()
+            .counter("greetings_total", "Greetings sent", &[]);
+        let client = builder
+            .register_handler(RequestMatcher::get().url("/test").build(), move |_| {
+                greetings.inc();
+                Response::builder().code(200).body("Hello").build()
+            })
+            .test_client();
+
+        client.send_raw("GET /test HTTP/1.1\r\n\r\n");
+        client.send_raw("GET /unknown HTTP/1.1\r\n\r\n");
+        client.send_raw("GET /test HTTP/3\r\n\r\n");
+        let response = client.send_raw("GET /metrics HTTP/1.1\r\n\r\n");
+
+        assert_eq!(200, response.code(), "Response code must be 200");
+        assert_eq!(
+            Some(&String::from("text/plain; version=0.0.4; charset=utf-8")),
+            response.get_header("Content-Type"),
+            "Metrics must be served in the text exposition format"
+        );
+        for sample in [
+            "greetings_total 1\n",
+            "http_requests_total{method=\"GET\",route=\"/test\",status=\"200\"} 1\n",
+            "http_requests_total{method=\"GET\",route=\"unmatched\",status=\"404\"} 1\n",
+            "http_request_duration_seconds_count{method=\"GET\",route=\"/test\"} 1\n",
+            "http_request_parse_errors_total{status=\"505\"} 1\n",
+            "http_requests_in_flight 1\n",
+        ] {
+            assert!(
+                response.body().contains(sample),
+                "Metrics must contain {}",
+                sample
+            );
+        }
+    }
+
+    #[test]
+    fn send_raw_must_serve_health_and_readiness_reports() {
+        let client = Server::builder(Config::default())
+            .register_health_check("database", || Err(String::from("Connection refused")))
+            .test_client();
+
+        let health = client.send_raw("GET /healthz HTTP/1.1\r\n\r\n");
+        let readiness = client.send_raw("GET /readyz HTTP/1.1\r\n\r\n");
+
+        assert_eq!(200, health.code(), "Live server must answer 200");
+        assert_eq!(
+            503,
+            readiness.code(),
+            "Server with a failing check must not be ready"
+        );
+        assert_eq!(
+            r#"{"status":"fail","checks":{"shutdown":{"status":"pass"},"database":{"status":"fail","message":"Connection refused"}}}"#,
+            readiness.body(),
+            "Readiness report must aggregate the checks"
+        );
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn send_must_block_on_async_handler() {
+        async fn echo(request: Request) -> Response {
+            Response::builder().code(200).body(request.body()).build()
+        }
+        let client = Server::builder(Config::default())
+            .register_handler(RequestMatcher::post().url("/async").build(), echo)
+            .test_client();
+
+        let response =
+            client.send_raw("POST /async HTTP/1.1\r\nContent-Length: 9\r\n\r\ntest_body");
+
+        assert_eq!(200, response.code(), "Response code must be 200");
+        assert_eq!(
+            "test_body",
+            response.body(),
+            "Async handler must be awaited by the test client"
+        );
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn send_must_block_on_async_handler_from_within_runtime() {
+        let client = Server::builder(Config::default())
+            .register_handler(RequestMatcher::get().url("/async").build(), |_| async {
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+                Response::builder().code(200).body("async").build()
+            })
+            .test_client();
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+
+        let response = runtime.block_on(async { client.send_raw("GET /async HTTP/1.1\r\n\r\n") });
+
+        assert_eq!(200, response.code(), "Response code must be 200");
+        assert_eq!(
+            "async",
+            response.body(),
+            "Async handler must be awaited from within a runtime"
+        );
+    }
+}
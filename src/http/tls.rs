@@ -0,0 +1,378 @@
+use std::{
+    fmt::Display,
+    io,
+    net::TcpStream,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, Instant, SystemTime},
+};
+
+use log::warn;
+use rustls::{
+    crypto::{ring, CryptoProvider},
+    pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer},
+    server::{ClientHello, ResolvesServerCert},
+    sign::CertifiedKey,
+    ServerConfig, ServerConnection, StreamOwned,
+};
+
+/// Protocols advertised to the clients through ALPN, in the order of preference
+const ALPN_PROTOCOLS: [&[u8]; 2] = [b"http/1.1", b"http/1.0"];
+
+/// How often the certificate and the key files are checked for modification
+const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+pub type TlsStream = StreamOwned<ServerConnection, TcpStream>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TlsConfig {
+    pub cert_path: PathBuf,
+    pub key_path: PathBuf,
+}
+
+#[derive(Debug)]
+pub enum TlsError {
+    Io(PathBuf, io::Error),
+    Pem(PathBuf, rustls::pki_types::pem::Error),
+    NoCertificates(PathBuf),
+    Rustls(rustls::Error),
+}
+
+/// Terminates TLS on accepted connections
+///
+/// The certificate and the key are re-read once any of the files is modified,
+/// which is checked during handshakes at most every 5 seconds,
+/// so certificates can be rotated without restarting the server
+pub struct TlsAcceptor {
+    config: Arc<ServerConfig>,
+    resolver: Arc<CertificateResolver>,
+}
+
+#[derive(Debug)]
+struct CertificateResolver {
+    tls_config: TlsConfig,
+    provider: Arc<CryptoProvider>,
+    loaded: RwLock<LoadedCertificate>,
+    /// When the files are checked for modification next
+    next_check: Mutex<Instant>,
+}
+
+#[derive(Debug)]
+struct LoadedCertificate {
+    modified: Option<SystemTime>,
+    certified_key: Arc<CertifiedKey>,
+}
+
+impl TlsAcceptor {
+    pub fn new(tls_config: TlsConfig) -> Result<TlsAcceptor, TlsError> {
+        let provider = Arc::new(ring::default_provider());
+        let resolver = Arc::new(CertificateResolver::new(tls_config, Arc::clone(&provider))?);
+
+        let mut config = ServerConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .map_err(TlsError::Rustls)?
+            .with_no_client_auth()
+            .with_cert_resolver(Arc::clone(&resolver) as Arc<dyn ResolvesServerCert>);
+        config.alpn_protocols = ALPN_PROTOCOLS.iter().map(|p| p.to_vec()).collect();
+
+        Ok(TlsAcceptor {
+            config: Arc::new(config),
+            resolver,
+        })
+    }
+
+    /// Performs the TLS handshake on the accepted connection
+    pub fn accept(&self, mut stream: TcpStream) -> Result<TlsStream, io::Error> {
+        let mut connection = ServerConnection::new(Arc::clone(&self.config))
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        while connection.is_handshaking() {
+            connection.complete_io(&mut stream)?;
+        }
+
+        Ok(StreamOwned::new(connection, stream))
+    }
+
+    /// Re-reads the certificate and the key regardless of their modification time
+    pub fn reload(&self) -> Result<(), TlsError> {
+        self.resolver.reload()
+    }
+}
+
+impl CertificateResolver {
+    fn new(
+        tls_config: TlsConfig,
+        provider: Arc<CryptoProvider>,
+    ) -> Result<CertificateResolver, TlsError> {
+        let loaded = load_certificate(&tls_config, &provider)?;
+
+        Ok(CertificateResolver {
+            tls_config,
+            provider,
+            loaded: RwLock::new(loaded),
+            next_check: Mutex::new(Instant::now() + CHECK_INTERVAL),
+        })
+    }
+
+    fn reload(&self) -> Result<(), TlsError> {
+        let loaded = load_certificate(&self.tls_config, &self.provider)?;
+        *self.loaded.write().unwrap() = loaded;
+
+        Ok(())
+    }
+
+    fn reload_if_modified(&self) {
+        // Handshakes running while another one checks the files keep the loaded certificate
+        let Ok(mut next_check) = self.next_check.try_lock() else {
+            return;
+        };
+        if Instant::now() < *next_check {
+            return;
+        }
+        *next_check = Instant::now() + CHECK_INTERVAL;
+
+        let modified = last_modified(&self.tls_config);
+        if modified == self.loaded.read().unwrap().modified {
+            return;
+        }
+
+        if let Err(e) = self.reload() {
+            warn!(
+                "Unable to reload TLS certificate, keeping the previous one: {}",
+                e
+            );
+        }
+    }
+}
+
+impl ResolvesServerCert for CertificateResolver {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        self.reload_if_modified();
+
+        Some(Arc::clone(&self.loaded.read().unwrap().certified_key))
+    }
+}
+
+fn load_certificate(
+    tls_config: &TlsConfig,
+    provider: &CryptoProvider,
+) -> Result<LoadedCertificate, TlsError> {
+    let modified = last_modified(tls_config);
+
+    let cert_path = &tls_config.cert_path;
+    let cert_chain = CertificateDer::pem_file_iter(cert_path)
+        .map_err(|e| pem_error(cert_path, e))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| pem_error(cert_path, e))?;
+    if cert_chain.is_empty() {
+        return Err(TlsError::NoCertificates(cert_path.clone()));
+    }
+
+    let key_path = &tls_config.key_path;
+    let key = PrivateKeyDer::from_pem_file(key_path).map_err(|e| pem_error(key_path, e))?;
+
+    let certified_key =
+        CertifiedKey::from_der(cert_chain, key, provider).map_err(TlsError::Rustls)?;
+
+    Ok(LoadedCertificate {
+        modified,
+        certified_key: Arc::new(certified_key),
+    })
+}
+
+fn last_modified(tls_config: &TlsConfig) -> Option<SystemTime> {
+    let modified = |path: &Path| path.metadata().and_then(|m| m.modified()).ok();
+
+    modified(&tls_config.cert_path).max(modified(&tls_config.key_path))
+}
+
+fn pem_error(path: &Path, error: rustls::pki_types::pem::Error) -> TlsError {
+    match error {
+        rustls::pki_types::pem::Error::Io(e) => TlsError::Io(path.to_path_buf(), e),
+        e => TlsError::Pem(path.to_path_buf(), e),
+    }
+}
+
+impl Display for TlsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TlsError::Io(path, e) => write!(f, "Unable to read {}: {}", path.display(), e),
+            TlsError::Pem(path, e) => write!(f, "Invalid PEM file {}: {:?}", path.display(), e),
+            TlsError::NoCertificates(path) => {
+                write!(f, "No certificates found in {}", path.display())
+            }
+            TlsError::Rustls(e) => write!(f, "Invalid TLS configuration: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for TlsError {}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        io::{Read, Write},
+        net::TcpListener,
+        thread,
+        time::Duration,
+    };
+
+    use rustls::{ClientConfig, ClientConnection, RootCertStore};
+
+    use super::*;
+    use crate::http::{
+        request::{limits::RequestLimits, Request},
+        response::Response,
+    };
+
+    struct TestCertificate {
+        tls_config: TlsConfig,
+        cert_der: CertificateDer<'static>,
+    }
+
+    fn write_certificate(dir: &Path) -> TestCertificate {
+        let certified_key = rcgen::generate_simple_self_signed(vec![String::from("localhost")])
+            .expect("Certificate must be generated");
+        let tls_config = TlsConfig {
+            cert_path: dir.join("cert.pem"),
+            key_path: dir.join("key.pem"),
+        };
+
+        fs::write(&tls_config.cert_path, certified_key.cert.pem()).unwrap();
+        fs::write(
+            &tls_config.key_path,
+            certified_key.signing_key.serialize_pem(),
+        )
+        .unwrap();
+
+        TestCertificate {
+            tls_config,
+            cert_der: certified_key.cert.der().clone(),
+        }
+    }
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("rust_web_server_{}_{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        dir
+    }
+
+    /// Serves a single request over TLS and returns the raw response with the negotiated ALPN protocol
+    fn exchange(
+        acceptor: Arc<TlsAcceptor>,
+        trusted_cert: CertificateDer<'static>,
+    ) -> (String, Option<Vec<u8>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut stream = acceptor.accept(stream).unwrap();
+            let request = Request::parse(&mut stream, &RequestLimits::default()).unwrap();
+            Response::builder()
+                .code(200)
+                .body(request.body())
+                .build()
+                .write(&mut stream)
+                .unwrap();
+            stream.conn.send_close_notify();
+            stream.flush().unwrap();
+        });
+
+        let mut roots = RootCertStore::empty();
+        roots.add(trusted_cert).unwrap();
+        let mut client_config =
+            ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+                .with_safe_default_protocol_versions()
+                .unwrap()
+                .with_root_certificates(roots)
+                .with_no_client_auth();
+        client_config.alpn_protocols = vec![b"http/1.1".to_vec()];
+
+        let connection =
+            ClientConnection::new(Arc::new(client_config), "localhost".try_into().unwrap())
+                .unwrap();
+        let stream = TcpStream::connect(address).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut stream = StreamOwned::new(connection, stream);
+
+        stream
+            .write_all(b"POST /test HTTP/1.1\r\nContent-Length: 9\r\n\r\ntest_body")
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        server.join().unwrap();
+
+        (response, stream.conn.alpn_protocol().map(|p| p.to_vec()))
+    }
+
+    #[test]
+    fn tls_acceptor_must_serve_requests_and_advertise_alpn() {
+        let dir = test_dir("tls_serve");
+        let certificate = write_certificate(&dir);
+        let acceptor = Arc::new(TlsAcceptor::new(certificate.tls_config).unwrap());
+
+        let (response, alpn_protocol) = exchange(acceptor, certificate.cert_der);
+
+        assert!(
+            response.starts_with("HTTP/1.1 200 OK\r\n") && response.ends_with("test_body"),
+            "Response must be received over TLS"
+        );
+        assert_eq!(
+            Some(b"http/1.1".to_vec()),
+            alpn_protocol,
+            "'http/1.1' must be negotiated through ALPN"
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn tls_acceptor_must_reload_modified_certificate() {
+        let dir = test_dir("tls_reload");
+        let old_certificate = write_certificate(&dir);
+        let acceptor = Arc::new(TlsAcceptor::new(old_certificate.tls_config).unwrap());
+
+        let new_certificate = write_certificate(&dir);
+        let modified = SystemTime::now() + Duration::from_secs(60);
+        fs::File::options()
+            .write(true)
+            .open(&new_certificate.tls_config.cert_path)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+        *acceptor.resolver.next_check.lock().unwrap() = Instant::now();
+
+        let (response, _) = exchange(acceptor, new_certificate.cert_der);
+
+        assert!(
+            response.starts_with("HTTP/1.1 200 OK\r\n"),
+            "New certificate must be used without restarting the acceptor"
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn tls_acceptor_must_reject_missing_certificate() {
+        let dir = test_dir("tls_missing");
+        let tls_config = TlsConfig {
+            cert_path: dir.join("missing_cert.pem"),
+            key_path: dir.join("missing_key.pem"),
+        };
+
+        let acceptor = TlsAcceptor::new(tls_config);
+
+        assert!(
+            acceptor.is_err_and(|e| matches!(e, TlsError::Io(_, _))),
+            "Missing certificate must be reported"
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
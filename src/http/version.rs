@@ -0,0 +1,71 @@
+use std::fmt::Display;
+
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum HttpVersion {
+    Http10,
+    #[default]
+    Http11,
+}
+
+impl HttpVersion {
+    pub fn parse(version: &str) -> Option<HttpVersion> {
+        match version {
+            "HTTP/1.0" => Some(HttpVersion::Http10),
+            "HTTP/1.1" => Some(HttpVersion::Http11),
+            _ => None,
+        }
+    }
+
+    /// Whether the connection is kept open after a response by default
+    ///
+    /// HTTP/1.0 closes the connection unless the client asks otherwise
+    pub fn is_persistent_by_default(&self) -> bool {
+        matches!(self, HttpVersion::Http11)
+    }
+
+    pub fn supports_chunked_encoding(&self) -> bool {
+        matches!(self, HttpVersion::Http11)
+    }
+}
+
+impl Display for HttpVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HttpVersion::Http10 => write!(f, "HTTP/1.0"),
+            HttpVersion::Http11 => write!(f, "HTTP/1.1"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn http_version_parse_must_return_correct_value() {
+        let http_10 = HttpVersion::parse("HTTP/1.0");
+        let http_11 = HttpVersion::parse("HTTP/1.1");
+        let http_2 = HttpVersion::parse("HTTP/2");
+        let malformed = HttpVersion::parse("HTTP/1.1.1");
+
+        assert!(
+            http_10.is_some_and(|v| v == HttpVersion::Http10),
+            "HTTP/1.0 must be parsed correctly"
+        );
+        assert!(
+            http_11.is_some_and(|v| v == HttpVersion::Http11),
+            "HTTP/1.1 must be parsed correctly"
+        );
+        assert!(http_2.is_none(), "HTTP/2 must be parsed into None");
+        assert!(
+            malformed.is_none(),
+            "Malformed version must be parsed into None"
+        );
+    }
+
+    #[test]
+    fn http_version_must_be_displayed_correctly() {
+        assert_eq!("HTTP/1.0", HttpVersion::Http10.to_string());
+        assert_eq!("HTTP/1.1", HttpVersion::Http11.to_string());
+    }
+}
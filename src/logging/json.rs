@@ -0,0 +1,102 @@
+use std::fmt::{Display, Write};
+
+/// Appends the value to the output as a JSON string literal
+pub fn write_string(output: &mut String, value: &str) {
+    output.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => output.push_str("\\\""),
+            '\\' => output.push_str("\\\\"),
+            '\n' => output.push_str("\\n"),
+            '\r' => output.push_str("\\r"),
+            '\t' => output.push_str("\\t"),
+            c if c.is_control() => {
+                let _ = write!(output, "\\u{:04x}", c as u32);
+            }
+            c => output.push(c),
+        }
+    }
+    output.push('"');
+}
+
+/// Builds a single line JSON object field by field
+pub struct JsonObject {
+    output: String,
+}
+
+impl JsonObject {
+    pub fn new() -> JsonObject {
+        JsonObject {
+            output: String::from("{"),
+        }
+    }
+
+    pub fn string(mut self, key: &str, value: &str) -> JsonObject {
+        self.key(key);
+        write_string(&mut self.output, value);
+
+        self
+    }
+
+    /// Adds a string field if there is a value, the field is left out otherwise
+    pub fn optional_string(self, key: &str, value: Option<&str>) -> JsonObject {
+        match value {
+            Some(value) => self.string(key, value),
+            None => self,
+        }
+    }
+
+    /// Adds a number field, the value must display as a JSON number
+    pub fn number(mut self, key: &str, value: impl Display) -> JsonObject {
+        self.key(key);
+        let _ = write!(self.output, "{}", value);
+
+        self
+    }
+
+    /// Adds a nested object field
+    pub fn object(mut self, key: &str, value: JsonObject) -> JsonObject {
+        self.key(key);
+        self.output.push_str(&value.build());
+
+        self
+    }
+
+    pub fn build(mut self) -> String {
+        self.output.push('}');
+
+        self.output
+    }
+
+    fn key(&mut self, key: &str) {
+        if self.output.len() > 1 {
+            self.output.push(',');
+        }
+        write_string(&mut self.output, key);
+        self.output.push(':');
+    }
+}
+
+impl Default for JsonObject {
+    fn default() -> Self {
+        JsonObject::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_object_must_escape_strings() {
+        let json = JsonObject::new()
+            .string("message", "say \"hi\"\n\u{1}")
+            .number("status", 200)
+            .build();
+
+        assert_eq!(
+            r#"{"message":"say \"hi\"\n\u0001","status":200}"#, json,
+            "Strings must be escaped"
+        );
+    }
+}
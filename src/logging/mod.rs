@@ -0,0 +1,182 @@
+use std::io::{self, Write};
+
+use clap::ValueEnum;
+use log::{LevelFilter, Log, Metadata, Record, SetLoggerError};
+
+use crate::{
+    logging::{json::JsonObject, timestamp::Timestamp},
+    trace::{self, CurrentSpan},
+};
+
+pub mod json;
+pub mod timestamp;
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Default)]
+pub enum LogFormat {
+    /// One human readable line per record
+    #[default]
+    Text,
+    /// One JSON object per line
+    Json,
+}
+
+/// Writes log records to stderr
+///
+/// Records are filtered by the global max level, so the level can be changed once installed
+pub struct Logger {
+    level: LevelFilter,
+    format: LogFormat,
+}
+
+impl Logger {
+    pub fn new(level: LevelFilter, format: LogFormat) -> Logger {
+        Logger { level, format }
+    }
+
+    /// Installs the logger as the global `log` backend
+    ///
+    /// Fails if a logger is already installed
+    pub fn init(self) -> Result<(), SetLoggerError> {
+        let level = self.level;
+        log::set_boxed_logger(Box::new(self))?;
+        log::set_max_level(level);
+
+        Ok(())
+    }
+
+    /// Formats the record, along with the IDs of the span it is written in if there is one
+    fn format(&self, timestamp: Timestamp, span: Option<&CurrentSpan>, record: &Record) -> String {
+        match self.format {
+            LogFormat::Text => {
+                let mut line = format!(
+                    "{} {:<5} {}: {}",
+                    timestamp,
+                    record.level(),
+                    record.target(),
+                    record.args()
+                );
+                if let Some(span) = span {
+                    line.push_str(&format!(
+                        " request_id={} trace_id={}",
+                        span.request_id, span.trace_id
+                    ));
+                }
+                line
+            }
+            LogFormat::Json => JsonObject::new()
+                .string("timestamp", &timestamp.to_string())
+                .string("level", record.level().as_str())
+                .string("target", record.target())
+                .string("message", &record.args().to_string())
+                .optional_string("request_id", span.map(|span| span.request_id.as_str()))
+                .optional_string("trace_id", span.map(|span| span.trace_id.as_str()))
+                .optional_string("span_id", span.map(|span| span.span_id.as_str()))
+                .build(),
+        }
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let line = self.format(Timestamp::now(), trace::current_span().as_ref(), record);
+        let _ = writeln!(io::stderr().lock(), "{}", line);
+    }
+
+    fn flush(&self) {
+        let _ = io::stderr().flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use log::Level;
+
+    use super::*;
+
+    fn format(format: LogFormat, span: Option<&CurrentSpan>) -> String {
+        let logger = Logger::new(LevelFilter::Info, format);
+        let timestamp = Timestamp {
+            year: 2000,
+            month: 10,
+            day: 10,
+            hour: 13,
+            minute: 55,
+            second: 36,
+            millisecond: 0,
+        };
+
+        logger.format(
+            timestamp,
+            span,
+            &Record::builder()
+                .level(Level::Warn)
+                .target("rust_web_server::http::server")
+                .args(format_args!(
+                    "Refused a connection: {}",
+                    "Job queue is full"
+                ))
+                .build(),
+        )
+    }
+
+    #[test]
+    fn logger_must_format_text_records() {
+        assert_eq!(
+            "2000-10-10T13:55:36.000Z WARN  rust_web_server::http::server: Refused a connection: Job queue is full",
+            format(LogFormat::Text, None),
+            "Text record must contain the timestamp, level, target and message"
+        );
+    }
+
+    #[test]
+    fn logger_must_format_json_records() {
+        assert_eq!(
+            r#"{"timestamp":"2000-10-10T13:55:36.000Z","level":"WARN","target":"rust_web_server::http::server","message":"Refused a connection: Job queue is full"}"#,
+            format(LogFormat::Json, None),
+            "JSON record must contain the timestamp, level, target and message"
+        );
+    }
+
+    #[test]
+    fn logger_must_add_span_ids_to_records() {
+        let span = CurrentSpan {
+            request_id: String::from("test_id"),
+            trace_id: String::from("0af7651916cd43dd8448eb211c80319c"),
+            span_id: String::from("b7ad6b7169203331"),
+        };
+
+        assert!(
+            format(LogFormat::Text, Some(&span))
+                .ends_with(" request_id=test_id trace_id=0af7651916cd43dd8448eb211c80319c"),
+            "Text record must end with the request and trace IDs"
+        );
+        assert!(
+            format(LogFormat::Json, Some(&span)).ends_with(
+                r#","request_id":"test_id","trace_id":"0af7651916cd43dd8448eb211c80319c","span_id":"b7ad6b7169203331"}"#
+            ),
+            "JSON record must contain the request, trace and span IDs"
+        );
+    }
+
+    #[test]
+    fn logger_must_filter_records_below_level() {
+        let logger = Logger::new(LevelFilter::Info, LogFormat::Text);
+
+        assert!(
+            !logger.enabled(&Metadata::builder().level(Level::Debug).build()),
+            "Debug records must be filtered out at info level"
+        );
+        assert!(
+            logger.enabled(&Metadata::builder().level(Level::Error).build()),
+            "Error records must be logged at info level"
+        );
+    }
+}
@@ -0,0 +1,119 @@
+use std::{
+    fmt::Display,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// A point in time broken down into UTC calendar fields
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Timestamp {
+    pub year: i64,
+    pub month: u32,
+    pub day: u32,
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+    pub millisecond: u32,
+}
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+impl Timestamp {
+    pub fn now() -> Timestamp {
+        Timestamp::from(SystemTime::now())
+    }
+
+    /// Formats the timestamp as in the Common Log Format, e.g. `10/Oct/2000:13:55:36 +0000`
+    pub fn to_common_log_format(&self) -> String {
+        format!(
+            "{:02}/{}/{:04}:{:02}:{:02}:{:02} +0000",
+            self.day,
+            MONTHS[self.month as usize - 1],
+            self.year,
+            self.hour,
+            self.minute,
+            self.second
+        )
+    }
+}
+
+impl From<SystemTime> for Timestamp {
+    fn from(time: SystemTime) -> Self {
+        let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let seconds = since_epoch.as_secs() as i64;
+        let (year, month, day) = civil_from_days(seconds.div_euclid(86_400));
+        let seconds_of_day = seconds.rem_euclid(86_400) as u32;
+
+        Timestamp {
+            year,
+            month,
+            day,
+            hour: seconds_of_day / 3600,
+            minute: seconds_of_day % 3600 / 60,
+            second: seconds_of_day % 60,
+            millisecond: since_epoch.subsec_millis(),
+        }
+    }
+}
+
+/// Formats the timestamp as RFC 3339, e.g. `2000-10-10T13:55:36.000Z`
+impl Display for Timestamp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+            self.year, self.month, self.day, self.hour, self.minute, self.second, self.millisecond
+        )
+    }
+}
+
+/// Converts days since the Unix epoch into a (year, month, day) date
+///
+/// See http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn timestamp_must_be_formatted_as_rfc_3339() {
+        let time = UNIX_EPOCH + Duration::from_millis(971_186_136_250);
+
+        assert_eq!(
+            "2000-10-10T13:55:36.250Z",
+            Timestamp::from(time).to_string(),
+            "Timestamp must be formatted in UTC"
+        );
+        assert_eq!(
+            "2024-02-29T00:00:00.000Z",
+            Timestamp::from(UNIX_EPOCH + Duration::from_secs(1_709_164_800)).to_string(),
+            "Leap day must be formatted"
+        );
+        assert_eq!(
+            "10/Oct/2000:13:55:36 +0000",
+            Timestamp::from(time).to_common_log_format(),
+            "Timestamp must be formatted as in the Common Log Format"
+        );
+    }
+}
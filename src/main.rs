@@ -1,15 +1,23 @@
 use http::server::Server;
+use log::error;
 
 use http::server::Config;
+use logging::Logger;
 
 use crate::http::request::matcher::RequestMatcher;
 use crate::http::response::Response;
 
 pub mod concurrent;
 pub mod http;
+pub mod logging;
+pub mod metrics;
+pub mod trace;
 
 fn main() {
     let config = Config::get_config();
+    Logger::new(config.log_level, config.log_format)
+        .init()
+        .expect("Logger must be installed once");
     let server = Server::builder(config)
         .register_handler(RequestMatcher::post().url("/test").build(), |_| {
             Response::builder()
@@ -18,6 +26,10 @@ fn main() {
                 .body("Test")
                 .build()
         })
-        .build();
+        .try_build()
+        .unwrap_or_else(|e| {
+            error!("{}", e);
+            std::process::exit(1);
+        });
     server.start();
 }
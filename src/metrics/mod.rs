@@ -0,0 +1,452 @@
+use std::{
+    collections::BTreeMap,
+    fmt::{Display, Write},
+    sync::{
+        atomic::{AtomicI64, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+/// Upper bounds of the latency histogram buckets in seconds, the Prometheus client defaults
+pub const DEFAULT_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// A set of named metrics that can be rendered in the Prometheus text exposition format
+///
+/// Metrics are created on first use and shared afterwards, so asking for the same name
+/// and labels twice returns the same metric
+#[derive(Default)]
+pub struct Registry {
+    families: Mutex<BTreeMap<String, Family>>,
+}
+
+pub type GaugeFn = Box<dyn Fn() -> f64 + Send + Sync + 'static>;
+
+/// A value that only goes up
+#[derive(Debug, Default)]
+pub struct Counter {
+    value: AtomicU64,
+}
+
+/// A value that goes up and down
+#[derive(Debug, Default)]
+pub struct Gauge {
+    value: AtomicI64,
+}
+
+/// Counts observed values into buckets by their upper bounds
+#[derive(Debug)]
+pub struct Histogram {
+    bounds: Vec<f64>,
+    /// Observations per bucket, the last one counts values above every bound
+    buckets: Vec<AtomicU64>,
+    /// Sum of the observed values as `f64` bits
+    sum: AtomicU64,
+}
+
+struct Family {
+    help: String,
+    kind: Kind,
+    series: BTreeMap<Labels, Series>,
+}
+
+type Labels = Vec<(String, String)>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Counter,
+    Gauge,
+    Histogram,
+}
+
+enum Series {
+    Counter(Arc<Counter>),
+    Gauge(Arc<Gauge>),
+    GaugeFn(GaugeFn),
+    Histogram(Arc<Histogram>),
+}
+
+impl Registry {
+    pub fn new() -> Registry {
+        Registry::default()
+    }
+
+    /// Returns the counter with the name and labels, it is created on first use
+    ///
+    /// # Panics
+    ///
+    /// If the name is not a valid metric name or is registered as another kind of metric
+    pub fn counter(&self, name: &str, help: &str, labels: &[(&str, &str)]) -> Arc<Counter> {
+        self.with_series(
+            name,
+            help,
+            Kind::Counter,
+            labels,
+            || Series::Counter(Arc::default()),
+            |series| match series {
+                Series::Counter(counter) => Arc::clone(counter),
+                _ => unreachable!("Series kind matches the family kind"),
+            },
+        )
+    }
+
+    /// Returns the gauge with the name and labels, it is created on first use
+    ///
+    /// # Panics
+    ///
+    /// If the name is not a valid metric name or is registered as another kind of metric
+    pub fn gauge(&self, name: &str, help: &str, labels: &[(&str, &str)]) -> Arc<Gauge> {
+        self.with_series(
+            name,
+            help,
+            Kind::Gauge,
+            labels,
+            || Series::Gauge(Arc::default()),
+            |series| match series {
+                Series::Gauge(gauge) => Arc::clone(gauge),
+                _ => panic!("Metric {} is already registered as a computed gauge", name),
+            },
+        )
+    }
+
+    /// Registers a gauge whose value is computed by `value` whenever the registry is rendered,
+    /// it replaces a gauge registered before with the same name and labels
+    pub fn gauge_fn(
+        &self,
+        name: &str,
+        help: &str,
+        labels: &[(&str, &str)],
+        value: impl Fn() -> f64 + Send + Sync + 'static,
+    ) {
+        let mut families = self.families.lock().unwrap();
+        let family = family(&mut families, name, help, Kind::Gauge);
+
+        family
+            .series
+            .insert(to_labels(labels), Series::GaugeFn(Box::new(value)));
+    }
+
+    /// Returns the histogram with the name and labels, it is created on first use
+    /// with the bucket bounds, which are ignored afterwards
+    ///
+    /// # Panics
+    ///
+    /// If the name is not a valid metric name or is registered as another kind of metric
+    pub fn histogram(
+        &self,
+        name: &str,
+        help: &str,
+        labels: &[(&str, &str)],
+        bounds: &[f64],
+    ) -> Arc<Histogram> {
+        self.with_series(
+            name,
+            help,
+            Kind::Histogram,
+            labels,
+            || Series::Histogram(Arc::new(Histogram::new(bounds))),
+            |series| match series {
+                Series::Histogram(histogram) => Arc::clone(histogram),
+                _ => unreachable!("Series kind matches the family kind"),
+            },
+        )
+    }
+
+    /// Renders every metric in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let families = self.families.lock().unwrap();
+        let mut output = String::new();
+
+        for (name, family) in families.iter() {
+            let _ = writeln!(output, "# HELP {} {}", name, escape_help(&family.help));
+            let _ = writeln!(output, "# TYPE {} {}", name, family.kind);
+
+            for (labels, series) in &family.series {
+                match series {
+                    Series::Counter(counter) => {
+                        write_sample(&mut output, name, labels, None, counter.get())
+                    }
+                    Series::Gauge(gauge) => {
+                        write_sample(&mut output, name, labels, None, gauge.get())
+                    }
+                    Series::GaugeFn(value) => {
+                        write_sample(&mut output, name, labels, None, value())
+                    }
+                    Series::Histogram(histogram) => {
+                        histogram.render(&mut output, name, labels);
+                    }
+                }
+            }
+        }
+
+        output
+    }
+
+    /// Looks up the series, creating it and its family if needed, and passes it to `get`
+    fn with_series<T>(
+        &self,
+        name: &str,
+        help: &str,
+        kind: Kind,
+        labels: &[(&str, &str)],
+        create: impl FnOnce() -> Series,
+        get: impl FnOnce(&Series) -> T,
+    ) -> T {
+        let mut families = self.families.lock().unwrap();
+        let family = family(&mut families, name, help, kind);
+
+        get(family
+            .series
+            .entry(to_labels(labels))
+            .or_insert_with(create))
+    }
+}
+
+impl Counter {
+    pub fn inc(&self) {
+        self.inc_by(1);
+    }
+
+    pub fn inc_by(&self, value: u64) {
+        self.value.fetch_add(value, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.value.load(Ordering::Relaxed)
+    }
+}
+
+impl Gauge {
+    pub fn inc(&self) {
+        self.value.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn dec(&self) {
+        self.value.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn set(&self, value: i64) {
+        self.value.store(value, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> i64 {
+        self.value.load(Ordering::Relaxed)
+    }
+}
+
+impl Histogram {
+    fn new(bounds: &[f64]) -> Histogram {
+        let mut bounds = bounds.to_vec();
+        bounds.sort_by(f64::total_cmp);
+        bounds.dedup();
+
+        Histogram {
+            buckets: (0..=bounds.len()).map(|_| AtomicU64::new(0)).collect(),
+            bounds,
+            sum: AtomicU64::new(0f64.to_bits()),
+        }
+    }
+
+    pub fn observe(&self, value: f64) {
+        let bucket = self.bounds.partition_point(|bound| *bound < value);
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+
+        let _ = self
+            .sum
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |sum| {
+                Some((f64::from_bits(sum) + value).to_bits())
+            });
+    }
+
+    pub fn count(&self) -> u64 {
+        self.buckets
+            .iter()
+            .map(|bucket| bucket.load(Ordering::Relaxed))
+            .sum()
+    }
+
+    pub fn sum(&self) -> f64 {
+        f64::from_bits(self.sum.load(Ordering::Relaxed))
+    }
+
+    /// Writes the cumulative buckets followed by the sum and the count
+    fn render(&self, output: &mut String, name: &str, labels: &Labels) {
+        let bucket_name = format!("{}_bucket", name);
+        let mut cumulative = 0;
+
+        for (index, bucket) in self.buckets.iter().enumerate() {
+            cumulative += bucket.load(Ordering::Relaxed);
+            let bound = match self.bounds.get(index) {
+                Some(bound) => bound.to_string(),
+                None => String::from("+Inf"),
+            };
+            write_sample(output, &bucket_name, labels, Some(&bound), cumulative);
+        }
+
+        write_sample(output, &format!("{}_sum", name), labels, None, self.sum());
+        write_sample(output, &format!("{}_count", name), labels, None, cumulative);
+    }
+}
+
+impl Display for Kind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Kind::Counter => write!(f, "counter"),
+            Kind::Gauge => write!(f, "gauge"),
+            Kind::Histogram => write!(f, "histogram"),
+        }
+    }
+}
+
+fn family<'a>(
+    families: &'a mut BTreeMap<String, Family>,
+    name: &str,
+    help: &str,
+    kind: Kind,
+) -> &'a mut Family {
+    if !is_valid_name(name) {
+        panic!("{} is not a valid metric name", name);
+    }
+
+    let family = families
+        .entry(String::from(name))
+        .or_insert_with(|| Family {
+            help: String::from(help),
+            kind,
+            series: BTreeMap::new(),
+        });
+    if family.kind != kind {
+        panic!("Metric {} is already registered as a {}", name, family.kind);
+    }
+
+    family
+}
+
+fn to_labels(labels: &[(&str, &str)]) -> Labels {
+    let mut labels: Labels = labels
+        .iter()
+        .map(|(name, value)| (String::from(*name), String::from(*value)))
+        .collect();
+    labels.sort();
+
+    labels
+}
+
+fn is_valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == ':')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == ':')
+}
+
+/// Writes a single sample line, `le` is the upper bound of a histogram bucket
+fn write_sample(
+    output: &mut String,
+    name: &str,
+    labels: &Labels,
+    le: Option<&str>,
+    value: impl Display,
+) {
+    output.push_str(name);
+
+    let le = le.map(|le| (String::from("le"), String::from(le)));
+    let mut labels = labels.iter().chain(le.as_ref()).peekable();
+    if labels.peek().is_some() {
+        output.push('{');
+        for (index, (label, value)) in labels.enumerate() {
+            if index > 0 {
+                output.push(',');
+            }
+            let _ = write!(output, "{}=\"{}\"", label, escape_label_value(value));
+        }
+        output.push('}');
+    }
+
+    let _ = writeln!(output, " {}", value);
+}
+
+fn escape_help(help: &str) -> String {
+    help.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_must_write_text_exposition_format() {
+        let registry = Registry::new();
+        registry
+            .counter("requests_total", "Handled requests", &[("route", "/a\"b")])
+            .inc_by(3);
+        registry
+            .gauge("in_flight", "Requests in flight", &[])
+            .set(2);
+        registry.gauge_fn("queue_depth", "Queued jobs", &[], || 5.0);
+        let histogram = registry.histogram("latency_seconds", "Latency", &[], &[0.1, 1.0]);
+        histogram.observe(0.05);
+        histogram.observe(0.5);
+        histogram.observe(2.0);
+
+        assert_eq!(
+            "# HELP in_flight Requests in flight\n\
+             # TYPE in_flight gauge\n\
+             in_flight 2\n\
+             # HELP latency_seconds Latency\n\
+             # TYPE latency_seconds histogram\n\
+             latency_seconds_bucket{le=\"0.1\"} 1\n\
+             latency_seconds_bucket{le=\"1\"} 2\n\
+             latency_seconds_bucket{le=\"+Inf\"} 3\n\
+             latency_seconds_sum 2.55\n\
+             latency_seconds_count 3\n\
+             # HELP queue_depth Queued jobs\n\
+             # TYPE queue_depth gauge\n\
+             queue_depth 5\n\
+             # HELP requests_total Handled requests\n\
+             # TYPE requests_total counter\n\
+             requests_total{route=\"/a\\\"b\"} 3\n",
+            registry.render(),
+            "Metrics must be rendered sorted by name with escaped label values"
+        );
+    }
+
+    #[test]
+    fn counter_must_be_shared_by_name_and_labels() {
+        let registry = Registry::new();
+
+        registry
+            .counter("hits_total", "Hits", &[("a", "1"), ("b", "2")])
+            .inc();
+        registry
+            .counter("hits_total", "Hits", &[("b", "2"), ("a", "1")])
+            .inc();
+        registry.counter("hits_total", "Hits", &[("a", "2")]).inc();
+
+        assert_eq!(
+            2,
+            registry
+                .counter("hits_total", "Hits", &[("a", "1"), ("b", "2")])
+                .get(),
+            "Same name and labels must return the same counter"
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "already registered as a counter")]
+    fn gauge_must_not_reuse_counter_name() {
+        let registry = Registry::new();
+
+        registry.counter("hits_total", "Hits", &[]);
+        registry.gauge("hits_total", "Hits", &[]);
+    }
+}
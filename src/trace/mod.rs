@@ -0,0 +1,348 @@
+use std::{
+    cell::RefCell,
+    collections::hash_map::RandomState,
+    future::Future,
+    hash::{BuildHasher, Hasher},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use log::debug;
+
+/// The W3C trace context of a span, see https://www.w3.org/TR/trace-context/
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpanContext {
+    pub trace_id: u128,
+    pub span_id: u64,
+    /// Trace flags, the lowest bit marks the trace as sampled
+    pub flags: u8,
+    /// Vendor specific `tracestate` entries, passed along unchanged
+    pub trace_state: Option<String>,
+}
+
+/// A unit of work within a trace, e.g. handling a single request
+///
+/// While a span is entered, log records written on the thread carry its request ID and trace context
+#[derive(Clone)]
+pub struct Span {
+    data: Arc<SpanData>,
+}
+
+/// Restores the span that was entered before once dropped
+pub struct Entered {
+    previous: Option<Arc<SpanData>>,
+}
+
+/// Enters the span every time the wrapped future is polled
+pub struct Instrumented<F> {
+    future: Pin<Box<F>>,
+    span: Span,
+}
+
+struct SpanData {
+    name: String,
+    request_id: String,
+    context: SpanContext,
+    parent_span_id: Option<u64>,
+    started: Instant,
+}
+
+/// Trace fields of the span entered on the current thread, as attached to log records
+#[derive(Debug, Clone, PartialEq)]
+pub struct CurrentSpan {
+    pub request_id: String,
+    pub trace_id: String,
+    pub span_id: String,
+}
+
+thread_local! {
+    static CURRENT_SPAN: RefCell<Option<Arc<SpanData>>> = const { RefCell::new(None) };
+}
+
+const TRACEPARENT_VERSION: u8 = 0;
+
+impl SpanContext {
+    /// Parses the `traceparent` and `tracestate` headers, `None` if `traceparent` is invalid
+    ///
+    /// Versions above 00 are parsed as 00 as long as their prefix is valid
+    pub fn parse(traceparent: &str, trace_state: Option<&str>) -> Option<SpanContext> {
+        let traceparent = traceparent.trim();
+        let mut fields = traceparent.split('-');
+        let version = parse_hex_field(fields.next()?, 2)?;
+        let trace_id = parse_hex_field(fields.next()?, 32)?;
+        let span_id = parse_hex_field(fields.next()?, 16)?;
+        let flags = parse_hex_field(fields.next()?, 2)?;
+
+        let is_valid_version = match version {
+            0 => fields.next().is_none(),
+            0xff => false,
+            _ => true,
+        };
+        if !is_valid_version || trace_id == 0 || span_id == 0 {
+            return None;
+        }
+
+        Some(SpanContext {
+            trace_id,
+            span_id: span_id as u64,
+            flags: flags as u8,
+            trace_state: trace_state
+                .map(str::trim)
+                .filter(|state| !state.is_empty())
+                .map(String::from),
+        })
+    }
+
+    /// Formats the context as a `traceparent` header value to propagate it to other services
+    pub fn traceparent(&self) -> String {
+        format!(
+            "{:02x}-{:032x}-{:016x}-{:02x}",
+            TRACEPARENT_VERSION, self.trace_id, self.span_id, self.flags
+        )
+    }
+
+    pub fn is_sampled(&self) -> bool {
+        self.flags & 1 == 1
+    }
+}
+
+impl Span {
+    /// Starts a span, as a child of the remote parent if there is one or as the root of a new trace
+    pub fn new(
+        name: impl Into<String>,
+        request_id: impl Into<String>,
+        parent: Option<&SpanContext>,
+    ) -> Span {
+        let context = match parent {
+            Some(parent) => SpanContext {
+                span_id: random_id(),
+                ..parent.clone()
+            },
+            None => SpanContext {
+                trace_id: u128::from(random_id()) << 64 | u128::from(random_id()),
+                span_id: random_id(),
+                flags: 0,
+                trace_state: None,
+            },
+        };
+
+        Span {
+            data: Arc::new(SpanData {
+                name: name.into(),
+                request_id: request_id.into(),
+                context,
+                parent_span_id: parent.map(|parent| parent.span_id),
+                started: Instant::now(),
+            }),
+        }
+    }
+
+    pub fn context(&self) -> &SpanContext {
+        &self.data.context
+    }
+
+    pub fn request_id(&self) -> &str {
+        &self.data.request_id
+    }
+
+    /// Makes the span the current one of the thread until the guard is dropped
+    pub fn enter(&self) -> Entered {
+        let previous = CURRENT_SPAN.with(|current| current.replace(Some(Arc::clone(&self.data))));
+
+        Entered { previous }
+    }
+
+    pub fn instrument<F: Future>(&self, future: F) -> Instrumented<F> {
+        Instrumented {
+            future: Box::pin(future),
+            span: self.clone(),
+        }
+    }
+
+    /// Ends the span and logs its duration
+    pub fn finish(self) {
+        let _entered = self.enter();
+        let data = &self.data;
+
+        debug!(
+            "Finished span '{}' in {:?} (parent span id: {})",
+            data.name,
+            data.started.elapsed(),
+            data.parent_span_id
+                .map_or_else(|| String::from("none"), |id| format!("{:016x}", id))
+        );
+    }
+}
+
+impl Drop for Entered {
+    fn drop(&mut self) {
+        CURRENT_SPAN.with(|current| *current.borrow_mut() = self.previous.take());
+    }
+}
+
+impl<F: Future> Future for Instrumented<F> {
+    type Output = F::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let _entered = self.span.enter();
+
+        self.future.as_mut().poll(cx)
+    }
+}
+
+/// Trace fields of the span entered on the current thread, if there is one
+pub fn current_span() -> Option<CurrentSpan> {
+    CURRENT_SPAN.with(|current| {
+        current.borrow().as_ref().map(|data| CurrentSpan {
+            request_id: data.request_id.clone(),
+            trace_id: format!("{:032x}", data.context.trace_id),
+            span_id: format!("{:016x}", data.context.span_id),
+        })
+    })
+}
+
+/// Generates a random request ID of 32 hex digits
+pub fn generate_request_id() -> String {
+    format!("{:016x}{:016x}", random_id(), random_id())
+}
+
+/// Generates a random non-zero ID
+///
+/// Not suitable for cryptography, the IDs only need to be unique
+fn random_id() -> u64 {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    loop {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+        hasher.write_u128(
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_nanos(),
+        );
+
+        let id = hasher.finish();
+        if id != 0 {
+            return id;
+        }
+    }
+}
+
+/// Parses a field of exactly `length` lowercase hex digits
+fn parse_hex_field(field: &str, length: usize) -> Option<u128> {
+    if field.len() != length
+        || !field
+            .chars()
+            .all(|c| c.is_ascii_digit() || ('a'..='f').contains(&c))
+    {
+        return None;
+    }
+
+    u128::from_str_radix(field, 16).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TRACEPARENT: &str = "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01";
+
+    #[test]
+    fn span_context_must_parse_traceparent() {
+        let context = SpanContext::parse(TRACEPARENT, Some("congo=t61rcWkgMzE")).unwrap();
+
+        assert_eq!(
+            0x0af7651916cd43dd8448eb211c80319c, context.trace_id,
+            "Trace ID must be parsed"
+        );
+        assert_eq!(
+            0xb7ad6b7169203331, context.span_id,
+            "Span ID must be parsed"
+        );
+        assert!(context.is_sampled(), "Sampled flag must be parsed");
+        assert_eq!(
+            Some(String::from("congo=t61rcWkgMzE")),
+            context.trace_state,
+            "Trace state must be kept"
+        );
+        assert_eq!(
+            TRACEPARENT,
+            context.traceparent(),
+            "Context must be formatted back"
+        );
+    }
+
+    #[test]
+    fn span_context_must_reject_invalid_traceparent() {
+        for traceparent in [
+            "",
+            "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331",
+            "00-0AF7651916CD43DD8448EB211C80319C-b7ad6b7169203331-01",
+            "00-00000000000000000000000000000000-b7ad6b7169203331-01",
+            "00-0af7651916cd43dd8448eb211c80319c-0000000000000000-01",
+            "ff-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01",
+            "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01-extra",
+        ] {
+            assert_eq!(
+                None,
+                SpanContext::parse(traceparent, None),
+                "Traceparent '{}' must be rejected",
+                traceparent
+            );
+        }
+        assert!(
+            SpanContext::parse(
+                "01-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01-extra",
+                None
+            )
+            .is_some(),
+            "Future versions must be parsed by their known prefix"
+        );
+    }
+
+    #[test]
+    fn span_must_continue_remote_trace() {
+        let parent = SpanContext::parse(TRACEPARENT, None).unwrap();
+
+        let child = Span::new("GET /test", "test_id", Some(&parent));
+        let root = Span::new("GET /test", "test_id", None);
+
+        assert_eq!(
+            parent.trace_id,
+            child.context().trace_id,
+            "Child span must continue the trace"
+        );
+        assert_ne!(
+            parent.span_id,
+            child.context().span_id,
+            "Child span must have its own ID"
+        );
+        assert_ne!(
+            parent.trace_id,
+            root.context().trace_id,
+            "Root span must start a new trace"
+        );
+    }
+
+    #[test]
+    fn enter_must_set_current_span_until_dropped() {
+        let span = Span::new("GET /test", "test_id", None);
+
+        let entered = span.enter();
+        let current = current_span();
+        drop(entered);
+
+        assert_eq!(
+            Some(String::from("test_id")),
+            current.map(|current| current.request_id),
+            "Entered span must be the current one"
+        );
+        assert_eq!(None, current_span(), "Span must be left once dropped");
+    }
+}